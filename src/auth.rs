@@ -1,192 +1,712 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
-use directories::ProjectDirs;
-use from_as::*;
-use graph_rs_sdk::oauth::OAuth;
+use anyhow::{anyhow, bail, Result};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use chrono::{DateTime, Utc};
+use graph_rs_sdk::oauth::{GrantRequest, GrantType, OAuth, OAuthCredential};
 use serde::{Deserialize, Serialize};
-use std::io::Result;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::time::Duration;
 use warp::Filter;
 
+use crate::graph::GraphClient;
+use crate::helper::{get_config_dir, get_profile, get_profile_cache_dir, get_profile_config_dir};
+use crate::journal::Journal;
+use crate::profiles;
+use crate::settings::{self, Settings};
+use crate::tokens;
+
 // Client Credentials Grant
 // If you have already given admin consent to a user you can skip
 // browser authorization step and go strait to requesting an access token.
 // The client_id and client_secret must be changed before running this example.
 static CLIENT_ID: &str = "CLIENTID";
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AccessCode {
-    code: String,
-}
+/// The tenant id Azure AD gives personal Microsoft accounts.
+const CONSUMER_TENANT_ID: &str = "9188040d-6c67-4c5b-b112-36a304b66dad";
 
-pub fn get_oauth_client() -> OAuth {
-    let mut oauth = OAuth::new();
-    oauth
-        .client_id(CLIENT_ID)
-        .add_scope("tasks.readwrite")
-        .add_scope("user.read")
-        .add_scope("offline_access")
-        .redirect_uri("http://localhost:8000/redirect")
-        .authorize_url("https://login.microsoftonline.com/common/oauth2/v2.0/authorize")
-        .access_token_url("https://login.microsoftonline.com/common/oauth2/v2.0/token")
-        .refresh_token_url("https://login.microsoftonline.com/common/oauth2/v2.0/token");
-    oauth
+/// Where the browser is sent back to after signing in; the port is the
+/// first free one from `--port` on.
+pub fn redirect_uri(port: u16) -> String {
+    format!("http://localhost:{}/redirect", port)
 }
 
-pub async fn req_access_token(code: String) {
+/// A client for the authority of the profile's cloud, see `tdi config`.
+pub fn get_oauth_client(redirect_uri: &str) -> Result<OAuth> {
+    let endpoints = settings::endpoints()?;
+    // Outside the global cloud, Graph scopes have to name the Graph endpoint
+    // they are for.
+    let graph_scope = |scope: &str| {
+        if endpoints.graph == "https://graph.microsoft.com" {
+            scope.to_lowercase()
+        } else {
+            format!("{}/{}", endpoints.graph, scope)
+        }
+    };
     let mut oauth = OAuth::new();
     oauth
         .client_id(CLIENT_ID)
-        .add_scope("tasks.readwrite")
-        .add_scope("user.read")
+        .add_scope(graph_scope("Tasks.ReadWrite"))
+        .add_scope(graph_scope("User.Read"))
+        .add_scope(graph_scope("MailboxSettings.Read"))
         .add_scope("offline_access")
-        .redirect_uri("http://localhost:8000/redirect")
-        .authorize_url("https://login.microsoftonline.com/common/oauth2/v2.0/authorize")
-        .access_token_url("https://login.microsoftonline.com/common/oauth2/v2.0/token")
-        .refresh_token_url("https://login.microsoftonline.com/common/oauth2/v2.0/token");
+        // For an id token naming the account, see `tdi auth status`.
+        .add_scope("openid")
+        .add_scope("profile")
+        .redirect_uri(redirect_uri)
+        .authorize_url(&endpoints.oauth_url("authorize"))
+        .access_token_url(&endpoints.oauth_url("token"))
+        .refresh_token_url(&endpoints.oauth_url("token"));
+    Ok(oauth)
+}
 
+/// Exchanges an authorization code for tokens and stores them, encrypted if
+/// `encrypt` is set or the previous token file was. `oauth` is the client the
+/// sign in was started with, holding its redirect URI and PKCE verifier.
+pub async fn req_access_token(mut oauth: OAuth, code: String, encrypt: bool) -> Result<()> {
     // previously set. This is done here as an example.
     oauth.access_code(code.as_str());
 
     let mut request = oauth.build_async().authorization_code_grant();
     let access_token = match request.access_token().send().await {
         Ok(res) => res,
-        Err(err) => {
-            println!("tdi login error: {:?}", err);
-            std::process::exit(1);
-        }
+        Err(err) => bail!("unable to get an access token: {:?}", err),
     };
 
     oauth.access_token(access_token);
-
-    match std::fs::create_dir_all(get_config_dir()) {
-        Ok(()) => {
-            println!("tdi: creating directory path for access token config.")
-        }
-        Err(_) => {
-            println!("tdi: error created directory path for access token config.");
-            std::process::exit(1);
-        }
+    // Only good for this sign in, no need to keep them.
+    for credential in [
+        OAuthCredential::AccessCode,
+        OAuthCredential::CodeVerifier,
+        OAuthCredential::CodeChallenge,
+        OAuthCredential::CodeChallengeMethod,
+        OAuthCredential::State,
+    ] {
+        oauth.remove(credential);
     }
-    let config_path = get_config_dir() + "/tdi.json";
-    oauth.as_file(config_path).unwrap();
+
+    tokens::save(&oauth, encrypt || tokens::is_encrypted())
+        .map_err(|err| anyhow!("unable to store the access token: {}", err))?;
 
     println!(
-        "tdi: logged in, and stored token for future use at {}.",
+        "tdi: logged in to profile {}, and stored token for future use at {}.",
+        get_profile(),
         get_config_dir()
     );
+    Ok(())
 }
 
-pub fn req_refresh_token(mut oauth: OAuth) {
-    let mut request = oauth.build().authorization_code_grant();
-    let access_token = match request.refresh_token().send() {
+/// How long before it expires an access token is refreshed, so that it does
+/// not expire in the middle of a command.
+const REFRESH_MARGIN_SECS: i64 = 5 * 60;
+
+/// Gets a new access token with the refresh token and stores it.
+async fn req_refresh_token(mut oauth: OAuth) -> Result<OAuth> {
+    let mut request = oauth.build_async().authorization_code_grant();
+    let access_token = match request.refresh_token().send().await {
         Ok(res) => res,
-        Err(err) => {
-            println!("tdi error fetching refresh token: {:?}", err);
-            std::process::exit(1);
-        }
+        Err(err) => bail!(
+            "unable to refresh the access token, run `tdi login`: {:?}",
+            err
+        ),
     };
 
     oauth.access_token(access_token);
+    tokens::save(&oauth, tokens::is_encrypted())?;
+    Ok(oauth)
+}
 
-    match std::fs::create_dir_all(get_config_dir()) {
-        Ok(()) => {
-            let config_path = get_config_dir() + "/tdi.json";
-            oauth.as_file(config_path).unwrap();
+fn bearer_token(oauth: &OAuth) -> Option<String> {
+    oauth
+        .get_access_token()
+        .map(|token| token.bearer_token().to_string())
+}
+
+/// Whether the stored access token should be replaced: it expires soon, or
+/// it is the one Graph just rejected.
+fn needs_refresh(oauth: &OAuth, rejected: Option<&str>) -> bool {
+    match oauth.get_access_token() {
+        None => true,
+        Some(token) => {
+            let expiring = token.timestamp().is_none_or(|expires_at| {
+                expires_at - Utc::now() < chrono::Duration::seconds(REFRESH_MARGIN_SECS)
+            });
+            expiring || rejected == Some(token.bearer_token())
         }
-        Err(_) => {
-            println!("tdi: error created directory path for access token config.");
-            std::process::exit(1);
+    }
+}
+
+async fn current_access_token(rejected: Option<&str>) -> Result<String> {
+    let not_logged_in = || {
+        anyhow!(
+            "not logged in to profile {}, run `tdi login`",
+            get_profile()
+        )
+    };
+    let oauth = tokens::load()?.ok_or_else(not_logged_in)?;
+    if !needs_refresh(&oauth, rejected) {
+        return bearer_token(&oauth).ok_or_else(not_logged_in);
+    }
+
+    // Only one process refreshes at a time; the others wait and then use the
+    // token it stored, as a refresh token may only be good for one use.
+    let _lock = tokens::lock()?;
+    let oauth = tokens::load()?.ok_or_else(not_logged_in)?;
+    if !needs_refresh(&oauth, rejected) {
+        return bearer_token(&oauth).ok_or_else(not_logged_in);
+    }
+    eprintln!("tdi: refreshing the access token.");
+    let oauth = req_refresh_token(oauth).await?;
+    bearer_token(&oauth).ok_or_else(not_logged_in)
+}
+
+/// The access token to call Graph with, refreshed first when it is about to
+/// expire.
+pub async fn access_token() -> Result<String> {
+    current_access_token(None).await
+}
+
+/// A new access token after Graph answered `401` to `rejected`, unless
+/// another process has replaced it already.
+pub async fn refresh_access_token(rejected: &str) -> Result<String> {
+    current_access_token(Some(rejected)).await
+}
+
+/// The query string Azure AD redirects the browser back with.
+#[derive(Debug, Default, Deserialize)]
+pub struct Callback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+impl Callback {
+    /// The authorization code, if the sign in succeeded and the callback
+    /// belongs to it.
+    pub fn code(self, expected_state: &str) -> std::result::Result<String, String> {
+        if let Some(error) = self.error {
+            return Err(self.error_description.unwrap_or(error));
+        }
+        if self.state.as_deref() != Some(expected_state) {
+            return Err("the sign in response does not match this login, try again".to_string());
         }
+        self.code
+            .ok_or_else(|| "no authorization code in the sign in response".to_string())
     }
 }
 
-pub fn read_access_token() -> String {
-    let oauth = OAuth::from_file(get_config_dir() + "/tdi.json").unwrap();
-    if oauth.get_access_token().unwrap().is_expired() {
-        println!("tdi: previous auth token has expired, refreshing.");
-        req_refresh_token(oauth);
+fn page(title: &str, message: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>tdi - {}</title></head>\
+         <body style=\"font-family: sans-serif; margin: 3em\"><h1>{}</h1><p>{}</p></body></html>",
+        title,
+        title,
+        message.replace('&', "&amp;").replace('<', "&lt;")
+    )
+}
+
+fn random_state() -> String {
+    let mut state = [0u8; 16];
+    OsRng.fill_bytes(&mut state);
+    base64::encode_config(state, base64::URL_SAFE_NO_PAD)
+}
+
+/// A client to sign in with, with a fresh PKCE challenge, and the authorize
+/// URL to send the user to.
+fn authorize(port: u16, state: &str) -> Result<(OAuth, String)> {
+    let mut oauth = get_oauth_client(&redirect_uri(port))?;
+    oauth.state(state);
+    if let Err(err) = oauth.generate_sha256_challenge_and_verifier() {
+        bail!("unable to create the PKCE challenge: {:?}", err);
     }
-    match std::fs::read_to_string(get_config_dir() + "/tdi.json") {
-        Ok(data) => {
-            let res: serde_json::Value =
-                serde_json::from_str(&data).expect("tdi: unable to parse configuration.");
-            let token: Option<&str> = res
-                .get("access_token")
-                .and_then(|value| value.get("access_token"))
-                .and_then(|value| value.as_str());
+    match oauth.encode_uri(GrantType::AuthorizationCode, GrantRequest::Authorization) {
+        Ok(url) => Ok((oauth, url)),
+        Err(err) => bail!("unable to create the sign in URL: {:?}", err),
+    }
+}
 
-            let token = token.unwrap();
-            token.to_string()
+/// Reads what was pasted after a manual sign in: the URL the browser was
+/// redirected to, its query string, or just the code.
+fn parse_pasted(input: &str) -> Callback {
+    let input = input.trim();
+    let query = match input.split_once('?') {
+        Some((_, query)) => query,
+        None if input.contains('=') => input,
+        None => {
+            return Callback {
+                code: Some(input.to_string()),
+                ..Callback::default()
+            }
         }
-        Err(_) => {
-            println!("tdi: unable to read access token configuration, perhaps run `tdi login`.");
-            std::process::exit(0);
+    };
+    let mut callback = Callback::default();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let value = Some(value.into_owned());
+        match key.as_ref() {
+            "code" => callback.code = value,
+            "state" => callback.state = value,
+            "error" => callback.error = value,
+            "error_description" => callback.error_description = value,
+            _ => {}
         }
     }
+    callback
 }
 
-fn get_config_dir() -> String {
-    let proj_dirs = ProjectDirs::from("com", "microsofthackathons", "tdi");
-    let config_dir = proj_dirs.unwrap().config_dir().to_path_buf();
-    config_dir.into_os_string().into_string().unwrap()
+/// Signs in without a local browser: the authorize URL is opened anywhere,
+/// and the address the browser ends up on is pasted back. That page fails to
+/// load as nothing listens on this machine, which is expected.
+async fn manual_login(encrypt: bool, port: u16) -> Result<()> {
+    let state = random_state();
+    let (oauth, url) = authorize(port, &state)?;
+    println!(
+        "tdi: sign in at the URL below, on any device. The browser then fails \
+         to load a localhost page; copy its full address and paste it here.\n\n{}\n",
+        url
+    );
+    eprint!("tdi: redirected URL or code: ");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    let callback = parse_pasted(&input);
+    // A bare code carries no state to check.
+    let code = if callback.state.is_none() && callback.error.is_none() {
+        callback
+            .code
+            .filter(|code| !code.is_empty())
+            .ok_or_else(|| anyhow!("no authorization code was pasted"))?
+    } else {
+        callback
+            .code(&state)
+            .map_err(|err| anyhow!("sign in failed: {}", err))?
+    };
+    req_access_token(oauth, code, encrypt).await
 }
 
+/// Signs in with the authorization code flow: opens the browser on the
+/// authorize URL and waits for it to be redirected back to a local server.
 #[tokio::main]
-pub async fn login() -> Result<()> {
-    println!("tdi: authenticating, a browser window will open.");
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
-    // If this is not the first time you are using the client credentials grant
-    // then you only have to run request_access_token() and you can comment out
-    // what is below.
-    let query = warp::query::<AccessCode>()
-        .map(Some)
-        .or_else(|_| async { Ok::<(Option<AccessCode>,), std::convert::Infallible>((None,)) });
-
-    let routes = warp::get().and(warp::path("redirect")).and(query).map(
-        move |cc: Option<AccessCode>| match cc {
-            Some(access_code) => {
-                // Print out for debugging purposes.
-                // println!("CODE: {:#?}", access_code.code);
-                tx.send(access_code.code).unwrap();
-                Ok(warp::reply::with_status("Hello from <b>tdi</b> - the access code was received and stored locally, you may safely close this browser window!", http::status::StatusCode::CREATED))
+pub async fn login(encrypt: &bool, manual: &bool, port: &u16, timeout: &u64) -> Result<()> {
+    let encrypt = *encrypt;
+    if *manual {
+        return manual_login(encrypt, *port).await;
+    }
+    let state = random_state();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let expected_state = state.clone();
+    let routes = warp::get()
+        .and(warp::path("redirect"))
+        .and(
+            warp::query::<Callback>()
+                .or(warp::any().map(Callback::default))
+                .unify(),
+        )
+        .map(move |callback: Callback| {
+            // Requests of other pages or logins are turned away without
+            // ending this one.
+            if callback.state.as_deref() != Some(expected_state.as_str()) {
+                return warp::reply::with_status(
+                    warp::reply::html(page(
+                        "Sign in failed",
+                        "the sign in response does not match this login",
+                    )),
+                    http::StatusCode::BAD_REQUEST,
+                );
             }
-            None =>  {
-                tx.send("error getting access code".to_string()).unwrap();
-                //Response::builder().body(String::from("There was an issue getting the access code."))
-                Ok(warp::reply::with_status("Hello from <b>tdi</b> - error encountered requesting the access code.", http::status::StatusCode::NOT_FOUND))
-            },
-        },
-    );
+            let result = callback.code(&expected_state);
+            let reply = match &result {
+                Ok(_) => warp::reply::with_status(
+                    warp::reply::html(page(
+                        "Signed in",
+                        "tdi received the sign in, you may close this window.",
+                    )),
+                    http::StatusCode::OK,
+                ),
+                Err(err) => warp::reply::with_status(
+                    warp::reply::html(page("Sign in failed", err)),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+            };
+            let _ = tx.send(result);
+            reply
+        });
 
-    // Get the oauth client and request a browser sign in
-    let mut oauth = get_oauth_client();
-    let mut request = oauth.build().code_flow();
-    request.browser_authorization().open().unwrap();
+    let bind = |port: u16| {
+        let (shutdown, stopped) = tokio::sync::oneshot::channel::<()>();
+        warp::serve(routes.clone())
+            .try_bind_with_graceful_shutdown(([127, 0, 0, 1], port), async move {
+                let _ = stopped.await;
+            })
+            .map(|(addr, server)| (addr, server, shutdown))
+    };
+    let (addr, server, shutdown) = match bind(*port) {
+        Ok(bound) => bound,
+        Err(_) => {
+            eprintln!("tdi: port {} is in use, using a free port instead.", port);
+            bind(0)?
+        }
+    };
+    let server = tokio::spawn(server);
 
-    let server = warp::serve(routes)
-        .bind_with_graceful_shutdown(([127, 0, 0, 1], 8000), async move {
-            let code = rx.recv().await.unwrap();
-            req_access_token(code).await;
-            std::process::exit(0);
-        })
-        .1;
+    let (mut oauth, url) = authorize(addr.port(), &state)?;
 
-    server.await;
+    println!("tdi: authenticating, a browser window will open.");
+    let opened = oauth
+        .build()
+        .authorization_code_grant()
+        .browser_authorization()
+        .open();
+    if opened.is_err() {
+        println!("tdi: unable to open a browser, sign in at:\n\n{}\n", url);
+    }
 
-    Ok(())
+    let result = tokio::time::timeout(Duration::from_secs(*timeout), rx.recv()).await;
+    let _ = shutdown.send(());
+    let _ = server.await;
+    match result {
+        Err(_) => bail!(
+            "timed out after {} seconds waiting for the sign in",
+            timeout
+        ),
+        Ok(None) => bail!("the sign in server stopped unexpectedly"),
+        Ok(Some(Err(err))) => bail!("sign in failed: {}", err),
+        Ok(Some(Ok(code))) => req_access_token(oauth, code, encrypt).await,
+    }
+}
+
+/// Files of a profile's config directory that belong to the signed in
+/// account. Settings such as the cloud are kept.
+const ACCOUNT_FILES: [&str; 5] = [
+    "tdi.json",
+    "tdi.json.tmp",
+    "tdi.json.lock",
+    "lists_cache.json",
+    "tasks_cache.json",
+];
+
+/// How many offline changes of `profile` have not been pushed yet.
+fn pending_changes(profile: &str) -> Result<usize> {
+    let path = Path::new(&get_profile_cache_dir(profile)).join("journal.json");
+    match std::fs::File::open(path) {
+        Ok(file) => {
+            let journal: Journal = serde_json::from_reader(std::io::BufReader::new(file))?;
+            Ok(journal.operations.len())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Deletes the tokens, id caches, offline data and history of a profile,
+/// returning whether it was logged in.
+fn clear_profile(profile: &str) -> Result<bool> {
+    let config_dir = Path::new(&get_profile_config_dir(profile)).to_path_buf();
+    let logged_in = config_dir.join("tdi.json").exists();
+    for file in ACCOUNT_FILES {
+        match std::fs::remove_file(config_dir.join(file)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    let cache_dir = get_profile_cache_dir(profile);
+    if Path::new(&cache_dir).exists() {
+        std::fs::remove_dir_all(cache_dir)?;
+    }
+    Ok(logged_in)
 }
 
-pub fn logout() -> Result<()> {
-    println!("tdi: logging out of Microsoft SSO");
-    let mut oauth: OAuth = OAuth::new();
+/// Signs the browser out of its Microsoft session too, so the next
+/// `tdi login` asks which account to use.
+fn end_browser_session() -> Result<()> {
+    let endpoints = settings::endpoints()?;
+    let mut oauth = OAuth::new();
     oauth
         .client_id(CLIENT_ID)
-        .logout_url("https://login.microsoftonline.com/common/oauth2/v2.0/logout")
-        .post_logout_redirect_uri("http://localhost:8000/redirect");
-    oauth.v1_logout().unwrap();
+        .logout_url(&endpoints.oauth_url("logout"))
+        .post_logout_redirect_uri(&endpoints.oauth_url("nativeclient"));
+    if oauth.v2_logout().is_err() {
+        eprintln!("tdi: unable to open a browser to end the Microsoft session.");
+    }
+    Ok(())
+}
+
+/// Logs the active profile, or every profile, out: deletes its tokens and
+/// local data. Offline changes that were not synced yet are only thrown away
+/// with `force`. `revoke` first invalidates the account's refresh tokens.
+pub fn logout(all: &bool, revoke: &bool, force: &bool) -> Result<()> {
+    let profiles = if *all {
+        profiles::profile_names()?
+    } else {
+        vec![get_profile().to_string()]
+    };
+
+    if !*force {
+        for profile in &profiles {
+            let pending = pending_changes(profile)?;
+            if pending > 0 {
+                bail!(
+                    "profile {} has {} offline change(s) that were not synced, run `tdi sync` or pass --force to discard them",
+                    profile,
+                    pending
+                );
+            }
+        }
+    }
 
-    std::fs::remove_file(get_config_dir() + "/tdi.json")?;
-    // TODO: remove the OAuth authorization and delete the locally stored cred
+    if *revoke && tokens::load()?.is_some() {
+        // Signs the account out of every session, not just this one.
+        GraphClient::new()?.post("/me/revokeSignInSessions", &json!({}))?;
+        println!(
+            "tdi: revoked the sign in sessions of profile {}.",
+            get_profile()
+        );
+    }
+
+    let mut any_logged_in = false;
+    for profile in &profiles {
+        if clear_profile(profile)? {
+            any_logged_in = true;
+            println!("tdi: logged out of profile {}.", profile);
+        } else if !*all {
+            println!("tdi: profile {} is not logged in.", profile);
+        }
+    }
+    if any_logged_in {
+        end_browser_session()?;
+    }
     Ok(())
 }
+
+/// What `tdi auth status` reports. Never holds the tokens themselves.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthStatus {
+    profile: String,
+    cloud: String,
+    logged_in: bool,
+    account: Option<String>,
+    tenant: Option<String>,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+    expired: bool,
+    refresh_token: bool,
+    encrypted: bool,
+}
+
+impl AuthStatus {
+    fn read() -> Result<AuthStatus> {
+        let mut status = AuthStatus {
+            profile: get_profile().to_string(),
+            cloud: Settings::load()?.cloud.to_string(),
+            ..AuthStatus::default()
+        };
+        let Some(token) = tokens::load()?.and_then(|oauth| oauth.get_access_token()) else {
+            return Ok(status);
+        };
+
+        // Prefer the id token, which is meant to be read by the client; the
+        // access token is opaque for personal accounts.
+        let claims = token
+            .id_token()
+            .and_then(|id_token| jwt_claims(&id_token))
+            .or_else(|| jwt_claims(token.bearer_token()))
+            .unwrap_or_default();
+        status.account = ["preferred_username", "upn", "email", "name"]
+            .iter()
+            .find_map(|claim| claims.get(*claim).and_then(Value::as_str))
+            .map(str::to_string);
+        status.tenant = claims.get("tid").and_then(Value::as_str).map(|tid| {
+            if tid == CONSUMER_TENANT_ID {
+                format!("{} (personal Microsoft account)", tid)
+            } else {
+                tid.to_string()
+            }
+        });
+
+        status.scopes = token
+            .scopes()
+            .map(|scopes| scopes.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+        status.expires_at = token.timestamp();
+        status.expired = status.expires_at.is_none_or(|at| at <= Utc::now());
+        status.refresh_token = token.refresh_token().is_some();
+        // An expired access token is fine as long as it can be refreshed.
+        status.logged_in = !status.expired || status.refresh_token;
+        status.encrypted = tokens::is_encrypted();
+        Ok(status)
+    }
+}
+
+/// The claims in the payload of a JWT, without verifying its signature.
+fn jwt_claims(jwt: &str) -> Option<Map<String, Value>> {
+    let payload = jwt.split('.').nth(1)?;
+    let bytes =
+        base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Shows who the active profile is logged in as and the state of its tokens.
+/// Exits with `exitcode::NOUSER` when it is not logged in.
+pub fn status(refresh: &bool, output_format: &str) -> Result<()> {
+    let mut status = AuthStatus::read()?;
+    if *refresh && status.refresh_token {
+        if let Some(token) = tokens::load()?.as_ref().and_then(bearer_token) {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(refresh_access_token(&token))?;
+        }
+        status = AuthStatus::read()?;
+    }
+
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&status)?),
+        _ if !status.logged_in => {
+            println!(
+                "tdi: not logged in to profile {}, run `tdi login`.",
+                status.profile
+            );
+        }
+        _ => {
+            let unknown = || "unknown".to_string();
+            println!("Profile:       {}", status.profile);
+            println!("Cloud:         {}", status.cloud);
+            println!("Account:       {}", status.account.unwrap_or_else(unknown));
+            println!("Tenant:        {}", status.tenant.unwrap_or_else(unknown));
+            println!("Scopes:        {}", status.scopes.join(" "));
+            match status.expires_at {
+                Some(at) if !status.expired => println!(
+                    "Access token:  valid until {} ({} minutes left)",
+                    at.format("%Y-%m-%d %H:%M UTC"),
+                    (at - Utc::now()).num_minutes()
+                ),
+                Some(at) => println!(
+                    "Access token:  expired at {}",
+                    at.format("%Y-%m-%d %H:%M UTC")
+                ),
+                None => println!("Access token:  expired"),
+            }
+            println!(
+                "Refresh token: {}",
+                if status.refresh_token {
+                    "present"
+                } else {
+                    "missing"
+                }
+            );
+            println!(
+                "Token file:    {}",
+                if status.encrypted {
+                    "encrypted"
+                } else {
+                    "not encrypted"
+                }
+            );
+        }
+    }
+
+    if *refresh && !status.refresh_token && status.logged_in {
+        eprintln!("tdi: no refresh token stored, run `tdi login` to get a new token.");
+    }
+    if !status.logged_in {
+        std::process::exit(exitcode::NOUSER);
+    }
+    Ok(())
+}
+
+/// Rewrites the token file encrypted with a passphrase, or in plain JSON.
+pub fn encrypt_tokens(encrypt: bool) -> Result<()> {
+    let Some(oauth) = tokens::load()? else {
+        bail!(
+            "not logged in to profile {}, run `tdi login`",
+            get_profile()
+        );
+    };
+    tokens::save(&oauth, encrypt)?;
+    println!(
+        "tdi: the token file of profile {} is {}.",
+        get_profile(),
+        if encrypt {
+            "now encrypted"
+        } else {
+            "no longer encrypted"
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_rs_sdk::oauth::AccessToken;
+
+    #[test]
+    fn reads_jwt_claims_without_padding() {
+        let payload = base64::encode_config(
+            r#"{"preferred_username":"ada@contoso.com","tid":"t1"}"#,
+            base64::URL_SAFE_NO_PAD,
+        );
+        let claims = jwt_claims(&format!("header.{}.signature", payload)).unwrap();
+        assert_eq!(claims["preferred_username"], "ada@contoso.com");
+        assert_eq!(claims["tid"], "t1");
+        assert!(jwt_claims("opaque-token").is_none());
+    }
+
+    #[test]
+    fn refreshes_before_expiry_or_after_rejection() {
+        let oauth = |expires_in| {
+            let mut oauth = OAuth::new();
+            oauth.access_token(AccessToken::new("Bearer", expires_in, "", "token"));
+            oauth
+        };
+        assert!(!needs_refresh(&oauth(3600), None));
+        assert!(needs_refresh(&oauth(60), None));
+        assert!(needs_refresh(&oauth(3600), Some("token")));
+        assert!(!needs_refresh(&oauth(3600), Some("older-token")));
+    }
+
+    #[test]
+    fn callbacks_must_carry_the_login_state() {
+        let callback = |state: &str| Callback {
+            code: Some("code".to_string()),
+            state: Some(state.to_string()),
+            ..Callback::default()
+        };
+        assert_eq!(
+            callback("expected").code("expected"),
+            Ok("code".to_string())
+        );
+        assert!(callback("forged").code("expected").is_err());
+    }
+
+    #[test]
+    fn pasted_redirects_and_bare_codes_are_read() {
+        let pasted = parse_pasted(
+            "http://localhost:8000/redirect?code=M.C1%2Fx&state=abc&session_state=s\n",
+        );
+        assert_eq!(pasted.code.as_deref(), Some("M.C1/x"));
+        assert_eq!(pasted.state.as_deref(), Some("abc"));
+
+        let pasted = parse_pasted("error=access_denied&error_description=No&state=abc");
+        assert_eq!(pasted.code("abc"), Err("No".to_string()));
+
+        let pasted = parse_pasted("  M.C1_code \n");
+        assert_eq!(pasted.code.as_deref(), Some("M.C1_code"));
+        assert!(pasted.state.is_none());
+
+        let denied = Callback {
+            error: Some("access_denied".to_string()),
+            error_description: Some("The user declined.".to_string()),
+            ..Callback::default()
+        };
+        assert_eq!(
+            denied.code("expected"),
+            Err("The user declined.".to_string())
+        );
+    }
+}
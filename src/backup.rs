@@ -0,0 +1,651 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! `tdi export` and `tdi import`: a versioned snapshot of lists and tasks,
+//! with their checklist items, linked resources and the account's
+//! categories, and recreating them from it.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::batch::BatchRequest;
+use crate::dates;
+use crate::graph::{Collection, GraphClient};
+use crate::ics;
+use crate::lists::{fetch_todo_lists, get_real_list_id, TodoList};
+use crate::markdown;
+use crate::spreadsheet;
+use crate::tasksv2::{create_task, ChecklistItem, LinkedResource, TodoTask};
+use crate::taskwarrior;
+use crate::todotxt;
+
+/// Bumped when a change to the format would make older versions of tdi
+/// misread a backup.
+pub const BACKUP_VERSION: u32 = 1;
+
+const DEFAULT_LIST: &str = "defaultList";
+
+/// The formats `tdi export` writes and `tdi import` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Everything, see [`Backup`].
+    Json,
+    /// One line per task, see [`todotxt`].
+    Todotxt,
+    /// A VTODO per task, see [`ics`].
+    Ics,
+    /// Taskwarrior's JSON, see [`taskwarrior`].
+    Taskwarrior,
+    /// A row per task, import only, see [`spreadsheet`].
+    Csv,
+    /// A heading per list and a checkbox per task, see [`markdown`].
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Format> {
+        match name {
+            "json" => Ok(Format::Json),
+            "todotxt" => Ok(Format::Todotxt),
+            "ics" => Ok(Format::Ics),
+            "taskwarrior" => Ok(Format::Taskwarrior),
+            "csv" => Ok(Format::Csv),
+            "markdown" => Ok(Format::Markdown),
+            _ => bail!(
+                "unknown format {}, use \"json\", \"todotxt\", \"ics\", \"taskwarrior\", \"csv\" or \"markdown\"",
+                name
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// The account's categories, with their colors.
+    #[serde(default)]
+    pub categories: Vec<Category>,
+    pub lists: Vec<BackupList>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Category {
+    pub display_name: String,
+    #[serde(default)]
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupList {
+    pub display_name: String,
+    /// "defaultList" for the "Tasks" list, which is merged into the
+    /// account's own rather than created.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub wellknown_list_name: String,
+    pub tasks: Vec<BackupTask>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupTask {
+    #[serde(flatten)]
+    pub task: TodoTask,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checklist_items: Vec<ChecklistItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linked_resources: Vec<LinkedResource>,
+}
+
+impl Backup {
+    pub fn new(lists: Vec<BackupList>) -> Backup {
+        Backup {
+            version: BACKUP_VERSION,
+            exported_at: Utc::now(),
+            categories: Vec::new(),
+            lists,
+        }
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.lists.iter().map(|list| list.tasks.len()).sum()
+    }
+}
+
+impl BackupTask {
+    pub fn new(task: TodoTask) -> BackupTask {
+        BackupTask {
+            task,
+            checklist_items: Vec::new(),
+            linked_resources: Vec::new(),
+        }
+    }
+
+    /// Takes the checklist items and linked resources Graph expanded into
+    /// the task.
+    fn from_expanded(mut task: TodoTask) -> BackupTask {
+        let mut take = |field: &str| task.extra.remove(field).unwrap_or(Value::Null);
+        let checklist_items = serde_json::from_value(take("checklistItems")).unwrap_or_default();
+        let linked_resources = serde_json::from_value(take("linkedResources")).unwrap_or_default();
+        BackupTask {
+            task,
+            checklist_items,
+            linked_resources,
+        }
+    }
+}
+
+/// The tasks of each list, with their checklist items and linked resources,
+/// one `$batch` round trip per 20 lists.
+pub fn fetch_backup(client: &GraphClient, lists: Vec<TodoList>) -> Result<Backup> {
+    let requests = lists
+        .iter()
+        .enumerate()
+        .map(|(i, list)| {
+            BatchRequest::get(
+                &i.to_string(),
+                &format!(
+                    "/me/todo/lists/{}/tasks?$expand=checklistItems,linkedResources",
+                    list.id
+                ),
+            )
+        })
+        .collect();
+
+    let mut backup = Backup::new(Vec::new());
+    for (list, response) in lists.into_iter().zip(client.batch(requests)?) {
+        let page: Collection<TodoTask> = response
+            .json()
+            .map_err(|err| anyhow!("unable to export {}: {}", list.display_name, err))?;
+        let mut tasks = page.value;
+        if let Some(next_link) = page.next_link {
+            tasks.extend(client.get_all::<TodoTask>(&next_link)?);
+        }
+        eprintln!("tdi: read {} tasks of {}", tasks.len(), list.display_name);
+        backup.lists.push(BackupList {
+            display_name: list.display_name,
+            wellknown_list_name: list.wellknown_list_name,
+            tasks: tasks.into_iter().map(BackupTask::from_expanded).collect(),
+        });
+    }
+
+    match client.get_all("/me/outlook/masterCategories") {
+        Ok(categories) => backup.categories = categories,
+        Err(err) => eprintln!("tdi: unable to read the categories: {}", err),
+    }
+    Ok(backup)
+}
+
+/// Reads the lists to export from Graph: every list, or the one given by
+/// the id shown by `tdi lists`.
+pub fn fetch_lists(
+    client: &GraphClient,
+    all: &bool,
+    list_id: &Option<u16>,
+) -> Result<Vec<TodoList>> {
+    let lists = fetch_todo_lists(client)?;
+    match (all, list_id) {
+        (true, _) => Ok(lists),
+        (false, Some(id)) => {
+            let real_id = get_real_list_id(id)?;
+            Ok(lists
+                .into_iter()
+                .filter(|list| list.id == real_id)
+                .collect())
+        }
+        (false, None) => bail!("pass --all or --list-id"),
+    }
+}
+
+pub fn export(all: &bool, list_id: &Option<u16>, format: &str) -> Result<()> {
+    let format: Format = format.parse()?;
+    if format == Format::Csv {
+        bail!("csv is for importing, export to json, todotxt, ics, taskwarrior or markdown");
+    }
+    let client = GraphClient::new()?;
+    let lists = fetch_lists(&client, all, list_id)?;
+    let backup = fetch_backup(&client, lists)?;
+    let output = match format {
+        Format::Json => serde_json::to_string_pretty(&backup)? + "\n",
+        Format::Todotxt => todotxt::format(&backup, &dates::time_zone()),
+        Format::Ics => ics::format(&backup),
+        Format::Taskwarrior => taskwarrior::format(&backup)?,
+        Format::Markdown => markdown::format(&backup),
+        Format::Csv => unreachable!(),
+    };
+    print!("{}", output);
+    eprintln!(
+        "tdi: exported {} tasks in {} lists",
+        backup.task_count(),
+        backup.lists.len()
+    );
+    Ok(())
+}
+
+/// Reads a file, or stdin for "-".
+pub fn read_input(file: &str) -> Result<String> {
+    if file == "-" {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        return Ok(input);
+    }
+    std::fs::read_to_string(file).map_err(|err| anyhow!("unable to read {}: {}", file, err))
+}
+
+pub fn parse_backup(input: &str) -> Result<Backup> {
+    let value: Value = serde_json::from_str(input)?;
+    match value["version"].as_u64() {
+        None => bail!("not a tdi export, there is no version"),
+        Some(version) if version > BACKUP_VERSION as u64 => bail!(
+            "the export is version {}, this tdi reads up to version {}, update tdi",
+            version,
+            BACKUP_VERSION
+        ),
+        Some(_) => Ok(serde_json::from_value(value)?),
+    }
+}
+
+/// What importing one list does.
+#[derive(Debug)]
+struct ListPlan<'a, 'b> {
+    list: &'a BackupList,
+    /// The list to add to, `None` to create one.
+    target: Option<&'b TodoList>,
+    tasks: Vec<&'a BackupTask>,
+    /// How many tasks are left out as the list has them already.
+    skipped: usize,
+}
+
+/// Matches the lists of the backup to those of the account: the default
+/// list to the default list and others by name, unless `new_lists`. Tasks
+/// with a title the list already has are left out, so an import can be
+/// repeated.
+fn plan<'a, 'b>(
+    backup: &'a Backup,
+    existing: &'b [TodoList],
+    titles: &HashMap<String, HashSet<String>>,
+    new_lists: bool,
+) -> Vec<ListPlan<'a, 'b>> {
+    backup
+        .lists
+        .iter()
+        .map(|list| {
+            let target = existing.iter().find(|existing| {
+                !new_lists
+                    && if list.wellknown_list_name == DEFAULT_LIST {
+                        existing.wellknown_list_name == DEFAULT_LIST
+                    } else {
+                        existing.display_name == list.display_name
+                    }
+            });
+            let known = target.and_then(|target| titles.get(&target.id));
+            let tasks: Vec<&BackupTask> = list
+                .tasks
+                .iter()
+                .filter(|task| known.is_none_or(|known| !known.contains(&task.task.title)))
+                .collect();
+            ListPlan {
+                list,
+                target,
+                skipped: list.tasks.len() - tasks.len(),
+                tasks,
+            }
+        })
+        .collect()
+}
+
+/// The titles of the tasks in each list, by list id.
+fn fetch_titles(
+    client: &GraphClient,
+    lists: &[&TodoList],
+) -> Result<HashMap<String, HashSet<String>>> {
+    let requests = lists
+        .iter()
+        .enumerate()
+        .map(|(i, list)| {
+            BatchRequest::get(
+                &i.to_string(),
+                &format!("/me/todo/lists/{}/tasks?$select=title", list.id),
+            )
+        })
+        .collect();
+    let mut titles = HashMap::new();
+    for (list, response) in lists.iter().zip(client.batch(requests)?) {
+        let page: Collection<Value> = response.json()?;
+        let mut tasks = page.value;
+        if let Some(next_link) = page.next_link {
+            tasks.extend(client.get_all::<Value>(&next_link)?);
+        }
+        let list_titles = tasks
+            .iter()
+            .filter_map(|task| task["title"].as_str().map(str::to_string))
+            .collect();
+        titles.insert(list.id.clone(), list_titles);
+    }
+    Ok(titles)
+}
+
+/// Names the categories of the backup the account does not have. Tasks keep
+/// them either way; adding them to Outlook, which takes write access to the
+/// mailbox settings, is left to the user.
+fn check_categories(client: &GraphClient, categories: &[Category]) {
+    if categories.is_empty() {
+        return;
+    }
+    let existing: Vec<Category> = match client.get_all("/me/outlook/masterCategories") {
+        Ok(existing) => existing,
+        Err(err) => {
+            eprintln!("tdi: unable to read the categories: {}", err);
+            return;
+        }
+    };
+    let missing: Vec<&str> = categories
+        .iter()
+        .filter(|category| {
+            !existing
+                .iter()
+                .any(|existing| existing.display_name == category.display_name)
+        })
+        .map(|category| category.display_name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        eprintln!(
+            "tdi: categories {} are not in this account, add them in Outlook to give them colors",
+            missing.join(", ")
+        );
+    }
+}
+
+/// Creates a task of the backup with its checklist items and linked
+/// resources.
+fn import_task(client: &GraphClient, list_id: &str, task: &BackupTask) -> Result<()> {
+    let id = create_task(client, list_id, &task.task, &task.checklist_items)?;
+    let url = format!("/me/todo/lists/{}/tasks/{}/linkedResources", list_id, id);
+    let requests = task
+        .linked_resources
+        .iter()
+        .enumerate()
+        .map(|(i, resource)| {
+            let resource = LinkedResource {
+                id: String::new(),
+                ..resource.clone()
+            };
+            BatchRequest::post(&i.to_string(), &url, json!(resource))
+        })
+        .collect();
+    // The task is there now, so missing links do not make it failed.
+    let responses = match client.batch(requests) {
+        Ok(responses) => responses,
+        Err(err) => {
+            eprintln!(
+                "tdi: unable to copy the linked resources of {}: {}",
+                task.task.title, err
+            );
+            return Ok(());
+        }
+    };
+    for response in responses {
+        if !response.is_success() {
+            eprintln!(
+                "tdi: unable to copy a linked resource of {}: {}",
+                task.task.title,
+                response.error()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// What [`restore`] did.
+#[derive(Debug, Default)]
+pub struct Restored<'a> {
+    pub created: usize,
+    /// Tasks left out as their lists have them already.
+    pub skipped: usize,
+    pub failed: Vec<&'a BackupTask>,
+}
+
+/// Recreates the lists and tasks of `backup`, printing progress to stderr.
+pub fn restore(backup: &Backup, dry_run: bool, new_lists: bool) -> Result<Restored<'_>> {
+    let client = GraphClient::new()?;
+    let existing = fetch_todo_lists(&client)?;
+    let targets: Vec<&TodoList> = plan(backup, &existing, &HashMap::new(), new_lists)
+        .iter()
+        .filter_map(|list| list.target)
+        .collect();
+    let titles = fetch_titles(&client, &targets)?;
+    let plans = plan(backup, &existing, &titles, new_lists);
+
+    check_categories(&client, &backup.categories);
+
+    let total: usize = plans.iter().map(|plan| plan.tasks.len()).sum();
+    let mut restored = Restored {
+        skipped: plans.iter().map(|plan| plan.skipped).sum(),
+        ..Restored::default()
+    };
+    let mut done = 0;
+    for plan in &plans {
+        let name = &plan.list.display_name;
+        if dry_run {
+            match plan.target {
+                Some(_) => println!("tdi: would add {} tasks to {}", plan.tasks.len(), name),
+                None => println!(
+                    "tdi: would create list {} with {} tasks",
+                    name,
+                    plan.tasks.len()
+                ),
+            }
+            if plan.skipped > 0 {
+                println!("tdi: {} tasks of {} are there already", plan.skipped, name);
+            }
+            continue;
+        }
+
+        let list_id = match plan.target {
+            Some(target) => target.id.clone(),
+            None => {
+                let created = client.post("/me/todo/lists", &json!({ "displayName": name }))?;
+                created["id"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("no id for the created list {}", name))?
+                    .to_string()
+            }
+        };
+        for task in &plan.tasks {
+            done += 1;
+            match import_task(&client, &list_id, task) {
+                Ok(()) => {
+                    restored.created += 1;
+                    eprintln!("tdi: [{}/{}] {}: {}", done, total, name, task.task.title);
+                }
+                Err(err) => {
+                    restored.failed.push(task);
+                    eprintln!(
+                        "tdi: [{}/{}] {}: {}: {}",
+                        done, total, name, task.task.title, err
+                    );
+                }
+            }
+        }
+        if plan.skipped > 0 {
+            eprintln!("tdi: skipped {} tasks {} has already", plan.skipped, name);
+        }
+    }
+
+    if !dry_run {
+        println!(
+            "tdi: imported {} tasks into {} lists",
+            restored.created,
+            plans.len()
+        );
+    }
+    Ok(restored)
+}
+
+/// The list to import formats without lists into: the one named `name`,
+/// else the default list.
+fn single_list(name: &Option<String>) -> BackupList {
+    BackupList {
+        display_name: name.clone().unwrap_or_else(|| "Tasks".to_string()),
+        wellknown_list_name: match name {
+            Some(_) => String::new(),
+            None => DEFAULT_LIST.to_string(),
+        },
+        tasks: Vec::new(),
+    }
+}
+
+pub fn import(
+    file: &str,
+    format: &str,
+    list: &Option<String>,
+    dry_run: &bool,
+    new_lists: &bool,
+) -> Result<()> {
+    let format: Format = format.parse()?;
+    let input = read_input(file)?;
+    let backup = match format {
+        Format::Json => {
+            if list.is_some() {
+                bail!("--list is for formats without lists, a json export has its own");
+            }
+            parse_backup(&input)?
+        }
+        Format::Todotxt => todotxt::parse(&input, single_list(list), &dates::time_zone())?,
+        Format::Ics => ics::parse(&input, single_list(list), &dates::time_zone())?,
+        Format::Taskwarrior => taskwarrior::parse(
+            &input,
+            single_list(list),
+            list.is_none(),
+            &dates::time_zone(),
+        )?,
+        Format::Markdown => markdown::parse(&input, single_list(list), list.is_none())?,
+        Format::Csv => return import_rows(&input, list, *dry_run, *new_lists),
+    };
+    let restored = restore(&backup, *dry_run, *new_lists)?;
+    if !restored.failed.is_empty() {
+        bail!(
+            "{} of {} tasks could not be imported",
+            restored.failed.len(),
+            restored.created + restored.failed.len()
+        );
+    }
+    Ok(())
+}
+
+/// Imports the rows of a CSV file, all of them checked before anything is
+/// sent, and reports which rows were created and which failed.
+fn import_rows(input: &str, list: &Option<String>, dry_run: bool, new_lists: bool) -> Result<()> {
+    let rows = spreadsheet::parse(input, single_list(list), &dates::time_zone())?;
+    let restored = restore(&rows.backup, dry_run, new_lists)?;
+    if dry_run {
+        return Ok(());
+    }
+    let mut failed: Vec<u64> = restored
+        .failed
+        .iter()
+        .filter_map(|task| rows.row(task))
+        .collect();
+    failed.sort_unstable();
+    println!(
+        "tdi: {} rows created, {} already there, {} failed",
+        restored.created,
+        restored.skipped,
+        failed.len()
+    );
+    if !failed.is_empty() {
+        let failed: Vec<String> = failed.iter().map(u64::to_string).collect();
+        bail!("rows {} could not be imported", failed.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(id: &str, name: &str, wellknown: &str) -> TodoList {
+        TodoList {
+            display_name: name.to_string(),
+            is_owner: true,
+            is_shared: false,
+            id: id.to_string(),
+            wellknown_list_name: wellknown.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_expanded_tasks_and_rejects_newer_versions() {
+        let backup = parse_backup(
+            &json!({
+                "version": 1,
+                "exportedAt": "2024-03-10T00:00:00Z",
+                "lists": [{
+                    "displayName": "Tasks",
+                    "wellknownListName": "defaultList",
+                    "tasks": [{
+                        "id": "t1", "title": "Milk", "status": "notStarted", "importance": "normal",
+                        "checklistItems": [{ "id": "c1", "displayName": "Oat", "isChecked": true }],
+                        "linkedResources": [{ "webUrl": "https://example.com" }],
+                    }],
+                }],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let task = &backup.lists[0].tasks[0];
+        assert_eq!(task.checklist_items[0].display_name, "Oat");
+        assert_eq!(
+            task.linked_resources[0].web_url.as_deref(),
+            Some("https://example.com")
+        );
+        assert!(!task.task.extra.contains_key("checklistItems"));
+
+        let newer = json!({ "version": 2, "exportedAt": "2024-03-10T00:00:00Z", "lists": [] });
+        assert!(parse_backup(&newer.to_string()).is_err());
+        assert!(parse_backup("{}").is_err());
+    }
+
+    #[test]
+    fn merges_lists_by_name_and_skips_tasks_already_there() {
+        let task = |title: &str| BackupTask::new(TodoTask::local("", title));
+        let backup = Backup::new(vec![
+            BackupList {
+                display_name: "Aufgaben".to_string(),
+                wellknown_list_name: DEFAULT_LIST.to_string(),
+                tasks: vec![task("Milk"), task("Eggs")],
+            },
+            BackupList {
+                display_name: "Work".to_string(),
+                wellknown_list_name: String::new(),
+                tasks: vec![task("Report")],
+            },
+        ]);
+        let existing = vec![
+            list("l1", "Tasks", DEFAULT_LIST),
+            list("l2", "Home", "none"),
+        ];
+        let titles = HashMap::from([("l1".to_string(), HashSet::from(["Milk".to_string()]))]);
+
+        let plans = plan(&backup, &existing, &titles, false);
+        assert_eq!(plans[0].target.map(|list| list.id.as_str()), Some("l1"));
+        assert_eq!(plans[0].tasks.len(), 1);
+        assert_eq!(plans[0].skipped, 1);
+        assert!(plans[1].target.is_none());
+
+        let plans = plan(&backup, &existing, &titles, true);
+        assert!(plans
+            .iter()
+            .all(|plan| plan.target.is_none() && plan.skipped == 0));
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! JSON batching for Microsoft Graph.
+//!
+//! Graph accepts up to 20 requests per `POST /$batch`. Requests are grouped
+//! so that a request and everything it `dependsOn` travel in the same batch,
+//! and items answered with `429`/`503` are resent (together with their
+//! dependents) after the `Retry-After` delay.
+//!
+//! https://learn.microsoft.com/en-us/graph/json-batching
+
+use anyhow::{anyhow, bail, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::graph::{error_message, GraphClient};
+
+pub const MAX_BATCH_SIZE: usize = 20;
+const MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_AFTER: u64 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+    pub id: String,
+    pub method: String,
+    /// Relative to the Graph version root, e.g. `/me/todo/lists`.
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+}
+
+impl BatchRequest {
+    pub fn new(id: &str, method: &str, url: &str, body: Option<Value>) -> BatchRequest {
+        let mut headers = HashMap::new();
+        if body.is_some() {
+            headers.insert("Content-Type".to_string(), "application/json".to_string());
+        }
+        BatchRequest {
+            id: id.to_string(),
+            method: method.to_string(),
+            url: url.to_string(),
+            body,
+            headers,
+            depends_on: Vec::new(),
+        }
+    }
+
+    pub fn get(id: &str, url: &str) -> BatchRequest {
+        BatchRequest::new(id, "GET", url, None)
+    }
+
+    pub fn post(id: &str, url: &str, body: Value) -> BatchRequest {
+        BatchRequest::new(id, "POST", url, Some(body))
+    }
+
+    pub fn patch(id: &str, url: &str, body: Value) -> BatchRequest {
+        BatchRequest::new(id, "PATCH", url, Some(body))
+    }
+
+    pub fn delete(id: &str, url: &str) -> BatchRequest {
+        BatchRequest::new(id, "DELETE", url, None)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> BatchRequest {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Only run this request once the request with the given id succeeded.
+    pub fn depends_on(mut self, id: &str) -> BatchRequest {
+        self.depends_on.push(id.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchResponse {
+    pub id: String,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Value,
+}
+
+impl BatchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    fn is_throttled(&self) -> bool {
+        self.status == 429 || self.status == 503
+    }
+
+    fn retry_after(&self) -> u64 {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+    }
+
+    pub fn error(&self) -> String {
+        error_message(self.status, &self.body)
+    }
+
+    /// Deserializes the body of a successful response.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        if !self.is_success() {
+            bail!(self.error());
+        }
+        Ok(serde_json::from_value(self.body.clone())?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchReply {
+    responses: Vec<BatchResponse>,
+}
+
+/// Splits requests into batches of at most [`MAX_BATCH_SIZE`], keeping every
+/// request in the same batch as the requests it depends on.
+pub fn chunk(requests: Vec<BatchRequest>) -> Result<Vec<Vec<BatchRequest>>> {
+    let mut index = HashMap::new();
+    for (i, request) in requests.iter().enumerate() {
+        if index.insert(request.id.clone(), i).is_some() {
+            bail!("duplicate batch request id {}", request.id);
+        }
+    }
+
+    // Union the requests connected through dependsOn into groups.
+    let mut parent: Vec<usize> = (0..requests.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, request) in requests.iter().enumerate() {
+        for dependency in &request.depends_on {
+            let j = *index.get(dependency).ok_or_else(|| {
+                anyhow!(
+                    "batch request {} depends on unknown {}",
+                    request.id,
+                    dependency
+                )
+            })?;
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+
+    let mut groups: Vec<Vec<BatchRequest>> = Vec::new();
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    for (i, request) in requests.into_iter().enumerate() {
+        let r = root(&mut parent, i);
+        let g = *group_of_root.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[g].push(request);
+    }
+
+    let mut batches: Vec<Vec<BatchRequest>> = Vec::new();
+    for group in groups {
+        if group.len() > MAX_BATCH_SIZE {
+            bail!(
+                "{} dependent batch requests exceed the limit of {}",
+                group.len(),
+                MAX_BATCH_SIZE
+            );
+        }
+        match batches
+            .iter_mut()
+            .find(|batch| batch.len() + group.len() <= MAX_BATCH_SIZE)
+        {
+            Some(batch) => batch.extend(group),
+            None => batches.push(group),
+        }
+    }
+    Ok(batches)
+}
+
+/// Picks the requests to resend: throttled ones, plus those that failed with
+/// `424 Failed Dependency` because something they depend on is resent.
+/// Returns them with `dependsOn` trimmed to requests in the retry, and the
+/// number of seconds to wait first.
+fn retries(
+    requests: &[BatchRequest],
+    responses: &HashMap<String, BatchResponse>,
+) -> (Vec<BatchRequest>, u64) {
+    let mut retry: HashSet<&str> = HashSet::new();
+    let mut wait = 0;
+    for response in responses.values().filter(|r| r.is_throttled()) {
+        retry.insert(&response.id);
+        wait = wait.max(response.retry_after());
+    }
+
+    // Requests are ordered after their dependencies within a batch, so keep
+    // sweeping until no further dependents are picked up.
+    loop {
+        let before = retry.len();
+        for request in requests {
+            let failed_dependency = responses
+                .get(&request.id)
+                .is_some_and(|response| response.status == 424);
+            if failed_dependency
+                && request
+                    .depends_on
+                    .iter()
+                    .any(|dependency| retry.contains(dependency.as_str()))
+            {
+                retry.insert(&request.id);
+            }
+        }
+        if retry.len() == before {
+            break;
+        }
+    }
+
+    let resend = requests
+        .iter()
+        .filter(|request| retry.contains(request.id.as_str()))
+        .map(|request| {
+            let mut request = request.clone();
+            request
+                .depends_on
+                .retain(|dependency| retry.contains(dependency.as_str()));
+            request
+        })
+        .collect();
+    (resend, wait)
+}
+
+pub(crate) async fn send(
+    client: &GraphClient,
+    requests: Vec<BatchRequest>,
+) -> Result<Vec<BatchResponse>> {
+    let order: Vec<String> = requests.iter().map(|r| r.id.clone()).collect();
+    let mut responses: HashMap<String, BatchResponse> = HashMap::new();
+    let mut pending = requests.clone();
+
+    for attempt in 0..=MAX_RETRIES {
+        for batch in chunk(pending)? {
+            for response in send_one(client, &batch).await? {
+                responses.insert(response.id.clone(), response);
+            }
+        }
+
+        let (resend, wait) = retries(&requests, &responses);
+        if resend.is_empty() || attempt == MAX_RETRIES {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+        pending = resend;
+    }
+
+    order
+        .iter()
+        .map(|id| {
+            responses
+                .remove(id)
+                .ok_or_else(|| anyhow!("no batch response for request {}", id))
+        })
+        .collect()
+}
+
+async fn send_one(client: &GraphClient, batch: &[BatchRequest]) -> Result<Vec<BatchResponse>> {
+    let body = json!({ "requests": batch });
+    for _ in 0..MAX_RETRIES {
+        // The envelope itself, which is throttled like the requests in it.
+        let reply = client.post_batch(&body).await?;
+        if reply.is_success() {
+            let reply: BatchReply = serde_json::from_value(reply.body)?;
+            return Ok(reply.responses);
+        }
+        if !reply.is_throttled() {
+            bail!(reply.error());
+        }
+        tokio::time::sleep(Duration::from_secs(reply.retry_after())).await;
+    }
+    bail!("Graph is throttling batch requests, try again later")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(id: &str, status: u16) -> BatchResponse {
+        BatchResponse {
+            id: id.to_string(),
+            status,
+            headers: HashMap::new(),
+            body: Value::Null,
+        }
+    }
+
+    #[test]
+    fn chunk_splits_at_batch_limit() {
+        let requests = (0..45)
+            .map(|i| BatchRequest::get(&i.to_string(), "/me"))
+            .collect();
+        let sizes: Vec<usize> = chunk(requests).unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![20, 20, 5]);
+    }
+
+    #[test]
+    fn chunk_keeps_dependencies_together() {
+        let mut requests: Vec<BatchRequest> = (0..19)
+            .map(|i| BatchRequest::get(&i.to_string(), "/me"))
+            .collect();
+        requests.push(BatchRequest::get("a", "/me"));
+        requests.push(BatchRequest::get("b", "/me").depends_on("a"));
+
+        let batches = chunk(requests).unwrap();
+        let with_b = batches
+            .iter()
+            .find(|batch| batch.iter().any(|r| r.id == "b"))
+            .unwrap();
+        assert!(with_b.iter().any(|r| r.id == "a"));
+        assert!(batches.iter().all(|batch| batch.len() <= MAX_BATCH_SIZE));
+    }
+
+    #[test]
+    fn chunk_rejects_unknown_dependency() {
+        let requests = vec![BatchRequest::get("a", "/me").depends_on("missing")];
+        assert!(chunk(requests).is_err());
+    }
+
+    #[test]
+    fn retries_throttled_and_failed_dependents() {
+        let requests = vec![
+            BatchRequest::get("1", "/me"),
+            BatchRequest::get("2", "/me").depends_on("1"),
+            BatchRequest::get("3", "/me"),
+        ];
+        let mut throttled = response("1", 429);
+        throttled
+            .headers
+            .insert("retry-after".to_string(), "7".to_string());
+        let responses = HashMap::from([
+            ("1".to_string(), throttled),
+            ("2".to_string(), response("2", 424)),
+            ("3".to_string(), response("3", 200)),
+        ]);
+
+        let (resend, wait) = retries(&requests, &responses);
+        let ids: Vec<&str> = resend.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(resend[1].depends_on, vec!["1".to_string()]);
+        assert_eq!(wait, 7);
+    }
+
+    #[test]
+    fn retried_dependent_drops_satisfied_dependency() {
+        let requests = vec![
+            BatchRequest::get("1", "/me"),
+            BatchRequest::get("2", "/me").depends_on("1"),
+        ];
+        let responses = HashMap::from([
+            ("1".to_string(), response("1", 200)),
+            ("2".to_string(), response("2", 429)),
+        ]);
+
+        let (resend, _) = retries(&requests, &responses);
+        assert_eq!(resend.len(), 1);
+        assert!(resend[0].depends_on.is_empty());
+    }
+}
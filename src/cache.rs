@@ -0,0 +1,226 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Local copy of every list and task, used to serve read commands with
+//! `--offline` or when Graph cannot be reached.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::is_unreachable;
+use crate::helper::get_cache_dir;
+use crate::journal::{Operation, OperationKind};
+use crate::lists::TodoList;
+use crate::tasksv2::TodoTask;
+use crate::user::MailboxSettings;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Store {
+    /// When the set of lists was last fetched.
+    pub synced_at: Option<DateTime<Utc>>,
+    /// Where `tdi sync` continues the lists delta query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_link: Option<String>,
+    pub lists: Vec<StoredList>,
+    /// From the last `tdi me`, for the user's time zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mailbox_settings: Option<MailboxSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredList {
+    pub list: TodoList,
+    /// When the tasks of this list were last fetched, if ever.
+    pub synced_at: Option<DateTime<Utc>>,
+    /// Where `tdi sync` continues the tasks delta query of this list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_link: Option<String>,
+    pub tasks: Vec<TodoTask>,
+}
+
+impl StoredList {
+    pub fn new(list: TodoList) -> StoredList {
+        StoredList {
+            list,
+            synced_at: None,
+            delta_link: None,
+            tasks: Vec::new(),
+        }
+    }
+}
+
+impl Store {
+    fn path() -> String {
+        get_cache_dir() + "/store.json"
+    }
+
+    /// Loads the store, or an empty one if nothing has been cached yet.
+    pub fn load() -> Result<Store> {
+        match std::fs::File::open(Store::path()) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Store::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Loads the store for serving a read command, failing if it is empty.
+    pub fn load_offline() -> Result<Store> {
+        let store = Store::load()?;
+        if store.synced_at.is_none() && store.lists.is_empty() {
+            return Err(anyhow!(
+                "no offline data yet, run `tdi tasks` while online first"
+            ));
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(get_cache_dir())?;
+        let tmp = Store::path() + ".tmp";
+        serde_json::to_writer(std::fs::File::create(&tmp)?, self)?;
+        std::fs::rename(tmp, Store::path())?;
+        Ok(())
+    }
+
+    /// Replaces the set of lists, keeping cached tasks of lists that remain.
+    pub fn set_lists(&mut self, lists: Vec<TodoList>) {
+        let mut previous = std::mem::take(&mut self.lists);
+        for list in lists {
+            match previous.iter().position(|s| s.list.id == list.id) {
+                Some(i) => {
+                    let mut stored = previous.swap_remove(i);
+                    stored.list = list;
+                    self.lists.push(stored);
+                }
+                None => self.lists.push(StoredList::new(list)),
+            }
+        }
+        self.synced_at = Some(Utc::now());
+    }
+
+    /// Adds a list fetched on its own, keeping what is stored of it.
+    pub fn add_list(&mut self, list: TodoList) {
+        if self.list(&list.id).is_none() {
+            self.lists.push(StoredList::new(list));
+        }
+    }
+
+    pub fn set_tasks(&mut self, list_id: &str, tasks: Vec<TodoTask>) {
+        if let Some(stored) = self.lists.iter_mut().find(|s| s.list.id == list_id) {
+            stored.tasks = tasks;
+            stored.synced_at = Some(Utc::now());
+        }
+    }
+
+    pub fn list(&self, list_id: &str) -> Option<&StoredList> {
+        self.lists.iter().find(|s| s.list.id == list_id)
+    }
+
+    pub fn task(&self, list_id: &str, task_id: &str) -> Option<&TodoTask> {
+        self.list(list_id)
+            .and_then(|stored| stored.tasks.iter().find(|task| task.id == task_id))
+    }
+
+    /// The list new tasks go to when no list is given.
+    pub fn default_list(&self) -> Option<&TodoList> {
+        self.lists
+            .iter()
+            .map(|stored| &stored.list)
+            .find(|list| list.wellknown_list_name == "defaultList")
+    }
+
+    pub fn remove_task(&mut self, list_id: &str, task_id: &str) {
+        if let Some(stored) = self.lists.iter_mut().find(|s| s.list.id == list_id) {
+            stored.tasks.retain(|task| task.id != task_id);
+        }
+    }
+
+    /// Applies a change made offline, so it shows before it is synced.
+    pub fn apply(&mut self, operation: &Operation) {
+        let Some(stored) = self
+            .lists
+            .iter_mut()
+            .find(|s| s.list.id == operation.list_id)
+        else {
+            return;
+        };
+        let position = stored
+            .tasks
+            .iter()
+            .position(|task| task.id == operation.task_id);
+
+        match (operation.kind, position) {
+            (OperationKind::Create, _) => {
+                let task = TodoTask::local(&operation.task_id, &operation.title);
+                stored.tasks.push(task.with_changes(&operation.changes));
+            }
+            (OperationKind::Update, Some(i)) => {
+                stored.tasks[i] = stored.tasks[i].with_changes(&operation.changes);
+            }
+            (OperationKind::Delete, Some(i)) => {
+                stored.tasks.remove(i);
+            }
+            (_, None) => {}
+        }
+    }
+}
+
+/// Runs `fetch` against Graph, returning `None` when the command should be
+/// served from the store instead: with `--offline`, or when Graph is
+/// unreachable.
+pub fn online<T>(offline: bool, fetch: impl FnOnce() -> Result<T>) -> Result<Option<T>> {
+    if offline {
+        return Ok(None);
+    }
+    match fetch() {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if is_unreachable(&err) => {
+            eprintln!("tdi: unable to reach Microsoft Graph, using offline data.");
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Prints how old the cached data is, on stderr so JSON output stays valid.
+pub fn print_age(synced_at: Option<DateTime<Utc>>) {
+    match synced_at {
+        Some(synced_at) => eprintln!(
+            "tdi: offline, showing data from {} ({})",
+            describe_age(Utc::now() - synced_at),
+            synced_at.format("%Y-%m-%d %H:%M UTC")
+        ),
+        None => eprintln!("tdi: offline, this data has never been synced"),
+    }
+}
+
+fn describe_age(age: chrono::Duration) -> String {
+    let (count, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        (age.num_minutes(), "minute")
+    } else {
+        return "just now".to_string();
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn describes_cache_age() {
+        assert_eq!(describe_age(Duration::seconds(20)), "just now");
+        assert_eq!(describe_age(Duration::minutes(1)), "1 minute ago");
+        assert_eq!(describe_age(Duration::hours(5)), "5 hours ago");
+        assert_eq!(describe_age(Duration::days(3)), "3 days ago");
+    }
+}
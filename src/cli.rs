@@ -3,12 +3,65 @@
 
 use clap::{Parser, Subcommand};
 
+/// How `--format taskwarrior` maps Taskwarrior fields to To Do.
+pub const TASKWARRIOR_HELP: &str = "\
+Taskwarrior (--format taskwarrior):
+    description    title
+    status         pending and waiting are not started, completed is
+                   completed; deleted and recurring templates are left out
+    priority       H is high, M or none normal and L low
+    due            due date
+    end            completion date
+    tags           categories, with spaces as \"_\"
+    annotations    the note, an annotation per line
+    project        the list; without --list, import adds tasks to the list
+                   named by their project
+    uuid           derived from the task id, the same for every export
+    entry          creation date, export only
+";
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Login to Microsoft's Graph database
-    Login {},
-    /// Logout of Microsoft's SSO
-    Logout {},
+    Login {
+        /// Encrypt the stored tokens with a passphrase, asked for or taken
+        /// from TDI_PASSPHRASE
+        #[clap(long)]
+        encrypt: bool,
+        /// Sign in on another device and paste the redirected URL, or just
+        /// the code, back into the terminal
+        #[clap(long)]
+        manual: bool,
+        /// The port to receive the sign in on; a free one is used if it is
+        /// taken
+        #[clap(long, default_value = "8000")]
+        port: u16,
+        /// How many seconds to wait for the sign in
+        #[clap(long, default_value = "300")]
+        timeout: u64,
+    },
+    /// Logout of Microsoft's SSO, deleting the tokens, caches, offline data
+    /// and history of the profile
+    Logout {
+        /// Log every profile out
+        #[clap(long)]
+        all: bool,
+        /// Also invalidate the refresh tokens of the account, which signs it
+        /// out of every app and device
+        #[clap(long, conflicts_with = "all")]
+        revoke: bool,
+        /// Discard offline changes that were not synced yet
+        #[clap(long)]
+        force: bool,
+    },
+    /// Inspect the stored login
+    Auth {
+        #[clap(subcommand)]
+        action: AuthCommands,
+    },
+    /// Open the full-screen interface, with lists, tasks, details and a
+    /// kanban board
+    Ui,
     /// Display the kanban board.
     Show {
         /// Display as JSON instead of the default table
@@ -18,13 +71,143 @@ pub enum Commands {
     Add {
         /// The task description text.
         task: String,
+        /// The list to add to, by the id shown by `tdi lists`; defaults to
+        /// the "Tasks" list
+        #[clap(short, long)]
+        list_id: Option<u16>,
+        /// When the task is due, "YYYY-MM-DD"
+        #[clap(long)]
+        due: Option<String>,
+        /// When to be reminded, "YYYY-MM-DD HH:MM"
+        #[clap(long)]
+        reminder: Option<String>,
+    },
+    /// Link a task to a web page, such as the pull request or work item it
+    /// is about; without a URL, show the links of the task
+    Link {
+        id: u32,
+        url: Option<String>,
+        /// What to call the link, defaults to the URL
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Remove a link of a task
+    Unlink {
+        id: u32,
+        /// The URL or number of the link, as shown by `tdi link`; can be
+        /// left out if the task has one link
+        link: Option<String>,
+    },
+    /// Open the first link of a task in the browser
+    Open { id: u32 },
+    /// Move tasks to another list
+    Move {
+        #[clap(required = true)]
+        ids: Vec<u32>,
+        /// The list to move to, by the id shown by `tdi lists`
+        #[clap(long)]
+        to: u16,
+    },
+    /// Write lists and tasks, with their checklist items, linked resources
+    /// and the account's categories, to stdout
+    #[clap(after_long_help = TASKWARRIOR_HELP)]
+    Export {
+        /// Export every list
+        #[clap(long, required_unless_present = "list-id")]
+        all: bool,
+        /// The list to export, by the id shown by `tdi lists`
+        #[clap(short, long, conflicts_with = "all")]
+        list_id: Option<u16>,
+        /// "json", "todotxt" for one todo.txt line per task, "ics" for an
+        /// iCalendar VTODO per task, "taskwarrior" for `task import`, or
+        /// "markdown" for a heading per list and a checkbox per task
+        #[clap(long, default_value = "json")]
+        format: String,
+    },
+    /// Recreate the lists and tasks of an export, adding to the lists with
+    /// the same names and leaving out tasks they have already
+    #[clap(after_long_help = TASKWARRIOR_HELP)]
+    Import {
+        /// The file to read, "-" for stdin
+        file: String,
+        /// "json", "todotxt" for one todo.txt line per task, "ics" for an
+        /// iCalendar VTODO per task, "taskwarrior" for `task export`, "csv"
+        /// for a row per task with the columns title, list, due,
+        /// importance, categories, notes and checklist, or "markdown" for
+        /// `- [ ]` items, under headings naming their lists
+        #[clap(long, default_value = "json")]
+        format: String,
+        /// The list to add to for formats without lists, created if there
+        /// is none with the name; defaults to the "Tasks" list. Given for
+        /// taskwarrior or markdown, projects and headings are not lists
+        #[clap(long)]
+        list: Option<String>,
+        /// Show what would be created without changing anything
+        #[clap(long)]
+        dry_run: bool,
+        /// Create new lists instead of adding to those with the same names
+        #[clap(long)]
+        new_lists: bool,
+    },
+    /// Keep a folder with a Markdown file per list in sync with To Do both
+    /// ways; new files become lists
+    Mirror {
+        /// The folder, created if there is none
+        dir: String,
+    },
+    /// Add a task for each TODO, FIXME and HACK comment of a source tree,
+    /// completing those whose comments are gone; files git ignores are left
+    /// out
+    Scan {
+        /// The folder or file to scan
+        path: String,
+        /// The list of the tasks, created if there is none with the name
+        #[clap(long)]
+        list: String,
+        /// Show what would change without changing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Revert the most recent complete, reopen, delete, edit, move or list
+    /// delete
+    Undo {
+        /// Show the history of commands that can be undone instead
+        #[clap(long)]
+        list: bool,
+        /// Display the history as "lines" or "json"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+    },
+    /// Change the title, importance, due date or reminder of a task
+    Edit {
+        id: u32,
+        #[clap(long)]
+        title: Option<String>,
+        /// "low", "normal" or "high"
+        #[clap(long)]
+        importance: Option<String>,
+        /// When the task is due, "YYYY-MM-DD"
+        #[clap(long)]
+        due: Option<String>,
+        /// When to be reminded, "YYYY-MM-DD HH:MM"
+        #[clap(long)]
+        reminder: Option<String>,
+    },
+    /// Complete the given tasks, by the ids shown by `tdi tasks`
+    Complete {
+        #[clap(required = true)]
+        ids: Vec<u32>,
+    },
+    /// Reopen the given tasks
+    Reopen {
+        #[clap(required = true)]
+        ids: Vec<u32>,
+    },
+    /// Delete the given tasks
+    Delete {
+        #[clap(required = true)]
+        ids: Vec<u32>,
     },
-    /// Complete the given task.
-    Complete { id: u32 },
-    /// Reopen the given task
-    Reopen { id: u32 },
-    /// Delete the given task
-    Delete { id: u32 },
     /// Show the user's details
     Me {
         /// Display output as "lines", "json" or "table"
@@ -36,7 +219,10 @@ pub enum Commands {
         /// Display output as "lines", "json" or "table"
         #[clap(default_value = "lines", short, long)]
         output_format: String,
+        #[clap(subcommand)]
+        action: Option<ListCommands>,
     },
+    /// Show the tasks of a list, or of every list when no id is given
     Tasks {
         /// Display output as "lines", "json" or "table"
         #[clap(default_value = "lines", short, long)]
@@ -44,17 +230,126 @@ pub enum Commands {
         /// Display all tasks, including those completed
         #[clap(short, long)]
         display_all: bool,
-        list_id: u16,
+        list_id: Option<u16>,
+    },
+    /// Download what changed since the last sync into the offline cache
+    Sync {
+        /// Display output as "lines" or "json"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+    },
+    /// List offline changes that conflict with remote edits
+    Conflicts {
+        /// Display output as "lines" or "json"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+        #[clap(subcommand)]
+        action: Option<ConflictCommands>,
+    },
+    /// Show or change the settings of the profile
+    Config {
+        #[clap(subcommand)]
+        action: Option<ConfigCommands>,
+    },
+    /// Manage account profiles
+    Profiles {
+        #[clap(subcommand)]
+        action: ProfileCommands,
     },
     /// Repl todo shell
     Intr,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum AuthCommands {
+    /// Show the account, tenant, scopes and token expiry of the profile;
+    /// exits with 67 when not logged in
+    Status {
+        /// Refresh the access token first
+        #[clap(long)]
+        refresh: bool,
+        /// Display output as "lines" or "json"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+    },
+    /// Encrypt the stored tokens with a passphrase, asked for or taken from
+    /// TDI_PASSPHRASE
+    Encrypt,
+    /// Store the tokens in plain JSON again
+    Decrypt,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ListCommands {
+    /// Delete a list and all of its tasks
+    Delete { id: u16 },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// List the settings
+    List {
+        /// Display output as "lines" or "json"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+    },
+    /// Print a setting
+    Get { key: String },
+    /// Change a setting:
+    /// "cloud" is "global", "usgov", "usgov-dod", "china" or "custom";
+    /// "authority" and "graph-endpoint" are the URLs of a custom cloud
+    Set { key: String, value: String },
+    /// Reset a setting to its default
+    Unset { key: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommands {
+    /// List profiles, marking the active one
+    List {
+        /// Display output as "lines", "json" or "table"
+        #[clap(default_value = "lines", short, long)]
+        output_format: String,
+    },
+    /// Use the given profile when `--profile` is not passed
+    Use { name: String },
+    /// Delete the stored tokens and caches of a profile
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConflictCommands {
+    /// Show the base, local and remote values of a conflict
+    Show { id: u32 },
+    /// Resolve a conflict
+    Resolve {
+        id: u32,
+        /// "local" pushes the offline change, "remote" discards it, "merge"
+        /// pushes only some of its fields
+        #[clap(long, default_value = "merge")]
+        keep: String,
+        /// With `--keep merge`, a field to take from the offline change;
+        /// defaults to the fields that were not also changed remotely
+        #[clap(long = "field")]
+        fields: Vec<String>,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 /// Simple CLI for Microsoft's To Do tasks
 pub struct Cli {
+    /// The account profile to use, see `tdi profiles`
+    #[clap(long, global = true, env = "TDI_PROFILE")]
+    pub profile: Option<String>,
+    /// Read lists and tasks from the local cache instead of Microsoft Graph
+    #[clap(long, global = true)]
+    pub offline: bool,
+    /// The time zone to show and enter dates in, an IANA or Windows name;
+    /// defaults to the one of the mailbox, else the system's
+    #[clap(long, global = true, env = "TDI_TZ")]
+    pub tz: Option<String>,
     #[clap(subcommand)]
     pub command: Option<Commands>,
 }
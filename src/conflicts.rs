@@ -0,0 +1,249 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Offline changes that `tdi sync` could not push because the task was also
+//! changed remotely, and `tdi conflicts` to resolve them.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::graph::{GraphClient, GraphError};
+use crate::helper::get_cache_dir;
+use crate::journal::{Operation, OperationKind};
+use crate::tasksv2::{writable_fields, TodoTask};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conflict {
+    pub operation: Operation,
+    /// The task as it is now in Graph, `None` if it no longer exists.
+    pub remote: Option<TodoTask>,
+    /// The fields changed on both sides.
+    pub fields: Vec<String>,
+    pub reason: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl Conflict {
+    pub fn new(
+        operation: Operation,
+        remote: Option<TodoTask>,
+        fields: Vec<String>,
+        reason: &str,
+    ) -> Conflict {
+        Conflict {
+            operation,
+            remote,
+            fields,
+            reason: reason.to_string(),
+            detected_at: Utc::now(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let kind = match self.operation.kind {
+            OperationKind::Create => "create",
+            OperationKind::Update => "update",
+            OperationKind::Delete => "delete",
+        };
+        if self.fields.is_empty() {
+            format!("{} ({}, local {})", self.operation.title, self.reason, kind)
+        } else {
+            format!(
+                "{} ({}, local {} of {})",
+                self.operation.title,
+                self.reason,
+                kind,
+                self.fields.join(", ")
+            )
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Conflicts {
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Conflicts {
+    fn path() -> String {
+        get_cache_dir() + "/conflicts.json"
+    }
+
+    pub fn load() -> Result<Conflicts> {
+        match std::fs::File::open(Conflicts::path()) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Conflicts::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(get_cache_dir())?;
+        serde_json::to_writer_pretty(std::fs::File::create(Conflicts::path())?, self)?;
+        Ok(())
+    }
+
+    /// Adds a conflict, replacing an older one about the same task.
+    pub fn add(&mut self, conflict: Conflict) {
+        self.conflicts
+            .retain(|c| c.operation.task_id != conflict.operation.task_id);
+        self.conflicts.push(conflict);
+    }
+
+    fn get(&self, id: &u32) -> Result<&Conflict> {
+        (*id as usize)
+            .checked_sub(1)
+            .and_then(|i| self.conflicts.get(i))
+            .ok_or_else(|| anyhow!("no conflict with id {}, run `tdi conflicts`", id))
+    }
+}
+
+pub fn list_conflicts(output_format: &str) -> Result<()> {
+    let conflicts = Conflicts::load()?;
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&conflicts.conflicts)?),
+        _ => {
+            if conflicts.conflicts.is_empty() {
+                println!("tdi: no conflicts.");
+            }
+            for (i, conflict) in conflicts.conflicts.iter().enumerate() {
+                println!("[{}] {}", i + 1, conflict.describe());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints base, local and remote values of every field involved.
+pub fn show_conflict(id: &u32) -> Result<()> {
+    let conflicts = Conflicts::load()?;
+    let conflict = conflicts.get(id)?;
+    println!("[{}] {}", id, conflict.describe());
+
+    let base = conflict
+        .operation
+        .base
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()?
+        .unwrap_or_default();
+    let remote = conflict
+        .remote
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut fields: Vec<&String> = conflict.operation.changes.keys().collect();
+    for field in &conflict.fields {
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+    for field in fields {
+        println!("{}:", field);
+        println!("  base:   {}", display(base.get(field)));
+        println!(
+            "  local:  {}",
+            display(conflict.operation.changes.get(field))
+        );
+        println!("  remote: {}", display(remote.get(field)));
+    }
+    Ok(())
+}
+
+fn display(value: Option<&Value>) -> String {
+    match value {
+        None => "-".to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Resolves a conflict by keeping the local change, the remote state, or
+/// merging: the given `fields` (or, if none are given, the fields that only
+/// changed locally) are taken from the local change, the rest stay remote.
+pub fn resolve_conflict(id: &u32, keep: &str, fields: &[String]) -> Result<()> {
+    let mut conflicts = Conflicts::load()?;
+    let conflict = conflicts.get(id)?;
+    let op = &conflict.operation;
+
+    let client = match keep {
+        "remote" => None,
+        "local" | "merge" => Some(GraphClient::new()?),
+        _ => bail!(
+            "unknown resolution {}, use \"local\", \"remote\" or \"merge\"",
+            keep
+        ),
+    };
+
+    if let Some(client) = client {
+        let changes = if keep == "merge" {
+            if op.kind != OperationKind::Update || conflict.remote.is_none() {
+                bail!("only edits of tasks that still exist can be merged");
+            }
+            let take: Vec<&String> = if fields.is_empty() {
+                op.changes
+                    .keys()
+                    .filter(|field| !conflict.fields.contains(field))
+                    .collect()
+            } else {
+                fields.iter().collect()
+            };
+            let mut changes = Map::new();
+            for field in take {
+                let value = op
+                    .changes
+                    .get(field)
+                    .ok_or_else(|| anyhow!("{} was not changed locally", field))?;
+                changes.insert(field.clone(), value.clone());
+            }
+            changes
+        } else {
+            op.changes.clone()
+        };
+        push(&client, conflict, changes)?;
+    }
+
+    let conflict = conflicts.conflicts.remove(*id as usize - 1);
+    conflicts.save()?;
+    println!(
+        "tdi: resolved [{}] {}, kept {} changes.",
+        id, conflict.operation.title, keep
+    );
+    Ok(())
+}
+
+fn push(client: &GraphClient, conflict: &Conflict, changes: Map<String, Value>) -> Result<()> {
+    let op = &conflict.operation;
+    let list_url = format!("/me/todo/lists/{}/tasks", op.list_id);
+    let task_url = format!("{}/{}", list_url, op.task_id);
+
+    match (op.kind, &conflict.remote) {
+        (OperationKind::Delete, _) => match client.request(Method::DELETE, &task_url, None) {
+            Err(err) if GraphError::status_of(&err) == Some(404) => Ok(()),
+            result => result.map(|_| ()),
+        },
+        (OperationKind::Create, _) => {
+            client.post(&list_url, &Value::Object(changes))?;
+            Ok(())
+        }
+        (OperationKind::Update, Some(_)) => {
+            if !changes.is_empty() {
+                client.patch(&task_url, &Value::Object(changes))?;
+            }
+            Ok(())
+        }
+        // Deleted remotely: recreate it from what it looked like plus the
+        // local change.
+        (OperationKind::Update, None) => {
+            let mut body = op.base.as_ref().map(writable_fields).unwrap_or_default();
+            body.extend(changes);
+            client.post(&list_url, &Value::Object(body))?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Dates as Graph sends them: a wall clock time and the name of the zone it
+//! is in, which is a Windows name like "Pacific Standard Time", an IANA name
+//! or "UTC". They are shown in the time zone from `--tz`, else the one of
+//! the user's mailbox, else the one of the system; due dates are days and
+//! are shown as they are.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::cache::Store;
+
+static TIME_ZONE: OnceLock<Tz> = OnceLock::new();
+
+/// The format of `dateTime`, Graph adds seven digits of fractions.
+const GRAPH_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Windows time zone names and the IANA zone each stands for, from the
+/// "001" territory of CLDR's windowsZones.xml.
+const WINDOWS_ZONES: &[(&str, &str)] = &[
+    ("Dateline Standard Time", "Etc/GMT+12"),
+    ("UTC-11", "Etc/GMT+11"),
+    ("Aleutian Standard Time", "America/Adak"),
+    ("Hawaiian Standard Time", "Pacific/Honolulu"),
+    ("Marquesas Standard Time", "Pacific/Marquesas"),
+    ("Alaskan Standard Time", "America/Anchorage"),
+    ("UTC-09", "Etc/GMT+9"),
+    ("Pacific Standard Time (Mexico)", "America/Tijuana"),
+    ("UTC-08", "Etc/GMT+8"),
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("US Mountain Standard Time", "America/Phoenix"),
+    ("Mountain Standard Time (Mexico)", "America/Mazatlan"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("Yukon Standard Time", "America/Whitehorse"),
+    ("Central America Standard Time", "America/Guatemala"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Easter Island Standard Time", "Pacific/Easter"),
+    ("Central Standard Time (Mexico)", "America/Mexico_City"),
+    ("Canada Central Standard Time", "America/Regina"),
+    ("SA Pacific Standard Time", "America/Bogota"),
+    ("Eastern Standard Time (Mexico)", "America/Cancun"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("Haiti Standard Time", "America/Port-au-Prince"),
+    ("Cuba Standard Time", "America/Havana"),
+    ("US Eastern Standard Time", "America/Indianapolis"),
+    ("Turks And Caicos Standard Time", "America/Grand_Turk"),
+    ("Paraguay Standard Time", "America/Asuncion"),
+    ("Atlantic Standard Time", "America/Halifax"),
+    ("Venezuela Standard Time", "America/Caracas"),
+    ("Central Brazilian Standard Time", "America/Cuiaba"),
+    ("SA Western Standard Time", "America/La_Paz"),
+    ("Pacific SA Standard Time", "America/Santiago"),
+    ("Newfoundland Standard Time", "America/St_Johns"),
+    ("Tocantins Standard Time", "America/Araguaina"),
+    ("E. South America Standard Time", "America/Sao_Paulo"),
+    ("SA Eastern Standard Time", "America/Cayenne"),
+    ("Argentina Standard Time", "America/Buenos_Aires"),
+    ("Greenland Standard Time", "America/Godthab"),
+    ("Montevideo Standard Time", "America/Montevideo"),
+    ("Magallanes Standard Time", "America/Punta_Arenas"),
+    ("Saint Pierre Standard Time", "America/Miquelon"),
+    ("Bahia Standard Time", "America/Bahia"),
+    ("UTC-02", "Etc/GMT+2"),
+    ("Azores Standard Time", "Atlantic/Azores"),
+    ("Cape Verde Standard Time", "Atlantic/Cape_Verde"),
+    ("UTC", "Etc/UTC"),
+    ("GMT Standard Time", "Europe/London"),
+    ("Greenwich Standard Time", "Atlantic/Reykjavik"),
+    ("Sao Tome Standard Time", "Africa/Sao_Tome"),
+    ("Morocco Standard Time", "Africa/Casablanca"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("Central Europe Standard Time", "Europe/Budapest"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("Central European Standard Time", "Europe/Warsaw"),
+    ("W. Central Africa Standard Time", "Africa/Lagos"),
+    ("Jordan Standard Time", "Asia/Amman"),
+    ("GTB Standard Time", "Europe/Bucharest"),
+    ("Middle East Standard Time", "Asia/Beirut"),
+    ("Egypt Standard Time", "Africa/Cairo"),
+    ("E. Europe Standard Time", "Europe/Chisinau"),
+    ("Syria Standard Time", "Asia/Damascus"),
+    ("West Bank Standard Time", "Asia/Hebron"),
+    ("South Africa Standard Time", "Africa/Johannesburg"),
+    ("FLE Standard Time", "Europe/Kiev"),
+    ("Israel Standard Time", "Asia/Jerusalem"),
+    ("South Sudan Standard Time", "Africa/Juba"),
+    ("Kaliningrad Standard Time", "Europe/Kaliningrad"),
+    ("Sudan Standard Time", "Africa/Khartoum"),
+    ("Libya Standard Time", "Africa/Tripoli"),
+    ("Namibia Standard Time", "Africa/Windhoek"),
+    ("Arabic Standard Time", "Asia/Baghdad"),
+    ("Turkey Standard Time", "Europe/Istanbul"),
+    ("Arab Standard Time", "Asia/Riyadh"),
+    ("Belarus Standard Time", "Europe/Minsk"),
+    ("Russian Standard Time", "Europe/Moscow"),
+    ("E. Africa Standard Time", "Africa/Nairobi"),
+    ("Volgograd Standard Time", "Europe/Volgograd"),
+    ("Iran Standard Time", "Asia/Tehran"),
+    ("Arabian Standard Time", "Asia/Dubai"),
+    ("Astrakhan Standard Time", "Europe/Astrakhan"),
+    ("Azerbaijan Standard Time", "Asia/Baku"),
+    ("Russia Time Zone 3", "Europe/Samara"),
+    ("Mauritius Standard Time", "Indian/Mauritius"),
+    ("Saratov Standard Time", "Europe/Saratov"),
+    ("Georgian Standard Time", "Asia/Tbilisi"),
+    ("Caucasus Standard Time", "Asia/Yerevan"),
+    ("Afghanistan Standard Time", "Asia/Kabul"),
+    ("West Asia Standard Time", "Asia/Tashkent"),
+    ("Ekaterinburg Standard Time", "Asia/Yekaterinburg"),
+    ("Pakistan Standard Time", "Asia/Karachi"),
+    ("Qyzylorda Standard Time", "Asia/Qyzylorda"),
+    ("India Standard Time", "Asia/Calcutta"),
+    ("Sri Lanka Standard Time", "Asia/Colombo"),
+    ("Nepal Standard Time", "Asia/Katmandu"),
+    ("Central Asia Standard Time", "Asia/Bishkek"),
+    ("Bangladesh Standard Time", "Asia/Dhaka"),
+    ("Omsk Standard Time", "Asia/Omsk"),
+    ("Myanmar Standard Time", "Asia/Rangoon"),
+    ("SE Asia Standard Time", "Asia/Bangkok"),
+    ("Altai Standard Time", "Asia/Barnaul"),
+    ("W. Mongolia Standard Time", "Asia/Hovd"),
+    ("North Asia Standard Time", "Asia/Krasnoyarsk"),
+    ("N. Central Asia Standard Time", "Asia/Novosibirsk"),
+    ("Tomsk Standard Time", "Asia/Tomsk"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("North Asia East Standard Time", "Asia/Irkutsk"),
+    ("Singapore Standard Time", "Asia/Singapore"),
+    ("W. Australia Standard Time", "Australia/Perth"),
+    ("Taipei Standard Time", "Asia/Taipei"),
+    ("Ulaanbaatar Standard Time", "Asia/Ulaanbaatar"),
+    ("Aus Central W. Standard Time", "Australia/Eucla"),
+    ("Transbaikal Standard Time", "Asia/Chita"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("North Korea Standard Time", "Asia/Pyongyang"),
+    ("Korea Standard Time", "Asia/Seoul"),
+    ("Yakutsk Standard Time", "Asia/Yakutsk"),
+    ("Cen. Australia Standard Time", "Australia/Adelaide"),
+    ("AUS Central Standard Time", "Australia/Darwin"),
+    ("E. Australia Standard Time", "Australia/Brisbane"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("West Pacific Standard Time", "Pacific/Port_Moresby"),
+    ("Tasmania Standard Time", "Australia/Hobart"),
+    ("Vladivostok Standard Time", "Asia/Vladivostok"),
+    ("Lord Howe Standard Time", "Australia/Lord_Howe"),
+    ("Bougainville Standard Time", "Pacific/Bougainville"),
+    ("Russia Time Zone 10", "Asia/Srednekolymsk"),
+    ("Magadan Standard Time", "Asia/Magadan"),
+    ("Norfolk Standard Time", "Pacific/Norfolk"),
+    ("Sakhalin Standard Time", "Asia/Sakhalin"),
+    ("Central Pacific Standard Time", "Pacific/Guadalcanal"),
+    ("Russia Time Zone 11", "Asia/Kamchatka"),
+    ("New Zealand Standard Time", "Pacific/Auckland"),
+    ("UTC+12", "Etc/GMT-12"),
+    ("Fiji Standard Time", "Pacific/Fiji"),
+    ("Chatham Islands Standard Time", "Pacific/Chatham"),
+    ("UTC+13", "Etc/GMT-13"),
+    ("Tonga Standard Time", "Pacific/Tongatapu"),
+    ("Samoa Standard Time", "Pacific/Apia"),
+    ("Line Islands Standard Time", "Pacific/Kiritimati"),
+];
+
+/// A `dateTimeTimeZone` of Graph, e.g. the `dueDateTime` of a task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateTimeTimeZone {
+    /// The wall clock time in `time_zone`, e.g. "2024-03-10T00:00:00.0000000".
+    pub date_time: String,
+    pub time_zone: String,
+}
+
+impl DateTimeTimeZone {
+    /// The instant this is, in the zone `tz`.
+    pub fn in_zone(&self, tz: &Tz) -> Result<DateTime<Tz>> {
+        let zone = resolve_zone(&self.time_zone)?;
+        let local = NaiveDateTime::parse_from_str(&self.date_time, GRAPH_FORMAT)
+            .map_err(|err| anyhow!("invalid date {}: {}", self.date_time, err))?;
+        Ok(from_local(&zone, local)?.with_timezone(tz))
+    }
+
+    /// What to send for `time`: its wall clock time and the name of its
+    /// zone, the Windows one when there is one as every Graph endpoint takes
+    /// those, else the IANA one.
+    pub fn from_date_time(time: &DateTime<Tz>) -> DateTimeTimeZone {
+        DateTimeTimeZone {
+            date_time: time.naive_local().format("%Y-%m-%dT%H:%M:%S").to_string(),
+            time_zone: zone_name(&time.timezone()),
+        }
+    }
+
+    /// What to send for the day of `time`, as due dates are: midnight of that
+    /// day in its zone, so To Do shows the same day wherever it is opened.
+    pub fn day(time: &DateTime<Tz>) -> DateTimeTimeZone {
+        DateTimeTimeZone {
+            date_time: time.format("%Y-%m-%dT00:00:00").to_string(),
+            time_zone: zone_name(&time.timezone()),
+        }
+    }
+}
+
+/// The Windows name of `tz` if it has one, else its IANA name.
+fn zone_name(tz: &Tz) -> String {
+    let name = tz.name();
+    WINDOWS_ZONES
+        .iter()
+        .find(|(_, iana)| *iana == name)
+        .map_or(name, |(windows, _)| windows)
+        .to_string()
+}
+
+/// The zone with an IANA or Windows name.
+pub fn resolve_zone(name: &str) -> Result<Tz> {
+    if name.eq_ignore_ascii_case("utc") || name == "tzone://Microsoft/Utc" {
+        return Ok(Tz::UTC);
+    }
+    if let Ok(tz) = name.parse::<Tz>() {
+        return Ok(tz);
+    }
+    WINDOWS_ZONES
+        .iter()
+        .find(|(windows, _)| windows.eq_ignore_ascii_case(name))
+        .and_then(|(_, iana)| iana.parse().ok())
+        .ok_or_else(|| anyhow!("unknown time zone {}", name))
+}
+
+/// Places a wall clock time in `zone`. A time that happens twice as the
+/// clocks go back is taken the first time; one skipped as they go forward is
+/// moved forward by the gap, as a clock would.
+fn from_local(zone: &Tz, local: NaiveDateTime) -> Result<DateTime<Tz>> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => Ok(time),
+        LocalResult::None => {
+            let before = zone
+                .from_local_datetime(&(local - Duration::days(1)))
+                .earliest();
+            let after = zone
+                .from_local_datetime(&(local + Duration::days(1)))
+                .earliest();
+            match (before, after) {
+                (Some(before), Some(after)) => {
+                    let gap = after.offset().fix().local_minus_utc()
+                        - before.offset().fix().local_minus_utc();
+                    zone.from_local_datetime(&(local + Duration::seconds(gap.into())))
+                        .earliest()
+                        .ok_or_else(|| anyhow!("{} does not exist in {}", local, zone))
+                }
+                _ => bail!("{} does not exist in {}", local, zone),
+            }
+        }
+    }
+}
+
+/// Selects the zone dates are shown and entered in, from `--tz`.
+pub fn set_time_zone(name: &Option<String>) -> Result<()> {
+    if let Some(name) = name {
+        let _ = TIME_ZONE.set(resolve_zone(name)?);
+    }
+    Ok(())
+}
+
+/// The zone dates are shown and entered in: the one from `--tz`, else the
+/// one of the mailbox from the last `tdi me`, else the system's, else UTC.
+pub fn time_zone() -> Tz {
+    *TIME_ZONE.get_or_init(|| {
+        let mailbox = Store::load()
+            .ok()
+            .and_then(|store| store.mailbox_settings)
+            .and_then(|settings| settings.time_zone);
+        mailbox
+            .and_then(|name| resolve_zone(&name).ok())
+            .or_else(|| {
+                iana_time_zone::get_timezone()
+                    .ok()
+                    .and_then(|name| name.parse().ok())
+            })
+            .unwrap_or(Tz::UTC)
+    })
+}
+
+/// Reads a date as given on the command line, "2024-03-10" or
+/// "2024-03-10 14:30", in the zone `tz`.
+pub fn parse_date(input: &str, tz: &Tz) -> Result<DateTime<Tz>> {
+    let local = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| {
+            anyhow!(
+                "invalid date {}, use \"YYYY-MM-DD\" or \"YYYY-MM-DD HH:MM\"",
+                input
+            )
+        })?;
+    from_local(tz, local)
+}
+
+/// A date for display: the day alone when it is at midnight, as due dates
+/// are, else the day and time.
+pub fn format_date(time: &DateTime<Tz>) -> String {
+    if time.format("%H:%M:%S").to_string() == "00:00:00" {
+        time.format("%Y-%m-%d").to_string()
+    } else {
+        time.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+/// Formats `value`, a `dateTimeTimeZone` of Graph, in `tz`; `None` when it is
+/// missing or cannot be read.
+pub fn display(value: Option<&serde_json::Value>, tz: &Tz) -> Option<String> {
+    let date: DateTimeTimeZone = serde_json::from_value(value?.clone()).ok()?;
+    date.in_zone(tz).ok().map(|time| format_date(&time))
+}
+
+/// The day of `value`, a `dateTimeTimeZone` of Graph such as a due date, as
+/// it was written: a day is the same day in every zone.
+pub fn display_day(value: Option<&serde_json::Value>) -> Option<String> {
+    let date: DateTimeTimeZone = serde_json::from_value(value?.clone()).ok()?;
+    NaiveDateTime::parse_from_str(&date.date_time, GRAPH_FORMAT)
+        .ok()
+        .map(|time| time.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn graph(date_time: &str, time_zone: &str) -> DateTimeTimeZone {
+        DateTimeTimeZone {
+            date_time: date_time.to_string(),
+            time_zone: time_zone.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolves_windows_and_iana_names() {
+        assert_eq!(
+            resolve_zone("Pacific Standard Time").unwrap(),
+            Tz::America__Los_Angeles
+        );
+        assert_eq!(
+            resolve_zone("Europe/Amsterdam").unwrap(),
+            Tz::Europe__Amsterdam
+        );
+        assert_eq!(resolve_zone("UTC").unwrap(), Tz::UTC);
+        assert!(resolve_zone("Mars Standard Time").is_err());
+    }
+
+    #[test]
+    fn converts_across_the_spring_forward() {
+        let la = Tz::America__Los_Angeles;
+        // Clocks in Los Angeles went from 02:00 PST to 03:00 PDT at 10:00 UTC.
+        let before = graph("2024-03-10T09:59:00.0000000", "UTC");
+        let after = graph("2024-03-10T10:00:00.0000000", "UTC");
+        assert_eq!(
+            format_date(&before.in_zone(&la).unwrap()),
+            "2024-03-10 01:59"
+        );
+        assert_eq!(
+            format_date(&after.in_zone(&la).unwrap()),
+            "2024-03-10 03:00"
+        );
+
+        // 02:30 never happened there, it is taken as 03:30 PDT.
+        let skipped = graph("2024-03-10T02:30:00.0000000", "Pacific Standard Time");
+        assert_eq!(
+            skipped.in_zone(&Tz::UTC).unwrap().to_rfc3339(),
+            "2024-03-10T10:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn converts_across_the_fall_back() {
+        // 02:30 happened twice in Berlin, first in CEST.
+        let twice = graph("2023-10-29T02:30:00.0000000", "W. Europe Standard Time");
+        assert_eq!(
+            twice.in_zone(&Tz::UTC).unwrap().to_rfc3339(),
+            "2023-10-29T00:30:00+00:00"
+        );
+        // A reminder at midnight UTC is at one in Berlin after the change,
+        // an hour earlier than before it.
+        let reminder = graph("2023-10-30T00:00:00.0000000", "UTC");
+        assert_eq!(
+            format_date(&reminder.in_zone(&Tz::Europe__Berlin).unwrap()),
+            "2023-10-30 01:00"
+        );
+    }
+
+    #[test]
+    fn writes_zone_names_and_keeps_due_days() {
+        let berlin = parse_date("2024-07-01 09:15", &Tz::Europe__Berlin).unwrap();
+        assert_eq!(
+            DateTimeTimeZone::from_date_time(&berlin),
+            graph("2024-07-01T09:15:00", "W. Europe Standard Time")
+        );
+        // Amsterdam has no Windows name of its own, the day is kept.
+        let amsterdam = parse_date("2024-07-01", &Tz::Europe__Amsterdam).unwrap();
+        assert_eq!(
+            DateTimeTimeZone::day(&amsterdam),
+            graph("2024-07-01T00:00:00", "Europe/Amsterdam")
+        );
+        assert_eq!(
+            DateTimeTimeZone::day(&berlin),
+            graph("2024-07-01T00:00:00", "W. Europe Standard Time")
+        );
+        // A due day is shown as it is, even west of where it was written.
+        let due = json!(graph("2024-07-01T00:00:00.0000000", "UTC"));
+        assert_eq!(display_day(Some(&due)).as_deref(), Some("2024-07-01"));
+        assert!(parse_date("tomorrow", &Tz::UTC).is_err());
+    }
+}
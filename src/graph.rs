@@ -0,0 +1,222 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+use anyhow::Result;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::auth::{access_token, refresh_access_token};
+use crate::batch::{self, BatchRequest, BatchResponse};
+use crate::settings;
+
+/// A page of a Graph collection, e.g. `GET /me/todo/lists`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Collection<T> {
+    pub value: Vec<T>,
+    #[serde(rename = "@odata.nextLink", skip_serializing_if = "Option::is_none")]
+    pub next_link: Option<String>,
+    /// Present on the last page of a delta query.
+    #[serde(rename = "@odata.deltaLink", skip_serializing_if = "Option::is_none")]
+    pub delta_link: Option<String>,
+}
+
+/// An error response from Graph, kept typed so callers can act on the status.
+#[derive(Debug)]
+pub struct GraphError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+impl GraphError {
+    pub fn new(status: u16, body: &Value) -> GraphError {
+        GraphError {
+            status,
+            message: error_message(status, body),
+        }
+    }
+
+    /// The status Graph answered with, if `err` is a Graph error response.
+    pub fn status_of(err: &anyhow::Error) -> Option<u16> {
+        err.downcast_ref::<GraphError>().map(|err| err.status)
+    }
+}
+
+/// Shared HTTP client for Microsoft Graph.
+///
+/// A command creates one of these and uses it for every request it makes, so
+/// there is a single connection pool and a single Tokio runtime per process.
+pub struct GraphClient {
+    runtime: tokio::runtime::Runtime,
+    http: reqwest::Client,
+    /// The Graph root of the profile's cloud, e.g.
+    /// `https://graph.microsoft.com/v1.0`.
+    graph_url: String,
+    /// Replaced when Graph rejects it, see [`GraphClient::send`].
+    token: Mutex<String>,
+}
+
+impl GraphClient {
+    /// Creates a client authenticated with the stored access token.
+    pub fn new() -> Result<GraphClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let token = runtime.block_on(access_token())?;
+        Ok(GraphClient {
+            runtime,
+            http: reqwest::Client::new(),
+            graph_url: settings::endpoints()?.graph_url(),
+            token: Mutex::new(token),
+        })
+    }
+
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Sends a single request and returns the parsed JSON body, or `Null` for
+    /// responses without content (e.g. `204` from a `DELETE`).
+    pub fn request(&self, method: Method, path: &str, body: Option<&Value>) -> Result<Value> {
+        self.block_on(self.request_async(method, path, body))
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let value = self.request(Method::GET, path, None)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        self.request(Method::POST, path, Some(body))
+    }
+
+    pub fn patch(&self, path: &str, body: &Value) -> Result<Value> {
+        self.request(Method::PATCH, path, Some(body))
+    }
+
+    /// Fetches every page of a collection by following `@odata.nextLink`.
+    pub fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut next = Some(path.to_string());
+        while let Some(path) = next {
+            let page: Collection<T> = self.get(&path)?;
+            items.extend(page.value);
+            next = page.next_link;
+        }
+        Ok(items)
+    }
+
+    /// Sends the requests through `/$batch`, see [`batch`] for how they are
+    /// split and retried. Responses are returned in the order of `requests`.
+    pub fn batch(&self, requests: Vec<BatchRequest>) -> Result<Vec<BatchResponse>> {
+        self.block_on(batch::send(self, requests))
+    }
+
+    pub(crate) async fn request_async(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value> {
+        let response = self
+            .send(|| {
+                let request = self.http.request(method.clone(), self.url(path));
+                match body {
+                    Some(body) => request.json(body),
+                    None => request,
+                }
+            })
+            .await?;
+        let status = response.status();
+        let text = response.text().await?;
+        let value = if text.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&text).unwrap_or(Value::String(text))
+        };
+
+        if status.is_success() {
+            Ok(value)
+        } else {
+            Err(GraphError::new(status.as_u16(), &value).into())
+        }
+    }
+
+    /// Posts one `/$batch` envelope, returning the HTTP status and body so the
+    /// caller can decide whether to retry a throttled envelope.
+    pub(crate) async fn post_batch(&self, body: &Value) -> Result<BatchResponse> {
+        let response = self
+            .send(|| self.http.post(self.url("/$batch")).json(body))
+            .await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.json().await.unwrap_or(Value::Null);
+        Ok(BatchResponse {
+            id: String::new(),
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// Strips the Graph root from an absolute link (e.g. a
+    /// `@odata.deltaLink`) so it can be used in a `$batch` request.
+    pub fn relative_url<'a>(&self, link: &'a str) -> &'a str {
+        link.strip_prefix(&self.graph_url).unwrap_or(link)
+    }
+
+    fn url(&self, path: &str) -> String {
+        if path.starts_with("https://") || path.starts_with("http://") {
+            path.to_string()
+        } else {
+            format!("{}{}", self.graph_url, path)
+        }
+    }
+
+    /// Sends the request made by `build` with the access token. When Graph
+    /// answers `401`, the token is refreshed and the request sent once more.
+    async fn send(&self, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let token = self.token.lock().unwrap().clone();
+        let response = build().bearer_auth(&token).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let token = refresh_access_token(&token).await?;
+        *self.token.lock().unwrap() = token.clone();
+        Ok(build().bearer_auth(&token).send().await?)
+    }
+}
+
+/// Whether a request failed because Graph could not be reached at all, as
+/// opposed to Graph answering with an error.
+pub fn is_unreachable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .is_some_and(|err| err.is_connect() || err.is_timeout())
+}
+
+/// Formats a Graph error body (`{"error": {"code": .., "message": ..}}`).
+pub fn error_message(status: u16, body: &Value) -> String {
+    let error = &body["error"];
+    match (error["code"].as_str(), error["message"].as_str()) {
+        (Some(code), Some(message)) => format!("{} {}: {}", status, code, message),
+        (Some(code), None) => format!("{} {}", status, code),
+        _ => format!("request failed with status {}", status),
+    }
+}
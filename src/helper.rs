@@ -2,9 +2,49 @@
 // Licensed under the MIT License.
 
 use directories::ProjectDirs;
+use std::sync::OnceLock;
 
-pub fn get_config_dir() -> String {
+pub const DEFAULT_PROFILE: &str = "default";
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Selects the profile whose tokens and caches this process uses.
+pub fn set_profile(name: &str) {
+    let _ = PROFILE.set(name.to_string());
+}
+
+pub fn get_profile() -> &'static str {
+    PROFILE.get().map(String::as_str).unwrap_or(DEFAULT_PROFILE)
+}
+
+/// The config directory shared by every profile.
+pub fn get_base_config_dir() -> String {
     let proj_dirs = ProjectDirs::from("com", "microsofthackathons", "tdi");
     let config_dir = proj_dirs.unwrap().config_dir().to_path_buf();
     config_dir.into_os_string().into_string().unwrap()
-}
\ No newline at end of file
+}
+
+/// The cache directory shared by every profile.
+pub fn get_base_cache_dir() -> String {
+    let proj_dirs = ProjectDirs::from("com", "microsofthackathons", "tdi");
+    let cache_dir = proj_dirs.unwrap().cache_dir().to_path_buf();
+    cache_dir.into_os_string().into_string().unwrap()
+}
+
+pub fn get_profile_config_dir(profile: &str) -> String {
+    get_base_config_dir() + "/profiles/" + profile
+}
+
+pub fn get_profile_cache_dir(profile: &str) -> String {
+    get_base_cache_dir() + "/profiles/" + profile
+}
+
+/// The config directory of the active profile.
+pub fn get_config_dir() -> String {
+    get_profile_config_dir(get_profile())
+}
+
+/// The cache directory of the active profile.
+pub fn get_cache_dir() -> String {
+    get_profile_cache_dir(get_profile())
+}
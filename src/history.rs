@@ -0,0 +1,336 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! History of mutating commands with the state they replaced, for `tdi undo`.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, Utc};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::graph::{GraphClient, GraphError};
+use crate::helper::get_cache_dir;
+use crate::lists::TodoList;
+use crate::tasksv2::{create_task, ChecklistItem, TodoTask};
+
+/// How many commands are remembered.
+const MAX_ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletedTask {
+    pub task: TodoTask,
+    pub checklist_items: Vec<ChecklistItem>,
+}
+
+/// One change made by a command, with what is needed to revert it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Change {
+    /// Fields of a task were changed; `before` holds their previous values.
+    #[serde(rename_all = "camelCase")]
+    TaskUpdated {
+        list_id: String,
+        task_id: String,
+        title: String,
+        before: Map<String, Value>,
+    },
+    #[serde(rename_all = "camelCase")]
+    TaskDeleted {
+        list_id: String,
+        deleted: DeletedTask,
+    },
+    /// Moving is a copy to the new list followed by a delete.
+    #[serde(rename_all = "camelCase")]
+    TaskMoved {
+        from_list_id: String,
+        to_list_id: String,
+        new_task_id: String,
+        deleted: DeletedTask,
+    },
+    #[serde(rename_all = "camelCase")]
+    ListDeleted {
+        list: TodoList,
+        tasks: Vec<DeletedTask>,
+    },
+    /// A task was created; what is left of undoing a move once the task is
+    /// back in its list.
+    #[serde(rename_all = "camelCase")]
+    TaskCreated {
+        list_id: String,
+        task_id: String,
+        title: String,
+    },
+}
+
+impl Change {
+    fn title(&self) -> &str {
+        match self {
+            Change::TaskUpdated { title, .. } | Change::TaskCreated { title, .. } => title,
+            Change::TaskDeleted { deleted, .. } | Change::TaskMoved { deleted, .. } => {
+                &deleted.task.title
+            }
+            Change::ListDeleted { list, .. } => &list.display_name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// What the command did, e.g. "completed".
+    pub command: String,
+    pub recorded_at: DateTime<Utc>,
+    pub changes: Vec<Change>,
+}
+
+impl Entry {
+    fn summary(&self) -> String {
+        let titles: Vec<&str> = self.changes.iter().map(Change::title).collect();
+        format!("{} {}", self.command, titles.join(", "))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub entries: Vec<Entry>,
+}
+
+impl History {
+    fn path() -> String {
+        get_cache_dir() + "/history.json"
+    }
+
+    pub fn load() -> Result<History> {
+        match std::fs::File::open(History::path()) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(get_cache_dir())?;
+        serde_json::to_writer_pretty(std::fs::File::create(History::path())?, self)?;
+        Ok(())
+    }
+
+    /// Remembers the changes of a command, dropping the oldest entries.
+    pub fn record(command: &str, changes: Vec<Change>) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let mut history = History::load()?;
+        history.entries.push(Entry {
+            command: command.to_string(),
+            recorded_at: Utc::now(),
+            changes,
+        });
+        let excess = history.entries.len().saturating_sub(MAX_ENTRIES);
+        history.entries.drain(..excess);
+        history.save()
+    }
+
+    /// Points older entries at the id a task was recreated with.
+    fn rename_task(&mut self, old_id: &str, new_id: &str) {
+        for change in self.entries.iter_mut().flat_map(|e| e.changes.iter_mut()) {
+            match change {
+                Change::TaskUpdated { task_id, .. } | Change::TaskCreated { task_id, .. }
+                    if task_id == old_id =>
+                {
+                    *task_id = new_id.to_string()
+                }
+                Change::TaskMoved { new_task_id, .. } if new_task_id == old_id => {
+                    *new_task_id = new_id.to_string()
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn rename_list(&mut self, old_id: &str, new_id: &str) {
+        for change in self.entries.iter_mut().flat_map(|e| e.changes.iter_mut()) {
+            let ids: Vec<&mut String> = match change {
+                Change::TaskUpdated { list_id, .. }
+                | Change::TaskDeleted { list_id, .. }
+                | Change::TaskCreated { list_id, .. } => vec![list_id],
+                Change::TaskMoved {
+                    from_list_id,
+                    to_list_id,
+                    ..
+                } => vec![from_list_id, to_list_id],
+                Change::ListDeleted { .. } => vec![],
+            };
+            for id in ids.into_iter().filter(|id| *id == old_id) {
+                *id = new_id.to_string();
+            }
+        }
+    }
+}
+
+pub fn show_history(output_format: &str) -> Result<()> {
+    let history = History::load()?;
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&history.entries)?),
+        _ => {
+            if history.entries.is_empty() {
+                println!("tdi: nothing to undo.");
+            }
+            for (i, entry) in history.entries.iter().rev().enumerate() {
+                println!(
+                    "[{}] {} // {}",
+                    i + 1,
+                    entry.summary(),
+                    entry
+                        .recorded_at
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reverts the most recent command in the history.
+pub fn undo() -> Result<()> {
+    let mut history = History::load()?;
+    let Some(entry) = history.entries.last() else {
+        bail!("nothing to undo");
+    };
+    let summary = entry.summary();
+
+    let client = GraphClient::new()?;
+    // Revert in reverse order, as a later change may build on an earlier one.
+    // Each step is saved as it is done, so that after a failure the next
+    // `tdi undo` only does what is left.
+    while let Some(change) = history.entries.last_mut().and_then(|e| e.changes.pop()) {
+        let result = revert(&client, &mut history, &change);
+        if let Some(entry) = history.entries.last_mut() {
+            match &result {
+                Ok(rest) => entry.changes.extend(rest.iter().cloned()),
+                Err(_) => entry.changes.push(change),
+            }
+        }
+        history.save()?;
+        result?;
+    }
+
+    history.entries.pop();
+    history.save()?;
+    println!("tdi: undid {}", summary);
+    Ok(())
+}
+
+/// Reverts the first step of a change, returning the changes left to revert
+/// for the rest of it.
+fn revert(client: &GraphClient, history: &mut History, change: &Change) -> Result<Vec<Change>> {
+    match change {
+        Change::TaskUpdated {
+            list_id,
+            task_id,
+            before,
+            ..
+        } => {
+            client.patch(
+                &format!("/me/todo/lists/{}/tasks/{}", list_id, task_id),
+                &Value::Object(before.clone()),
+            )?;
+        }
+        Change::TaskDeleted { list_id, deleted } => {
+            let new_id = create_task(client, list_id, &deleted.task, &deleted.checklist_items)?;
+            history.rename_task(&deleted.task.id, &new_id);
+        }
+        Change::TaskMoved {
+            from_list_id,
+            to_list_id,
+            new_task_id,
+            deleted,
+        } => {
+            let new_id = create_task(
+                client,
+                from_list_id,
+                &deleted.task,
+                &deleted.checklist_items,
+            )?;
+            history.rename_task(&deleted.task.id, &new_id);
+            return Ok(vec![Change::TaskCreated {
+                list_id: to_list_id.clone(),
+                task_id: new_task_id.clone(),
+                title: deleted.task.title.clone(),
+            }]);
+        }
+        Change::TaskCreated {
+            list_id, task_id, ..
+        } => {
+            let url = format!("/me/todo/lists/{}/tasks/{}", list_id, task_id);
+            match client.request(Method::DELETE, &url, None) {
+                Err(err) if GraphError::status_of(&err) == Some(404) => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+        Change::ListDeleted { list, tasks } => {
+            let created = client.post(
+                "/me/todo/lists",
+                &json!({ "displayName": list.display_name }),
+            )?;
+            let new_list_id = created["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("no id for the recreated list {}", list.display_name))?
+                .to_string();
+            history.rename_list(&list.id, &new_list_id);
+            // The tasks are brought back one by one into the new list.
+            return Ok(tasks
+                .iter()
+                .rev()
+                .map(|deleted| Change::TaskDeleted {
+                    list_id: new_list_id.clone(),
+                    deleted: deleted.clone(),
+                })
+                .collect());
+        }
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn updated(list_id: &str, task_id: &str) -> Change {
+        Change::TaskUpdated {
+            list_id: list_id.to_string(),
+            task_id: task_id.to_string(),
+            title: "title".to_string(),
+            before: Map::new(),
+        }
+    }
+
+    #[test]
+    fn recreated_ids_are_renamed_in_older_entries() {
+        let mut history = History {
+            entries: vec![Entry {
+                command: "completed".to_string(),
+                recorded_at: Utc::now(),
+                changes: vec![updated("list", "old-task"), updated("other", "task")],
+            }],
+        };
+        history.rename_task("old-task", "new-task");
+        history.rename_list("list", "new-list");
+
+        let ids: Vec<(&str, &str)> = history.entries[0]
+            .changes
+            .iter()
+            .map(|change| match change {
+                Change::TaskUpdated {
+                    list_id, task_id, ..
+                } => (list_id.as_str(), task_id.as_str()),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(ids, vec![("new-list", "new-task"), ("other", "task")]);
+    }
+}
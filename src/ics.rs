@@ -0,0 +1,727 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! iCalendar (RFC 5545) VTODOs for `tdi export` and `tdi import`, to move
+//! tasks to and from CalDAV based apps. A task is a VTODO with its title as
+//! SUMMARY, its note as DESCRIPTION, DUE, STATUS, PRIORITY, CATEGORIES,
+//! its recurrence as RRULE and its reminder as a VALARM.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::backup::{Backup, BackupList, BackupTask};
+use crate::dates::{self, DateTimeTimeZone};
+use crate::tasksv2::TodoTask;
+
+const DAYS: [(&str, &str); 7] = [
+    ("MO", "monday"),
+    ("TU", "tuesday"),
+    ("WE", "wednesday"),
+    ("TH", "thursday"),
+    ("FR", "friday"),
+    ("SA", "saturday"),
+    ("SU", "sunday"),
+];
+
+/// The `index` of relative Graph patterns and the BYDAY prefix for it.
+const INDICES: [(&str, i32); 5] = [
+    ("first", 1),
+    ("second", 2),
+    ("third", 3),
+    ("fourth", 4),
+    ("last", -1),
+];
+
+const STATUSES: [(&str, &str); 4] = [
+    ("notStarted", "NEEDS-ACTION"),
+    ("inProgress", "IN-PROCESS"),
+    ("completed", "COMPLETED"),
+    ("deferred", "CANCELLED"),
+];
+
+/// A `patternedRecurrence` of Graph.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Recurrence {
+    pattern: Pattern,
+    range: Range,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Pattern {
+    #[serde(rename = "type")]
+    kind: String,
+    interval: u32,
+    month: u32,
+    day_of_month: u32,
+    days_of_week: Vec<String>,
+    first_day_of_week: String,
+    index: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Range {
+    #[serde(rename = "type")]
+    kind: String,
+    start_date: String,
+    end_date: String,
+    number_of_occurrences: u32,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Splits on commas that are not escaped.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            _ => {
+                escaped = c == '\\' && !escaped;
+                items.last_mut().unwrap().push(c);
+            }
+        }
+    }
+    items
+        .iter()
+        .map(|item| unescape(item))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Breaks a content line into lines of at most 75 octets.
+fn fold(line: &str) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out + "\r\n"
+}
+
+fn utc(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn field_date(task: &TodoTask, field: &str, tz: &Tz) -> Option<DateTime<Tz>> {
+    let date: DateTimeTimeZone = serde_json::from_value(task.extra.get(field)?.clone()).ok()?;
+    date.in_zone(tz).ok()
+}
+
+/// A `dateTimeTimeZone` field in the zone it was written in.
+fn own_zone_date(task: &TodoTask, field: &str) -> Option<DateTime<Tz>> {
+    let date: DateTimeTimeZone = serde_json::from_value(task.extra.get(field)?.clone()).ok()?;
+    date.in_zone(&dates::resolve_zone(&date.time_zone).ok()?)
+        .ok()
+}
+
+fn rrule(recurrence: &Recurrence) -> Option<String> {
+    let pattern = &recurrence.pattern;
+    let byday = |prefix: String| {
+        let days: Vec<String> = pattern
+            .days_of_week
+            .iter()
+            .filter_map(|day| DAYS.iter().find(|(_, name)| name == day))
+            .map(|(code, _)| format!("{}{}", prefix, code))
+            .collect();
+        format!("BYDAY={}", days.join(","))
+    };
+    let index = INDICES
+        .iter()
+        .find(|(name, _)| *name == pattern.index)
+        .map(|(_, n)| n.to_string())
+        .unwrap_or_default();
+
+    let mut parts = match pattern.kind.as_str() {
+        "daily" => vec!["FREQ=DAILY".to_string()],
+        "weekly" => vec!["FREQ=WEEKLY".to_string(), byday(String::new())],
+        "absoluteMonthly" => vec![
+            "FREQ=MONTHLY".to_string(),
+            format!("BYMONTHDAY={}", pattern.day_of_month),
+        ],
+        "relativeMonthly" => vec!["FREQ=MONTHLY".to_string(), byday(index)],
+        "absoluteYearly" => vec![
+            "FREQ=YEARLY".to_string(),
+            format!("BYMONTH={}", pattern.month),
+            format!("BYMONTHDAY={}", pattern.day_of_month),
+        ],
+        "relativeYearly" => vec![
+            "FREQ=YEARLY".to_string(),
+            format!("BYMONTH={}", pattern.month),
+            byday(index),
+        ],
+        _ => return None,
+    };
+    if pattern.interval > 1 {
+        parts.push(format!("INTERVAL={}", pattern.interval));
+    }
+    match recurrence.range.kind.as_str() {
+        "endDate" => parts.push(format!(
+            "UNTIL={}",
+            recurrence.range.end_date.replace('-', "")
+        )),
+        "numbered" => parts.push(format!("COUNT={}", recurrence.range.number_of_occurrences)),
+        _ => {}
+    }
+    Some(parts.join(";"))
+}
+
+/// A task as a VTODO, in content lines.
+fn format_task(task: &TodoTask, stamp: &DateTime<Utc>) -> Vec<String> {
+    let mut lines = vec!["BEGIN:VTODO".to_string()];
+    let uid = match task.id.is_empty() {
+        true => format!("{}@tdi", stamp.timestamp_nanos_opt().unwrap_or_default()),
+        false => format!("{}@tdi", task.id),
+    };
+    lines.push(format!("UID:{}", uid));
+    lines.push(format!("DTSTAMP:{}", utc(stamp)));
+    if let Some(created) = task.extra.get("createdDateTime").and_then(Value::as_str) {
+        if let Ok(created) = created.parse::<DateTime<Utc>>() {
+            lines.push(format!("CREATED:{}", utc(&created)));
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape(&task.title)));
+    let note = task
+        .extra
+        .get("body")
+        .and_then(|body| body["content"].as_str())
+        .unwrap_or_default();
+    if !note.trim().is_empty() {
+        lines.push(format!("DESCRIPTION:{}", escape(note)));
+    }
+
+    let status = STATUSES
+        .iter()
+        .find(|(name, _)| *name == task.status)
+        .map(|(_, status)| *status)
+        .unwrap_or("NEEDS-ACTION");
+    lines.push(format!("STATUS:{}", status));
+    if let Some(done) = field_date(task, "completedDateTime", &Tz::UTC) {
+        lines.push(format!("COMPLETED:{}", utc(&done.with_timezone(&Utc))));
+    }
+    let priority = match task.importance.as_str() {
+        "high" => 1,
+        "low" => 9,
+        _ => 5,
+    };
+    lines.push(format!("PRIORITY:{}", priority));
+
+    if let Some(categories) = task.extra.get("categories").and_then(Value::as_array) {
+        let names: Vec<String> = categories
+            .iter()
+            .filter_map(Value::as_str)
+            .map(escape)
+            .collect();
+        if !names.is_empty() {
+            lines.push(format!("CATEGORIES:{}", names.join(",")));
+        }
+    }
+
+    // A due day is at midnight in the zone it was written in, anything else
+    // is a time.
+    if let Some(due) = own_zone_date(task, "dueDateTime") {
+        match due.time() == NaiveTime::MIN {
+            true => lines.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d"))),
+            false => lines.push(format!("DUE:{}", utc(&due.with_timezone(&Utc)))),
+        }
+    }
+    let recurrence = task
+        .extra
+        .get("recurrence")
+        .and_then(|recurrence| serde_json::from_value::<Recurrence>(recurrence.clone()).ok());
+    if let Some(rule) = recurrence.as_ref().and_then(rrule) {
+        lines.push(format!("RRULE:{}", rule));
+    }
+
+    if task.extra.get("isReminderOn") == Some(&json!(true)) {
+        if let Some(reminder) = field_date(task, "reminderDateTime", &Tz::UTC) {
+            lines.extend([
+                "BEGIN:VALARM".to_string(),
+                "ACTION:DISPLAY".to_string(),
+                format!("DESCRIPTION:{}", escape(&task.title)),
+                format!(
+                    "TRIGGER;VALUE=DATE-TIME:{}",
+                    utc(&reminder.with_timezone(&Utc))
+                ),
+                "END:VALARM".to_string(),
+            ]);
+        }
+    }
+    lines.push("END:VTODO".to_string());
+    lines
+}
+
+/// Every task of the backup as a VTODO of one calendar.
+pub fn format(backup: &Backup) -> String {
+    let stamp = Utc::now();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Microsoft//tdi//EN".to_string(),
+    ];
+    for task in backup.lists.iter().flat_map(|list| &list.tasks) {
+        lines.extend(format_task(&task.task, &stamp));
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// A content line: name, parameters and value.
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // The value starts at the first colon outside a quoted parameter.
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(i),
+            _ => None,
+        })?;
+        let mut head = line[..colon].split(';');
+        let name = head.next()?.to_ascii_uppercase();
+        let params = head
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.to_ascii_uppercase(),
+                    value.trim_matches('"').to_string(),
+                )
+            })
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A DATE or DATE-TIME value: UTC, in its TZID, or floating in `tz`.
+    fn date_time(&self, tz: &Tz) -> Result<DateTime<Tz>> {
+        let value = self.value.trim();
+        if let Some(utc) = value.strip_suffix('Z') {
+            let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+                .map_err(|_| anyhow!("invalid date {} of {}", value, self.name))?;
+            return Ok(time.and_utc().with_timezone(tz));
+        }
+        let zone = match self.param("TZID") {
+            Some(tzid) => dates::resolve_zone(tzid)?,
+            None => *tz,
+        };
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .or_else(|_| {
+                NaiveDate::parse_from_str(value, "%Y%m%d")
+                    .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+            })
+            .map_err(|_| anyhow!("invalid date {} of {}", value, self.name))?;
+        let local = dates::parse_date(&time.format("%Y-%m-%d %H:%M").to_string(), &zone)?;
+        Ok(local.with_timezone(tz))
+    }
+}
+
+/// Joins folded lines back into content lines.
+fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in input.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A duration such as "-PT15M" or "P1D".
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (sign, rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total += match c {
+                    'W' => Duration::weeks(n),
+                    'D' => Duration::days(n),
+                    'H' => Duration::hours(n),
+                    'M' => Duration::minutes(n),
+                    _ => Duration::seconds(n),
+                };
+            }
+            _ => return None,
+        }
+    }
+    Some(total * sign)
+}
+
+/// The Graph recurrence for an RRULE of a task due on `due`.
+fn parse_rrule(rule: &str, due: NaiveDate) -> Option<Recurrence> {
+    let parts: Vec<(&str, &str)> = rule.split(';').filter_map(|p| p.split_once('=')).collect();
+    let get = |key: &str| {
+        parts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| *value)
+    };
+    let days: Vec<(i32, String)> = get("BYDAY")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|day| day.len() >= 2)
+        .filter_map(|day| {
+            let (index, code) = day.split_at(day.len() - 2);
+            let name = DAYS.iter().find(|(c, _)| *c == code)?.1.to_string();
+            Some((index.parse().unwrap_or(0), name))
+        })
+        .collect();
+    let index = days
+        .iter()
+        .map(|(index, _)| *index)
+        .find(|index| *index != 0);
+    let days_of_week = days.into_iter().map(|(_, name)| name).collect();
+    let day_of_month = get("BYMONTHDAY")
+        .and_then(|day| day.parse().ok())
+        .unwrap_or(due.day());
+    let month = get("BYMONTH")
+        .and_then(|month| month.parse().ok())
+        .unwrap_or(due.month());
+
+    let mut pattern = Pattern {
+        interval: get("INTERVAL").and_then(|n| n.parse().ok()).unwrap_or(1),
+        first_day_of_week: "sunday".to_string(),
+        index: "first".to_string(),
+        ..Pattern::default()
+    };
+    if let Some(index) = index {
+        pattern.index = INDICES.iter().find(|(_, n)| *n == index)?.0.to_string();
+    }
+    match get("FREQ")?.to_ascii_uppercase().as_str() {
+        "DAILY" => pattern.kind = "daily".to_string(),
+        "WEEKLY" => {
+            pattern.kind = "weekly".to_string();
+            pattern.days_of_week = days_of_week;
+            if pattern.days_of_week.is_empty() {
+                let weekday = due.weekday().num_days_from_monday() as usize;
+                pattern.days_of_week = vec![DAYS[weekday].1.to_string()];
+            }
+        }
+        "MONTHLY" if index.is_some() => {
+            pattern.kind = "relativeMonthly".to_string();
+            pattern.days_of_week = days_of_week;
+        }
+        "MONTHLY" => {
+            pattern.kind = "absoluteMonthly".to_string();
+            pattern.day_of_month = day_of_month;
+        }
+        "YEARLY" if index.is_some() => {
+            pattern.kind = "relativeYearly".to_string();
+            pattern.month = month;
+            pattern.days_of_week = days_of_week;
+        }
+        "YEARLY" => {
+            pattern.kind = "absoluteYearly".to_string();
+            pattern.month = month;
+            pattern.day_of_month = day_of_month;
+        }
+        _ => return None,
+    }
+
+    let mut range = Range {
+        kind: "noEnd".to_string(),
+        start_date: due.format("%Y-%m-%d").to_string(),
+        end_date: "0001-01-01".to_string(),
+        number_of_occurrences: 0,
+    };
+    if let Some(until) = get("UNTIL").and_then(|until| until.get(..8)) {
+        let until = NaiveDate::parse_from_str(until, "%Y%m%d").ok()?;
+        range.kind = "endDate".to_string();
+        range.end_date = until.format("%Y-%m-%d").to_string();
+    } else if let Some(count) = get("COUNT").and_then(|count| count.parse().ok()) {
+        range.kind = "numbered".to_string();
+        range.number_of_occurrences = count;
+    }
+    Some(Recurrence { pattern, range })
+}
+
+/// The task a VTODO describes, from its properties and the TRIGGERs of its
+/// alarms.
+fn parse_todo(properties: &[Property], triggers: &[Property], tz: &Tz) -> Result<TodoTask> {
+    let get = |name: &str| properties.iter().find(|property| property.name == name);
+    let title = get("SUMMARY")
+        .map(|summary| unescape(&summary.value))
+        .unwrap_or_default();
+    let mut fields = Map::new();
+
+    if let Some(description) = get("DESCRIPTION") {
+        fields.insert(
+            "body".to_string(),
+            json!({ "content": unescape(&description.value), "contentType": "text" }),
+        );
+    }
+    let status = get("STATUS")
+        .and_then(|status| {
+            STATUSES
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(status.value.trim()))
+        })
+        .map(|(status, _)| *status)
+        .unwrap_or(if get("COMPLETED").is_some() {
+            "completed"
+        } else {
+            "notStarted"
+        });
+    fields.insert("status".to_string(), json!(status));
+    if let Some(completed) = get("COMPLETED") {
+        let completed = completed.date_time(tz)?;
+        fields.insert(
+            "completedDateTime".to_string(),
+            json!(DateTimeTimeZone::from_date_time(&completed)),
+        );
+    }
+    let importance = match get("PRIORITY").and_then(|p| p.value.trim().parse::<u8>().ok()) {
+        Some(1..=4) => "high",
+        Some(6..=9) => "low",
+        _ => "normal",
+    };
+    fields.insert("importance".to_string(), json!(importance));
+
+    let categories: Vec<String> = properties
+        .iter()
+        .filter(|property| property.name == "CATEGORIES")
+        .flat_map(|property| split_list(&property.value))
+        .collect();
+    if !categories.is_empty() {
+        fields.insert("categories".to_string(), json!(categories));
+    }
+
+    let due = get("DUE").map(|due| due.date_time(tz)).transpose()?;
+    if let Some(due) = &due {
+        fields.insert("dueDateTime".to_string(), json!(DateTimeTimeZone::day(due)));
+    }
+    if let Some(rule) = get("RRULE") {
+        match due
+            .as_ref()
+            .and_then(|due| parse_rrule(&rule.value, due.date_naive()))
+        {
+            Some(recurrence) => {
+                fields.insert("recurrence".to_string(), json!(recurrence));
+            }
+            None => eprintln!(
+                "tdi: left out the recurrence {} of {}, To Do repeats tasks with a due date daily, weekly, monthly or yearly",
+                rule.value, title
+            ),
+        }
+    }
+
+    let start = get("DTSTART")
+        .map(|start| start.date_time(tz))
+        .transpose()?;
+    let reminder = triggers.iter().find_map(|trigger| {
+        if trigger.param("VALUE") == Some("DATE-TIME") || trigger.value.ends_with('Z') {
+            return trigger.date_time(tz).ok();
+        }
+        let base = match trigger.param("RELATED") {
+            Some("END") => due.or(start),
+            _ => start.or(due),
+        }?;
+        Some(base + parse_duration(trigger.value.trim())?)
+    });
+    if let Some(reminder) = reminder {
+        fields.insert(
+            "reminderDateTime".to_string(),
+            json!(DateTimeTimeZone::from_date_time(&reminder)),
+        );
+        fields.insert("isReminderOn".to_string(), json!(true));
+    }
+    Ok(TodoTask::local("", &title).with_changes(&fields))
+}
+
+/// Reads the VTODOs of a calendar into `list`; events and journals are
+/// left out.
+pub fn parse(input: &str, mut list: BackupList, tz: &Tz) -> Result<Backup> {
+    let mut components: Vec<String> = Vec::new();
+    let mut properties = Vec::new();
+    let mut triggers = Vec::new();
+    for line in unfold(input) {
+        let Some(property) = Property::parse(&line) else {
+            continue;
+        };
+        match property.name.as_str() {
+            "BEGIN" => {
+                components.push(property.value.trim().to_ascii_uppercase());
+                if components.last().map(String::as_str) == Some("VTODO") {
+                    properties.clear();
+                    triggers.clear();
+                }
+            }
+            "END" => {
+                if components.pop().as_deref() == Some("VTODO") {
+                    let task = parse_todo(&properties, &triggers, tz)?;
+                    if task.title.is_empty() {
+                        eprintln!("tdi: left out a task without a SUMMARY");
+                    } else {
+                        list.tasks.push(BackupTask::new(task));
+                    }
+                }
+            }
+            _ => match components.last().map(String::as_str) {
+                Some("VTODO") => properties.push(property),
+                Some("VALARM") if property.name == "TRIGGER" => triggers.push(property),
+                _ => {}
+            },
+        }
+    }
+    Ok(Backup::new(vec![list]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list() -> BackupList {
+        BackupList {
+            display_name: "Tasks".to_string(),
+            wellknown_list_name: String::new(),
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_tasks() {
+        let tz = Tz::Europe__Berlin;
+        let task: TodoTask = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Water the plants; all of them",
+            "status": "inProgress",
+            "importance": "high",
+            "body": { "content": "Start with the ferns,\nthen the cacti", "contentType": "text" },
+            "categories": ["Home", "Garden, front"],
+            "dueDateTime": { "dateTime": "2024-03-10T00:00:00", "timeZone": "W. Europe Standard Time" },
+            "isReminderOn": true,
+            "reminderDateTime": { "dateTime": "2024-03-09T18:30:00", "timeZone": "UTC" },
+            "recurrence": {
+                "pattern": {
+                    "type": "relativeMonthly", "interval": 2, "month": 0, "dayOfMonth": 0,
+                    "daysOfWeek": ["sunday"], "firstDayOfWeek": "sunday", "index": "second",
+                },
+                "range": {
+                    "type": "numbered", "startDate": "2024-03-10",
+                    "endDate": "0001-01-01", "numberOfOccurrences": 6,
+                },
+            },
+        }))
+        .unwrap();
+        let mut backup = Backup::new(vec![list()]);
+        backup.lists[0].tasks.push(BackupTask::new(task.clone()));
+
+        let ics = format(&backup);
+        assert!(ics.contains("DUE;VALUE=DATE:20240310\r\n"));
+        assert!(ics.contains("RRULE:FREQ=MONTHLY;BYDAY=2SU;INTERVAL=2;COUNT=6\r\n"));
+        assert!(ics.contains("TRIGGER;VALUE=DATE-TIME:20240309T183000Z\r\n"));
+        assert!(ics.lines().all(|line| line.len() <= 75));
+
+        let parsed = &parse(&ics, list(), &tz).unwrap().lists[0].tasks[0].task;
+        assert_eq!(parsed.title, task.title);
+        assert_eq!(parsed.status, "inProgress");
+        assert_eq!(parsed.importance, "high");
+        for field in ["body", "categories", "dueDateTime", "recurrence"] {
+            assert_eq!(parsed.extra[field], task.extra[field], "{}", field);
+        }
+        assert_eq!(
+            parsed.extra["reminderDateTime"],
+            json!({ "dateTime": "2024-03-09T19:30:00", "timeZone": "W. Europe Standard Time" })
+        );
+    }
+
+    #[test]
+    fn reads_todos_of_other_apps() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nSUMMARY:Not a task\r\nEND:VEVENT\r\n\
+            BEGIN:VTODO\r\n\
+            SUMMARY:Renew the pass\r\n port\r\n\
+            DUE;TZID=America/New_York:20240310T090000\r\n\
+            PRIORITY:7\r\n\
+            STATUS:COMPLETED\r\n\
+            RRULE:FREQ=YEARLY\r\n\
+            BEGIN:VALARM\r\nTRIGGER;RELATED=END:-PT1H30M\r\nEND:VALARM\r\n\
+            END:VTODO\r\n\
+            END:VCALENDAR\r\n";
+        let backup = parse(ics, list(), &Tz::UTC).unwrap();
+        assert_eq!(backup.lists[0].tasks.len(), 1);
+        let task = &backup.lists[0].tasks[0].task;
+        assert_eq!(task.title, "Renew the passport");
+        assert_eq!(task.importance, "low");
+        assert_eq!(task.status, "completed");
+        // Due that day; reminded an hour and a half before 09:00 in New
+        // York, after the clocks went forward.
+        assert_eq!(
+            task.extra["dueDateTime"],
+            json!({ "dateTime": "2024-03-10T00:00:00", "timeZone": "UTC" })
+        );
+        assert_eq!(
+            task.extra["reminderDateTime"],
+            json!({ "dateTime": "2024-03-10T11:30:00", "timeZone": "UTC" })
+        );
+        assert_eq!(
+            task.extra["recurrence"]["pattern"]["type"],
+            "absoluteYearly"
+        );
+        assert_eq!(task.extra["recurrence"]["pattern"]["month"], 3);
+        assert_eq!(task.extra["recurrence"]["pattern"]["dayOfMonth"], 10);
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Changes made while offline. They are applied to the offline store right
+//! away and replayed against Graph by the next `tdi sync`; a change whose
+//! task was also edited remotely is set aside as a conflict instead.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::batch::BatchRequest;
+use crate::cache::Store;
+use crate::conflicts::{Conflict, Conflicts};
+use crate::graph::GraphClient;
+use crate::helper::get_cache_dir;
+use crate::tasksv2::TodoTask;
+
+/// Prefix of the ids given to tasks created offline, until they are pushed.
+pub const LOCAL_ID_PREFIX: &str = "local-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OperationKind {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub list_id: String,
+    pub task_id: String,
+    pub title: String,
+    /// The fields to create the task with, or to change.
+    pub changes: Map<String, Value>,
+    /// The task as it was before the first offline change, to tell which
+    /// fields were edited remotely in the meantime.
+    pub base: Option<TodoTask>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl Operation {
+    pub fn new(
+        kind: OperationKind,
+        list_id: &str,
+        task_id: &str,
+        title: &str,
+        changes: Map<String, Value>,
+        base: Option<TodoTask>,
+    ) -> Operation {
+        Operation {
+            kind,
+            list_id: list_id.to_string(),
+            task_id: task_id.to_string(),
+            title: title.to_string(),
+            changes,
+            base,
+            recorded_at: Utc::now(),
+        }
+    }
+
+    fn task_url(&self) -> String {
+        format!("/me/todo/lists/{}/tasks/{}", self.list_id, self.task_id)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    pub operations: Vec<Operation>,
+}
+
+/// What replaying the journal did, for the `tdi sync` report.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Replay {
+    pub pushed: Vec<String>,
+    pub conflicts: Vec<String>,
+    /// The title of each change that stays queued, with why it failed.
+    pub failed: Vec<String>,
+}
+
+impl Journal {
+    fn path() -> String {
+        get_cache_dir() + "/journal.json"
+    }
+
+    pub fn load() -> Result<Journal> {
+        match std::fs::File::open(Journal::path()) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Journal::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(get_cache_dir())?;
+        serde_json::to_writer_pretty(std::fs::File::create(Journal::path())?, self)?;
+        Ok(())
+    }
+
+    /// Adds an operation, folding it into a pending one for the same task so
+    /// that there is at most one operation per task to replay.
+    pub fn record(&mut self, operation: Operation) {
+        let pending = self
+            .operations
+            .iter()
+            .position(|op| op.task_id == operation.task_id);
+        let Some(i) = pending else {
+            self.operations.push(operation);
+            return;
+        };
+
+        let pending = &mut self.operations[i];
+        match (pending.kind, operation.kind) {
+            // The task never reached Graph, so there is nothing to delete.
+            (OperationKind::Create, OperationKind::Delete) => {
+                self.operations.remove(i);
+            }
+            (_, OperationKind::Delete) => {
+                pending.kind = OperationKind::Delete;
+                pending.changes.clear();
+            }
+            (_, _) => {
+                pending.changes.extend(operation.changes);
+                pending.title = operation.title;
+            }
+        }
+    }
+
+    /// Pushes every operation to Graph, keeping those that could not be sent
+    /// and moving those that conflict with remote edits to `conflicts`.
+    pub fn replay(
+        &mut self,
+        client: &GraphClient,
+        store: &mut Store,
+        conflicts: &mut Conflicts,
+    ) -> Result<Replay> {
+        let mut replay = Replay::default();
+        let operations = std::mem::take(&mut self.operations);
+        let (creates, others): (Vec<Operation>, Vec<Operation>) = operations
+            .into_iter()
+            .partition(|op| op.kind == OperationKind::Create);
+
+        let requests = creates
+            .iter()
+            .enumerate()
+            .map(|(i, op)| {
+                BatchRequest::post(
+                    &i.to_string(),
+                    &format!("/me/todo/lists/{}/tasks", op.list_id),
+                    Value::Object(op.changes.clone()),
+                )
+            })
+            .collect();
+        let responses = match client.batch(requests) {
+            Ok(responses) => responses,
+            Err(err) => return Ok(self.requeue(replay, creates.into_iter().chain(others), &err)),
+        };
+        for (op, response) in creates.into_iter().zip(responses) {
+            match response.status {
+                status if (200..300).contains(&status) => {
+                    store.remove_task(&op.list_id, &op.task_id);
+                    replay.pushed.push(op.title);
+                }
+                409 | 412 => {
+                    replay.conflicts.push(op.title.clone());
+                    conflicts.add(Conflict::new(op, None, Vec::new(), &response.error()));
+                }
+                // Anything else, a malformed payload included, cannot be
+                // resolved as a conflict and is tried again.
+                _ => {
+                    replay
+                        .failed
+                        .push(format!("{}: {}", op.title, response.error()));
+                    self.operations.push(op);
+                }
+            }
+        }
+
+        // Fetch the remote state of every changed task to look for conflicts.
+        let requests = others
+            .iter()
+            .enumerate()
+            .map(|(i, op)| BatchRequest::get(&i.to_string(), &op.task_url()))
+            .collect();
+        // A batch that fails as a whole leaves its operations queued, while
+        // what was done before it is kept.
+        let responses = match client.batch(requests) {
+            Ok(responses) => responses,
+            Err(err) => return Ok(self.requeue(replay, others, &err)),
+        };
+        let mut ready = Vec::new();
+        for (op, response) in others.into_iter().zip(responses) {
+            if response.status == 404 {
+                if op.kind == OperationKind::Delete {
+                    replay.pushed.push(op.title);
+                } else {
+                    replay.conflicts.push(op.title.clone());
+                    conflicts.add(Conflict::new(op, None, Vec::new(), "deleted remotely"));
+                }
+                continue;
+            }
+            let remote: TodoTask = match response.json() {
+                Ok(remote) => remote,
+                Err(_) => {
+                    replay
+                        .failed
+                        .push(format!("{}: {}", op.title, response.error()));
+                    self.operations.push(op);
+                    continue;
+                }
+            };
+            let fields = conflicting_fields(&op, &remote);
+            if fields.is_empty() {
+                ready.push((op, remote));
+            } else {
+                replay.conflicts.push(op.title.clone());
+                conflicts.add(Conflict::new(op, Some(remote), fields, "edited remotely"));
+            }
+        }
+
+        let requests = ready
+            .iter()
+            .enumerate()
+            .map(|(i, (op, remote))| {
+                let request = match op.kind {
+                    OperationKind::Delete => BatchRequest::delete(&i.to_string(), &op.task_url()),
+                    _ => BatchRequest::patch(
+                        &i.to_string(),
+                        &op.task_url(),
+                        Value::Object(op.changes.clone()),
+                    ),
+                };
+                match remote.etag() {
+                    Some(etag) => request.header("If-Match", etag),
+                    None => request,
+                }
+            })
+            .collect();
+        let responses = match client.batch(requests) {
+            Ok(responses) => responses,
+            Err(err) => {
+                let ready = ready.into_iter().map(|(op, _)| op);
+                return Ok(self.requeue(replay, ready, &err));
+            }
+        };
+        for ((op, remote), response) in ready.into_iter().zip(responses) {
+            match response.status {
+                status if (200..300).contains(&status) => replay.pushed.push(op.title),
+                // Deleted or edited remotely after it was fetched above.
+                404 if op.kind == OperationKind::Delete => replay.pushed.push(op.title),
+                404 => {
+                    replay.conflicts.push(op.title.clone());
+                    conflicts.add(Conflict::new(op, None, Vec::new(), "deleted remotely"));
+                }
+                409 | 412 => {
+                    replay.conflicts.push(op.title.clone());
+                    let fields = op.changes.keys().cloned().collect();
+                    conflicts.add(Conflict::new(op, Some(remote), fields, "edited remotely"));
+                }
+                _ => {
+                    replay
+                        .failed
+                        .push(format!("{}: {}", op.title, response.error()));
+                    self.operations.push(op);
+                }
+            }
+        }
+
+        Ok(replay)
+    }
+
+    /// Puts back operations that could not be sent, as failed.
+    fn requeue(
+        &mut self,
+        mut replay: Replay,
+        operations: impl IntoIterator<Item = Operation>,
+        err: &anyhow::Error,
+    ) -> Replay {
+        for op in operations {
+            replay.failed.push(format!("{}: {}", op.title, err));
+            self.operations.push(op);
+        }
+        replay
+    }
+}
+
+/// The fields an offline operation would overwrite although they were also
+/// changed remotely since the operation was recorded.
+pub fn conflicting_fields(op: &Operation, remote: &TodoTask) -> Vec<String> {
+    let Some(base) = &op.base else {
+        return Vec::new();
+    };
+    if base.last_modified() == remote.last_modified() {
+        return Vec::new();
+    }
+
+    let base = serde_json::to_value(base).unwrap_or_default();
+    let remote = serde_json::to_value(remote).unwrap_or_default();
+    let changed_remotely = |field: &str| base.get(field) != remote.get(field);
+
+    match op.kind {
+        // Deleting would discard whatever was edited remotely.
+        OperationKind::Delete => {
+            let mut fields: Vec<String> = remote
+                .as_object()
+                .map(|remote| {
+                    remote
+                        .keys()
+                        .filter(|field| !is_metadata(field) && changed_remotely(field))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            fields.sort();
+            fields
+        }
+        _ => op
+            .changes
+            .iter()
+            .filter(|(field, local)| changed_remotely(field) && remote.get(*field) != Some(*local))
+            .map(|(field, _)| field.clone())
+            .collect(),
+    }
+}
+
+fn is_metadata(field: &str) -> bool {
+    field.starts_with('@') || field == "lastModifiedDateTime"
+}
+
+/// Records an offline change: applies it to the store and queues it.
+pub fn record_offline(store: &mut Store, operation: Operation) -> Result<()> {
+    store.apply(&operation);
+    store.save()?;
+
+    let mut journal = Journal::load()?;
+    journal.record(operation);
+    journal.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn task(title: &str, modified: &str) -> TodoTask {
+        serde_json::from_value(json!({
+            "id": "1",
+            "title": title,
+            "status": "notStarted",
+            "importance": "normal",
+            "lastModifiedDateTime": modified,
+        }))
+        .unwrap()
+    }
+
+    fn update(changes: Value, base: TodoTask) -> Operation {
+        Operation::new(
+            OperationKind::Update,
+            "list",
+            "1",
+            "title",
+            changes.as_object().unwrap().clone(),
+            Some(base),
+        )
+    }
+
+    #[test]
+    fn record_folds_updates_and_drops_deleted_creates() {
+        let mut journal = Journal::default();
+        let create = Operation::new(
+            OperationKind::Create,
+            "list",
+            "local-1",
+            "new",
+            Map::new(),
+            None,
+        );
+        journal.record(create.clone());
+        journal.record(Operation {
+            kind: OperationKind::Update,
+            changes: json!({ "status": "completed" })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..create.clone()
+        });
+        assert_eq!(journal.operations.len(), 1);
+        assert_eq!(journal.operations[0].kind, OperationKind::Create);
+        assert_eq!(journal.operations[0].changes["status"], "completed");
+
+        journal.record(Operation {
+            kind: OperationKind::Delete,
+            ..create
+        });
+        assert!(journal.operations.is_empty());
+    }
+
+    #[test]
+    fn no_conflict_when_remote_unchanged() {
+        let base = task("old", "2022-10-01T10:00:00Z");
+        let op = update(json!({ "title": "new" }), base.clone());
+        assert!(conflicting_fields(&op, &base).is_empty());
+    }
+
+    #[test]
+    fn no_conflict_when_remote_changed_other_fields() {
+        let base = task("old", "2022-10-01T10:00:00Z");
+        let mut remote = task("old", "2022-10-02T10:00:00Z");
+        remote.status = "completed".to_string();
+        let op = update(json!({ "title": "new" }), base);
+        assert!(conflicting_fields(&op, &remote).is_empty());
+    }
+
+    #[test]
+    fn conflict_when_both_changed_a_field() {
+        let base = task("old", "2022-10-01T10:00:00Z");
+        let remote = task("remote", "2022-10-02T10:00:00Z");
+        let op = update(json!({ "title": "local" }), base);
+        assert_eq!(conflicting_fields(&op, &remote), vec!["title"]);
+    }
+}
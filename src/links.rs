@@ -0,0 +1,145 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Linked resources: the web pages a task points to, such as the pull
+//! request or work item it is about.
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::Method;
+use serde_json::json;
+use url::Url;
+
+use crate::graph::GraphClient;
+use crate::tasksv2::{get_cached_task, LinkedResource, TaskIdCache};
+
+fn links_url(task: &TaskIdCache) -> String {
+    format!(
+        "/me/todo/lists/{}/tasks/{}/linkedResources",
+        task.list_id, task.id
+    )
+}
+
+/// How a link is shown: its name and where it goes.
+pub fn describe(link: &LinkedResource) -> String {
+    let name = link
+        .display_name
+        .as_deref()
+        .or(link.application_name.as_deref())
+        .unwrap_or_default();
+    match (name, link.web_url.as_deref()) {
+        ("", Some(url)) => url.to_string(),
+        (name, Some(url)) if name != url => format!("{} {}", name, url),
+        (name, _) => name.to_string(),
+    }
+}
+
+fn print_links(title: &str, links: &[LinkedResource]) {
+    if links.is_empty() {
+        println!("tdi: {} has no links", title);
+    }
+    for (n, link) in links.iter().enumerate() {
+        println!("{}. {}", n + 1, describe(link));
+    }
+}
+
+/// Links a task to `url`, named `name` or else the URL; without a URL,
+/// shows the links of the task.
+pub fn link(id: &u32, url: &Option<String>, name: &Option<String>) -> Result<()> {
+    let task = get_cached_task(id)?;
+    let client = GraphClient::new()?;
+    let Some(url) = url else {
+        let links: Vec<LinkedResource> = client.get_all(&links_url(&task))?;
+        print_links(&task.display_name, &links);
+        return Ok(());
+    };
+
+    let parsed = Url::parse(url).map_err(|err| anyhow!("invalid URL {}: {}", url, err))?;
+    let link = LinkedResource {
+        web_url: Some(url.clone()),
+        application_name: Some(parsed.host_str().unwrap_or("tdi").to_string()),
+        display_name: Some(name.clone().unwrap_or_else(|| url.clone())),
+        ..LinkedResource::default()
+    };
+    client.post(&links_url(&task), &json!(link))?;
+    println!("tdi: linked {} to {}", task.display_name, url);
+    Ok(())
+}
+
+/// Removes a link of a task, by its URL or its number in `tdi link`; it can
+/// be left out when there is a single one.
+pub fn unlink(id: &u32, which: &Option<String>) -> Result<()> {
+    let task = get_cached_task(id)?;
+    let client = GraphClient::new()?;
+    let links: Vec<LinkedResource> = client.get_all(&links_url(&task))?;
+    let link = match which {
+        None if links.len() == 1 => &links[0],
+        None if links.is_empty() => bail!("{} has no links", task.display_name),
+        None => {
+            print_links(&task.display_name, &links);
+            bail!(
+                "{} has several links, give the URL or number of one",
+                task.display_name
+            );
+        }
+        Some(which) => which
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| links.get(n.checked_sub(1)?))
+            .or_else(|| {
+                links
+                    .iter()
+                    .find(|link| link.web_url.as_ref() == Some(which))
+            })
+            .ok_or_else(|| anyhow!("{} has no link {}", task.display_name, which))?,
+    };
+    client.request(
+        Method::DELETE,
+        &format!("{}/{}", links_url(&task), link.id),
+        None,
+    )?;
+    println!(
+        "tdi: unlinked {} from {}",
+        task.display_name,
+        describe(link)
+    );
+    Ok(())
+}
+
+/// Opens the first link of a task with a URL in the browser.
+pub fn open(id: &u32) -> Result<()> {
+    let task = get_cached_task(id)?;
+    let client = GraphClient::new()?;
+    let links: Vec<LinkedResource> = client.get_all(&links_url(&task))?;
+    let url = links
+        .iter()
+        .find_map(|link| link.web_url.as_deref())
+        .ok_or_else(|| anyhow!("{} has no link to open", task.display_name))?;
+    if webbrowser::open(url).is_err() {
+        bail!("unable to open a browser, the link is {}", url);
+    }
+    println!("tdi: opened {}", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_links() {
+        let link = |name: Option<&str>, app: Option<&str>, url: Option<&str>| LinkedResource {
+            display_name: name.map(str::to_string),
+            application_name: app.map(str::to_string),
+            web_url: url.map(str::to_string),
+            ..LinkedResource::default()
+        };
+        let pr = "https://github.com/org/repo/pull/7";
+        assert_eq!(
+            describe(&link(Some("PR 7"), None, Some(pr))),
+            format!("PR 7 {}", pr)
+        );
+        assert_eq!(describe(&link(Some(pr), Some("github.com"), Some(pr))), pr);
+        assert_eq!(describe(&link(None, Some("Outlook"), None)), "Outlook");
+        assert_eq!(describe(&link(None, None, Some(pr))), pr);
+    }
+}
@@ -1,26 +1,25 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
+use anyhow::Result;
 use cli_table::{print_stdout, Table, WithTitle};
-use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::io::Result;
 
-use crate::auth::read_access_token;
+use crate::batch::BatchRequest;
+use crate::cache::{self, Store};
+use crate::graph::{Collection, GraphClient};
+use crate::helper::get_config_dir;
+use crate::history::{Change, DeletedTask, History};
+use crate::tasksv2::{ChecklistItem, TodoTask};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct TodoList {
-    display_name: String,
-    is_owner: bool,
-    is_shared: bool,
-    id: String,
-    wellknown_list_name: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct TodoListResponse {
-    value: Vec<TodoList>,
+pub struct TodoList {
+    pub display_name: String,
+    pub is_owner: bool,
+    pub is_shared: bool,
+    pub id: String,
+    pub wellknown_list_name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Table)]
@@ -34,38 +33,26 @@ struct TodoListIdCache {
     id: String,
 }
 
-pub fn get_todo_lists(output_format: &str) -> Result<()> {
-    let token = read_access_token();
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
-
-    let _res = rt.block_on(async { get_todo_lists_async(output_format, token).await });
-
-    Ok(())
-}
-
-async fn get_todo_lists_async(output_format: &str, token: String) -> Result<()> {
-    let client = reqwest::Client::new();
-
-    let response = client
-        .get("https://graph.microsoft.com/v1.0/me/todo/lists")
-        .bearer_auth(token)
-        .send()
-        .await
-        .unwrap();
-
-    //dbg!("response: {:?}", &response);
-    let body = response.text().await.unwrap();
-    //dbg!("body: {:?}", &body);
-    let todo_lists_response: TodoListResponse = serde_json::from_str(&body).unwrap();
-    //dbg!("todo_lists: {:?}", &todo_lists_response);
+pub fn get_todo_lists(output_format: &str, offline: &bool) -> Result<()> {
+    let fetched = cache::online(*offline, || fetch_todo_lists(&GraphClient::new()?))?;
+    let todo_lists = match fetched {
+        Some(todo_lists) => {
+            let mut store = Store::load()?;
+            store.set_lists(todo_lists.clone());
+            store.save()?;
+            todo_lists
+        }
+        None => {
+            let store = Store::load_offline()?;
+            cache::print_age(store.synced_at);
+            store.lists.into_iter().map(|stored| stored.list).collect()
+        }
+    };
 
     let mut list_counter = 0i16;
     let mut todo_list_id_cache: Vec<TodoListIdCache> = Vec::new();
 
-    for todo_list in &todo_lists_response.value {
+    for todo_list in &todo_lists {
         list_counter += 1;
         todo_list_id_cache.push(TodoListIdCache {
             display_name: todo_list.display_name.clone(),
@@ -84,31 +71,91 @@ async fn get_todo_lists_async(output_format: &str, token: String) -> Result<()>
     // We need to cache the list ids so we can use them later
     // to use the simpler 0-n ids. The real ones are unmanageable:
     // AQMkADAwATMwMAItYjBkZPPtZWQ0ZS0wWEItMDAKAC4AAANkdZgpr8LxTL4LkrPqypbXAQBPdIWRHCTMQpY9NGnpa9LvAAACARIAAAA=
-    let _result = serde_json::to_writer_pretty(
-        std::fs::File::create(get_config_dir() + "/lists_cache.json").unwrap(),
+    std::fs::create_dir_all(get_config_dir())?;
+    serde_json::to_writer_pretty(
+        std::fs::File::create(get_config_dir() + "/lists_cache.json")?,
         &todo_list_id_cache,
-    );
+    )?;
 
     Ok(())
 }
 
-fn as_table(todo_list_id_cache: &Vec<TodoListIdCache>) {
+pub fn fetch_todo_lists(client: &GraphClient) -> Result<Vec<TodoList>> {
+    client.get_all("/me/todo/lists")
+}
+
+/// Deletes a list with all its tasks, keeping them in the history so that
+/// `tdi undo` can recreate them.
+pub fn delete_list(id: &u16) -> Result<()> {
+    let real_id = get_real_list_id(id)?;
+    let client = GraphClient::new()?;
+    let url = format!("/me/todo/lists/{}", real_id);
+
+    let list: TodoList = client.get(&url)?;
+    let tasks: Vec<TodoTask> = client.get_all(&format!("{}/tasks", url))?;
+    let requests = tasks
+        .iter()
+        .map(|task| {
+            BatchRequest::get(
+                &task.id,
+                &format!("{}/tasks/{}/checklistItems", url, task.id),
+            )
+        })
+        .collect();
+    let mut deleted = Vec::new();
+    for (task, response) in tasks.into_iter().zip(client.batch(requests)?) {
+        let items: Collection<ChecklistItem> = response.json()?;
+        deleted.push(DeletedTask {
+            task,
+            checklist_items: items.value,
+        });
+    }
+
+    client.request(reqwest::Method::DELETE, &url, None)?;
+    println!(
+        "tdi: deleted list {} and its {} tasks",
+        list.display_name,
+        deleted.len()
+    );
+
+    let mut store = Store::load()?;
+    store.lists.retain(|stored| stored.list.id != real_id);
+    store.save()?;
+
+    History::record(
+        "deleted list",
+        vec![Change::ListDeleted {
+            list,
+            tasks: deleted,
+        }],
+    )
+}
+
+/// Resolves the short id shown by `tdi lists` to the Graph list id.
+pub fn get_real_list_id(easy_id: &u16) -> Result<String> {
+    let todo_list_id_cache: Vec<TodoListIdCache> = serde_json::from_reader(
+        std::fs::File::open(get_config_dir() + "/lists_cache.json")
+            .map_err(|_| anyhow::anyhow!("no cached list ids, run `tdi lists` first"))?,
+    )?;
+
+    todo_list_id_cache
+        .into_iter()
+        .find(|todo_list| todo_list.easy_id == easy_id.to_string())
+        .map(|todo_list| todo_list.id)
+        .ok_or_else(|| anyhow::anyhow!("no list with id {}, run `tdi lists` to refresh", easy_id))
+}
+
+fn as_table(todo_list_id_cache: &[TodoListIdCache]) {
     print_stdout(todo_list_id_cache.with_title()).unwrap();
 }
 
-fn as_json(todo_list_id_cache: &Vec<TodoListIdCache>) {
+fn as_json(todo_list_id_cache: &[TodoListIdCache]) {
     let json = serde_json::to_string(&todo_list_id_cache);
     println!("{}", json.unwrap());
 }
 
-fn as_lines(todo_list_id_cache: &Vec<TodoListIdCache>) {
+fn as_lines(todo_list_id_cache: &[TodoListIdCache]) {
     for todo_list in todo_list_id_cache {
         println!("[{}] {}", todo_list.easy_id, todo_list.display_name);
     }
 }
-
-fn get_config_dir() -> String {
-    let proj_dirs = ProjectDirs::from("com", "microsofthackathons", "tdi");
-    let config_dir = proj_dirs.unwrap().config_dir().to_path_buf();
-    config_dir.into_os_string().into_string().unwrap()
-}
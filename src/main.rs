@@ -2,34 +2,152 @@
 // Licensed under the MIT License.
 
 mod auth;
+mod backup;
+mod batch;
+mod cache;
 mod cli;
+mod conflicts;
+mod dates;
+mod graph;
+mod helper;
+mod history;
+mod ics;
+mod journal;
+mod links;
 mod lists;
+mod markdown;
+mod mirror;
+mod profiles;
+mod scan;
+mod settings;
+mod spreadsheet;
+mod sync;
 mod tasks;
 mod tasksv2;
+mod taskwarrior;
+mod todotxt;
+mod tokens;
+mod ui;
 mod user;
 
 use clap::Parser;
 
-use cli::{Cli, Commands::*};
+use cli::{
+    AuthCommands, Cli, Commands::*, ConfigCommands, ConflictCommands, ListCommands, ProfileCommands,
+};
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    profiles::migrate_legacy_files()?;
+    let profile = profiles::resolve_profile(&cli.profile)?;
+    helper::set_profile(&profile);
+    dates::set_time_zone(&cli.tz)?;
+
     match &cli.command {
-        Some(Login {}) => auth::login(),
-        Some(Logout {}) => auth::logout(),
+        Some(Login {
+            encrypt,
+            manual,
+            port,
+            timeout,
+        }) => auth::login(encrypt, manual, port, timeout),
+        Some(Logout { all, revoke, force }) => auth::logout(all, revoke, force),
+        Some(Auth { action }) => match action {
+            AuthCommands::Status {
+                refresh,
+                output_format,
+            } => auth::status(refresh, output_format),
+            AuthCommands::Encrypt => auth::encrypt_tokens(true),
+            AuthCommands::Decrypt => auth::encrypt_tokens(false),
+        },
         Some(Me { output_format }) => user::show_me(output_format),
+        Some(Ui) => ui::run(&cli.offline),
         Some(Show { json }) => tasks::show_tasks(json),
-        Some(Add { task }) => tasks::add_task(task),
-        Some(Complete { id }) => tasks::complete_task(id),
-        Some(Reopen { id }) => tasks::reopen_task(id),
-        Some(Delete { id }) => tasks::delete_task(id),
-        Some(Lists { output_format }) => lists::get_todo_lists(output_format),
+        Some(Add {
+            task,
+            list_id,
+            due,
+            reminder,
+        }) => tasksv2::add_task(task, list_id, due, reminder, &cli.offline),
+        Some(Edit {
+            id,
+            title,
+            importance,
+            due,
+            reminder,
+        }) => tasksv2::edit_task(id, title, importance, due, reminder, &cli.offline),
+        Some(Complete { ids }) => tasksv2::complete_tasks(ids, &cli.offline),
+        Some(Reopen { ids }) => tasksv2::reopen_tasks(ids, &cli.offline),
+        Some(Delete { ids }) => tasksv2::delete_tasks(ids, &cli.offline),
+        Some(Lists {
+            output_format,
+            action,
+        }) => match action {
+            None => lists::get_todo_lists(output_format, &cli.offline),
+            Some(ListCommands::Delete { id }) => lists::delete_list(id),
+        },
+        Some(Move { ids, to }) => tasksv2::move_tasks(ids, to),
+        Some(Link { id, url, name }) => links::link(id, url, name),
+        Some(Unlink { id, link }) => links::unlink(id, link),
+        Some(Open { id }) => links::open(id),
+        Some(Export {
+            all,
+            list_id,
+            format,
+        }) => backup::export(all, list_id, format),
+        Some(Import {
+            file,
+            format,
+            list,
+            dry_run,
+            new_lists,
+        }) => backup::import(file, format, list, dry_run, new_lists),
+        Some(Mirror { dir }) => mirror::mirror(dir),
+        Some(Scan {
+            path,
+            list,
+            dry_run,
+        }) => scan::scan(path, list, dry_run),
+        Some(Undo {
+            list,
+            output_format,
+        }) => {
+            if *list {
+                history::show_history(output_format)
+            } else {
+                history::undo()
+            }
+        }
         Some(Tasks {
             output_format,
             display_all,
             list_id,
-        }) => tasksv2::get_todo_tasks(output_format, display_all, list_id),
+        }) => tasksv2::get_todo_tasks(output_format, display_all, list_id, &cli.offline),
+        Some(Sync { output_format }) => sync::sync(output_format),
+        Some(Conflicts {
+            output_format,
+            action,
+        }) => match action {
+            None => conflicts::list_conflicts(output_format),
+            Some(ConflictCommands::Show { id }) => conflicts::show_conflict(id),
+            Some(ConflictCommands::Resolve { id, keep, fields }) => {
+                conflicts::resolve_conflict(id, keep, fields)
+            }
+        },
+        Some(Config { action }) => match action {
+            None => settings::list_settings("lines"),
+            Some(ConfigCommands::List { output_format }) => settings::list_settings(output_format),
+            Some(ConfigCommands::Get { key }) => settings::get_setting(key),
+            Some(ConfigCommands::Set { key, value }) => settings::set_setting(key, Some(value)),
+            Some(ConfigCommands::Unset { key }) => settings::set_setting(key, None),
+        },
+        Some(Profiles { action }) => match action {
+            ProfileCommands::List { output_format } => {
+                profiles::list_profiles(output_format, &profile)
+            }
+            ProfileCommands::Use { name } => profiles::use_profile(name),
+            ProfileCommands::Remove { name } => profiles::remove_profile(name),
+        },
         Some(Intr) => tasks::interactive(),
         None => {
             println!("Default subcommand");
@@ -0,0 +1,196 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Markdown checklists for `tdi export` and `tdi import`: a heading per
+//! list, a `- [ ]` or `- [x]` item per task and the checklist items of a
+//! task nested under it. Importing keeps to the items with a checkbox, so
+//! notes around them, such as those of a meeting, are left out.
+
+use anyhow::Result;
+
+use crate::backup::{Backup, BackupList, BackupTask};
+use crate::tasksv2::{ChecklistItem, TodoTask};
+
+pub fn checkbox(checked: bool) -> &'static str {
+    match checked {
+        true => "[x]",
+        false => "[ ]",
+    }
+}
+
+/// Every list of the backup as a heading with its tasks.
+pub fn format(backup: &Backup) -> String {
+    let lists: Vec<String> = backup
+        .lists
+        .iter()
+        .map(|list| {
+            let mut out = format!("# {}\n\n", list.display_name);
+            for task in &list.tasks {
+                let done = task.task.status == "completed";
+                out += &format!("- {} {}\n", checkbox(done), task.task.title);
+                for item in &task.checklist_items {
+                    out += &format!("  - {} {}\n", checkbox(item.is_checked), item.display_name);
+                }
+            }
+            out
+        })
+        .collect();
+    lists.join("\n")
+}
+
+/// The text of an ATX heading such as "## Actions".
+fn heading(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    match text.len() < line.len() && (text.is_empty() || text.starts_with(' ')) {
+        true => Some(text.trim()),
+        false => None,
+    }
+}
+
+/// An item with a checkbox: its indentation, whether it is checked and its
+/// text.
+pub fn parse_item(line: &str) -> Option<(usize, bool, &str)> {
+    let text = line.trim_start();
+    let indent = line[..line.len() - text.len()]
+        .chars()
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    let text = text
+        .strip_prefix(['-', '*', '+'])
+        .or_else(|| {
+            let digits = text
+                .find(|c: char| !c.is_ascii_digit())
+                .filter(|n| *n > 0)?;
+            text[digits..].strip_prefix(['.', ')'])
+        })?
+        .strip_prefix(' ')?
+        .trim_start();
+    let checked = match text.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    let title = text[3..].trim();
+    match title.is_empty() {
+        true => None,
+        false => Some((indent, checked, title)),
+    }
+}
+
+/// Reads the items with a checkbox into `list`, or with `by_heading` into
+/// a list named by the heading above them if there is one. Items nested
+/// under another are its checklist items.
+pub fn parse(input: &str, list: BackupList, by_heading: bool) -> Result<Backup> {
+    let mut lists = vec![list];
+    let mut current = 0;
+    // The indentation of the last task, to tell its checklist items.
+    let mut task_indent = None;
+    for line in input.lines() {
+        if let Some(name) = heading(line) {
+            task_indent = None;
+            if !by_heading || name.is_empty() {
+                continue;
+            }
+            current = match lists.iter().position(|l| l.display_name == name) {
+                Some(index) => index,
+                None => {
+                    lists.push(BackupList {
+                        display_name: name.to_string(),
+                        wellknown_list_name: String::new(),
+                        tasks: Vec::new(),
+                    });
+                    lists.len() - 1
+                }
+            };
+            continue;
+        }
+        let Some((indent, checked, title)) = parse_item(line) else {
+            continue;
+        };
+
+        let tasks = &mut lists[current].tasks;
+        match (task_indent, tasks.last_mut()) {
+            (Some(parent), Some(task)) if indent > parent => {
+                task.checklist_items.push(ChecklistItem {
+                    display_name: title.to_string(),
+                    is_checked: checked,
+                    id: String::new(),
+                    extra: Default::default(),
+                });
+            }
+            _ => {
+                let mut task = TodoTask::local("", title);
+                if checked {
+                    task.status = "completed".to_string();
+                }
+                tasks.push(BackupTask::new(task));
+                task_indent = Some(indent);
+            }
+        }
+    }
+    lists.retain(|list| !list.tasks.is_empty());
+    Ok(Backup::new(lists))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default() -> BackupList {
+        BackupList {
+            display_name: "Tasks".to_string(),
+            wellknown_list_name: "defaultList".to_string(),
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_lists() {
+        let markdown = "# Groceries\n\n\
+            - [ ] Milk\n\
+            - [x] Bread\n  \
+              - [x] Whole grain\n  \
+              - [ ] Sliced\n\
+            \n\
+            # Chores\n\n\
+            - [ ] Vacuum\n";
+        let backup = parse(markdown, default(), true).unwrap();
+        assert_eq!(backup.lists.len(), 2);
+        assert_eq!(backup.lists[0].tasks[1].task.status, "completed");
+        assert_eq!(backup.lists[0].tasks[1].checklist_items.len(), 2);
+        assert_eq!(format(&backup), markdown);
+    }
+
+    #[test]
+    fn reads_meeting_notes() {
+        let notes = "Before any heading:\n\
+            * [ ] Send the notes\n\
+            \n\
+            # Planning, 10 March\n\
+            ## Attendees\n\
+            - Ana\n\
+            - Ben\n\
+            ## Actions\n\
+            1. [ ] Book the room\n\
+            \t- [X] Ask for a screen\n\
+            2. [ ] Draft the agenda\n\
+            - [] Not an item\n\
+            #hashtag\n\
+            - [ ] Share the slides\n";
+        let backup = parse(notes, default(), true).unwrap();
+        let names: Vec<&str> = backup
+            .lists
+            .iter()
+            .map(|list| list.display_name.as_str())
+            .collect();
+        assert_eq!(names, ["Tasks", "Actions"]);
+        let actions = &backup.lists[1].tasks;
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0].task.title, "Book the room");
+        assert!(actions[0].checklist_items[0].is_checked);
+
+        let one_list = parse(notes, default(), false).unwrap();
+        assert_eq!(one_list.lists.len(), 1);
+        assert_eq!(one_list.task_count(), 4);
+    }
+}
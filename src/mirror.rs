@@ -0,0 +1,700 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! `tdi mirror`: a folder with a Markdown file per list, kept in sync with
+//! To Do both ways. Tasks are the `- [ ]` lines of a file, each with the id
+//! of its task in a hidden `<!-- tdi:ID -->` marker, and the id of the list
+//! is in a `<!-- tdi:list ID -->` marker. A state file in the folder keeps
+//! each task as it was at the last run, so that a change in a file can be
+//! told apart from one made in To Do; when both changed, To Do wins.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::graph::GraphClient;
+use crate::history::{Change, DeletedTask, History};
+use crate::lists::{fetch_todo_lists, TodoList};
+use crate::markdown;
+use crate::tasksv2::{previous_values, TodoTask};
+
+const STATE_FILE: &str = ".tdi-mirror.json";
+
+/// What a task line holds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Synced {
+    title: String,
+    completed: bool,
+}
+
+impl Synced {
+    fn of(task: &TodoTask) -> Synced {
+        Synced {
+            title: task.title.clone(),
+            completed: task.status == "completed",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListState {
+    file: String,
+    /// The tasks as they were after the last run, by id.
+    tasks: BTreeMap<String, Synced>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// By list id.
+    lists: BTreeMap<String, ListState>,
+}
+
+impl State {
+    fn load(dir: &Path) -> Result<State> {
+        match std::fs::File::open(dir.join(STATE_FILE)) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        serde_json::to_writer_pretty(std::fs::File::create(dir.join(STATE_FILE))?, self)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    /// The marker with the id of the list.
+    List(String),
+    Task {
+        id: Option<String>,
+        task: Synced,
+    },
+    /// Anything else, kept as it is.
+    Text(String),
+}
+
+impl Line {
+    fn parse(line: &str) -> Line {
+        let marker = line
+            .trim_end()
+            .strip_suffix("-->")
+            .and_then(|rest| rest.rsplit_once("<!-- tdi:"));
+        let (text, id) = match marker {
+            Some((text, id)) => (text.trim_end(), Some(id.trim())),
+            None => (line, None),
+        };
+        if let Some(list) = id.and_then(|id| id.strip_prefix("list ")) {
+            if text.is_empty() {
+                return Line::List(list.trim().to_string());
+            }
+        }
+        match markdown::parse_item(text) {
+            Some((0, completed, title)) => Line::Task {
+                id: id.map(str::to_string),
+                task: Synced {
+                    title: title.to_string(),
+                    completed,
+                },
+            },
+            _ => Line::Text(line.to_string()),
+        }
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Line::List(id) => format!("<!-- tdi:list {} -->", id),
+            Line::Task { id, task } => {
+                let line = format!("- {} {}", markdown::checkbox(task.completed), task.title);
+                match id {
+                    Some(id) => format!("{} <!-- tdi:{} -->", line, id),
+                    None => line,
+                }
+            }
+            Line::Text(text) => text.clone(),
+        }
+    }
+}
+
+fn parse_file(content: &str) -> Vec<Line> {
+    content.lines().map(Line::parse).collect()
+}
+
+fn render_file(lines: &[Line]) -> String {
+    lines.iter().map(|line| line.render() + "\n").collect()
+}
+
+/// What To Do needs to get the changes made in a file.
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Create the task of the line at this index.
+    Create(usize),
+    Update {
+        id: String,
+        changes: Map<String, Value>,
+    },
+    Delete(String),
+}
+
+#[derive(Debug)]
+struct Merge {
+    lines: Vec<Line>,
+    actions: Vec<Action>,
+    /// How many lines changed for changes made in To Do.
+    pulled: usize,
+    /// Titles of tasks changed on both sides.
+    conflicts: Vec<String>,
+}
+
+/// One field of a task: the file's value if only the file changed it,
+/// else To Do's. Returns the value, whether To Do needs it, and whether
+/// both sides changed it.
+fn pick<T: PartialEq + Clone>(local: &T, remote: &T, base: Option<&T>) -> (T, bool, bool) {
+    match base {
+        Some(base) if local != base && remote == base => (local.clone(), true, false),
+        Some(base) if local != base && local != remote => (remote.clone(), false, true),
+        _ => (remote.clone(), false, false),
+    }
+}
+
+/// Merges the lines of a file with the tasks of its list, given the tasks
+/// as they were after the last run.
+fn merge(lines: Vec<Line>, remote: &[TodoTask], base: &BTreeMap<String, Synced>) -> Merge {
+    let by_id: HashMap<&str, &TodoTask> =
+        remote.iter().map(|task| (task.id.as_str(), task)).collect();
+    let mut merge = Merge {
+        lines: Vec::new(),
+        actions: Vec::new(),
+        pulled: 0,
+        conflicts: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    for line in lines {
+        let Line::Task { id, task: local } = line else {
+            merge.lines.push(line);
+            continue;
+        };
+        // A copied line is a new task.
+        match id.filter(|id| !seen.contains(id)) {
+            Some(id) if by_id.contains_key(id.as_str()) => {
+                let theirs = Synced::of(by_id[id.as_str()]);
+                let base = base.get(&id);
+                let (title, push_title, title_conflict) =
+                    pick(&local.title, &theirs.title, base.map(|b| &b.title));
+                let (completed, push_completed, completed_conflict) = pick(
+                    &local.completed,
+                    &theirs.completed,
+                    base.map(|b| &b.completed),
+                );
+                if title_conflict || completed_conflict {
+                    merge.conflicts.push(theirs.title.clone());
+                }
+                let mut changes = Map::new();
+                if push_title {
+                    changes.insert("title".to_string(), json!(title));
+                }
+                if push_completed {
+                    let status = if completed { "completed" } else { "notStarted" };
+                    changes.insert("status".to_string(), json!(status));
+                }
+                if !changes.is_empty() {
+                    merge.actions.push(Action::Update {
+                        id: id.clone(),
+                        changes,
+                    });
+                }
+                let task = Synced { title, completed };
+                if task != local {
+                    merge.pulled += 1;
+                }
+                seen.insert(id.clone());
+                merge.lines.push(Line::Task { id: Some(id), task });
+            }
+            // Deleted in To Do.
+            Some(id) if base.contains_key(&id) => merge.pulled += 1,
+            _ => {
+                merge.actions.push(Action::Create(merge.lines.len()));
+                merge.lines.push(Line::Task {
+                    id: None,
+                    task: local,
+                });
+            }
+        }
+    }
+
+    // Tasks not in the file: deleted from it, else new in To Do, which go
+    // after the last task so that notes at the end stay there.
+    let mut at = merge
+        .lines
+        .iter()
+        .rposition(|line| matches!(line, Line::Task { .. }))
+        .map_or(merge.lines.len(), |i| i + 1);
+    for task in remote.iter().filter(|task| !seen.contains(&task.id)) {
+        let theirs = Synced::of(task);
+        match base.get(&task.id) {
+            Some(base) if *base == theirs => merge.actions.push(Action::Delete(task.id.clone())),
+            _ => {
+                merge.pulled += 1;
+                merge.lines.insert(
+                    at,
+                    Line::Task {
+                        id: Some(task.id.clone()),
+                        task: theirs,
+                    },
+                );
+                at += 1;
+            }
+        }
+    }
+    merge
+}
+
+/// Sends the changes of a merge to To Do and returns the tasks as they are
+/// now, by id. Failed changes keep their state, so the next run tries them
+/// again.
+fn apply(
+    client: &GraphClient,
+    file: &str,
+    list_id: &str,
+    merge: &mut Merge,
+    remote: &[TodoTask],
+    base: &BTreeMap<String, Synced>,
+    history: &mut Vec<Change>,
+) -> BTreeMap<String, Synced> {
+    let url = format!("/me/todo/lists/{}/tasks", list_id);
+    let by_id: HashMap<&str, &TodoTask> =
+        remote.iter().map(|task| (task.id.as_str(), task)).collect();
+    let mut kept = BTreeMap::new();
+    for action in &merge.actions {
+        match action {
+            Action::Create(index) => {
+                let Line::Task { id, task } = &mut merge.lines[*index] else {
+                    continue;
+                };
+                let status = if task.completed {
+                    "completed"
+                } else {
+                    "notStarted"
+                };
+                let created = client
+                    .post(&url, &json!({ "title": task.title, "status": status }))
+                    .and_then(|created| {
+                        created["id"]
+                            .as_str()
+                            .map(str::to_string)
+                            .ok_or_else(|| anyhow!("no id for the created task"))
+                    });
+                match created {
+                    Ok(created) => {
+                        eprintln!("tdi: {}: added {}", file, task.title);
+                        *id = Some(created);
+                    }
+                    Err(err) => eprintln!("tdi: {}: unable to add {}: {}", file, task.title, err),
+                }
+            }
+            Action::Update { id, changes } => {
+                let task = by_id[id.as_str()];
+                let changes = Value::Object(changes.clone());
+                let url = format!("{}/{}", url, id);
+                match client.patch(&url, &changes) {
+                    Ok(_) => {
+                        eprintln!("tdi: {}: updated {}", file, task.title);
+                        history.push(Change::TaskUpdated {
+                            list_id: list_id.to_string(),
+                            task_id: id.clone(),
+                            title: task.title.clone(),
+                            before: previous_values(task, &changes),
+                        });
+                    }
+                    Err(err) => {
+                        eprintln!("tdi: {}: unable to update {}: {}", file, task.title, err);
+                        kept.extend(base.get(id).map(|synced| (id.clone(), synced.clone())));
+                    }
+                }
+            }
+            Action::Delete(id) => {
+                let task = by_id[id.as_str()];
+                match client.request(Method::DELETE, &format!("{}/{}", url, id), None) {
+                    Ok(_) => {
+                        eprintln!("tdi: {}: deleted {}", file, task.title);
+                        let mut task = task.clone();
+                        let checklist_items = task
+                            .extra
+                            .remove("checklistItems")
+                            .and_then(|items| serde_json::from_value(items).ok())
+                            .unwrap_or_default();
+                        history.push(Change::TaskDeleted {
+                            list_id: list_id.to_string(),
+                            deleted: DeletedTask {
+                                task,
+                                checklist_items,
+                            },
+                        });
+                    }
+                    Err(err) => {
+                        eprintln!("tdi: {}: unable to delete {}: {}", file, task.title, err);
+                        kept.insert(id.clone(), Synced::of(task));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut synced: BTreeMap<String, Synced> = merge
+        .lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Task { id: Some(id), task } => Some((id.clone(), task.clone())),
+            _ => None,
+        })
+        .collect();
+    synced.extend(kept);
+    synced
+}
+
+/// A file name for a list, unlike those of `taken`.
+fn file_name(list: &TodoList, taken: &HashSet<String>) -> String {
+    let stem: String = list
+        .display_name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c => c,
+        })
+        .collect();
+    let stem = match stem.trim().trim_start_matches('.') {
+        "" => "list".to_string(),
+        stem => stem.to_string(),
+    };
+    (1..)
+        .map(|n| match n {
+            1 => format!("{}.md", stem),
+            n => format!("{} ({}).md", stem, n),
+        })
+        .find(|name| !taken.contains(name))
+        .unwrap()
+}
+
+/// Mirrors one list into the file `name`, returning its new state.
+fn mirror_list(
+    client: &GraphClient,
+    dir: &Path,
+    name: &str,
+    list_id: &str,
+    lines: Vec<Line>,
+    base: &BTreeMap<String, Synced>,
+    history: &mut Vec<Change>,
+) -> Result<ListState> {
+    let path = dir.join(name);
+    // With their checklist items, so that deleted tasks can be brought back
+    // whole by `tdi undo`.
+    let remote: Vec<TodoTask> = client.get_all(&format!(
+        "/me/todo/lists/{}/tasks?$expand=checklistItems",
+        list_id
+    ))?;
+    let before = render_file(&lines);
+    let mut merge = merge(lines, &remote, base);
+    for title in &merge.conflicts {
+        eprintln!(
+            "tdi: {}: {} changed in both places, kept the To Do version",
+            name, title
+        );
+    }
+    if merge.pulled > 0 {
+        eprintln!("tdi: {}: {} changes from To Do", name, merge.pulled);
+    }
+    let tasks = apply(client, name, list_id, &mut merge, &remote, base, history);
+    let after = render_file(&merge.lines);
+    if after != before || !path.exists() {
+        std::fs::write(&path, after)?;
+    }
+    Ok(ListState {
+        file: name.to_string(),
+        tasks,
+    })
+}
+
+/// Keeps `dir` and To Do in sync: a Markdown file per list, with new files
+/// becoming lists.
+pub fn mirror(dir: &str) -> Result<()> {
+    let dir = PathBuf::from(shellexpand::tilde(dir).to_string());
+    std::fs::create_dir_all(&dir)?;
+    let client = GraphClient::new()?;
+    let mut state = State::load(&dir)?;
+    let lists = fetch_todo_lists(&client)?;
+
+    // The files, by the list they mirror, and those new.
+    let mut files: HashMap<String, (String, Vec<Line>)> = HashMap::new();
+    let mut new_files = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("invalid file name {}", path.display()))?
+            .to_string();
+        let lines = parse_file(&std::fs::read_to_string(&path)?);
+        let list_id = lines.iter().find_map(|line| match line {
+            Line::List(id) => Some(id.clone()),
+            _ => None,
+        });
+        match list_id {
+            Some(id) => {
+                files.insert(id, (name, lines));
+            }
+            None => new_files.push((name, lines)),
+        }
+    }
+    new_files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Lists deleted in To Do are forgotten; their files are kept, as they
+    // may hold what was never synced, and warned about below.
+    let ids: HashSet<&str> = lists.iter().map(|list| list.id.as_str()).collect();
+    state.lists.retain(|id, _| ids.contains(id.as_str()));
+
+    // History is recorded even when a list fails, for the changes already
+    // made to the others.
+    let mut history = Vec::new();
+    let sync_lists = || -> Result<()> {
+        let mut taken: HashSet<String> = files
+            .values()
+            .map(|(name, _)| name.clone())
+            .chain(new_files.iter().map(|(name, _)| name.clone()))
+            .collect();
+        for list in &lists {
+            let base = state.lists.remove(&list.id).unwrap_or_default();
+            let (name, lines, base) = match files.remove(&list.id) {
+                Some((name, lines)) => (name, lines, base.tasks),
+                // Without the file there is nothing to compare to, so nothing
+                // is deleted in To Do.
+                None => {
+                    let name = file_name(list, &taken);
+                    taken.insert(name.clone());
+                    let lines = vec![
+                        Line::List(list.id.clone()),
+                        Line::Text(format!("# {}", list.display_name)),
+                        Line::Text(String::new()),
+                    ];
+                    (name, lines, BTreeMap::new())
+                }
+            };
+            let list_state =
+                mirror_list(&client, &dir, &name, &list.id, lines, &base, &mut history)?;
+            state.lists.insert(list.id.clone(), list_state);
+            state.save(&dir)?;
+        }
+
+        for (name, _) in files.values() {
+            eprintln!(
+                "tdi: left {} alone, its list is not in To Do; remove its list marker to create one",
+                name
+            );
+        }
+
+        for (name, mut lines) in new_files {
+            let display_name = lines
+                .iter()
+                .find_map(|line| match line {
+                    Line::Text(text) => text.strip_prefix("# ").map(str::trim),
+                    _ => None,
+                })
+                .unwrap_or_else(|| name.trim_end_matches(".md"))
+                .to_string();
+            let created = client.post("/me/todo/lists", &json!({ "displayName": display_name }))?;
+            let list_id = created["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("no id for the created list {}", display_name))?
+                .to_string();
+            eprintln!("tdi: {}: created the list {}", name, display_name);
+            // Marked at once, so that a failure below does not lead the
+            // next run to create the list again.
+            lines.insert(0, Line::List(list_id.clone()));
+            std::fs::write(dir.join(&name), render_file(&lines))?;
+            let marked = ListState {
+                file: name.clone(),
+                ..ListState::default()
+            };
+            state.lists.insert(list_id.clone(), marked);
+            state.save(&dir)?;
+            let list_state = mirror_list(
+                &client,
+                &dir,
+                &name,
+                &list_id,
+                lines,
+                &BTreeMap::new(),
+                &mut history,
+            )?;
+            state.lists.insert(list_id, list_state);
+            state.save(&dir)?;
+        }
+        Ok(())
+    };
+    let result = sync_lists();
+    let recorded = History::record("mirrored", history);
+    result?;
+    recorded?;
+    println!(
+        "tdi: mirrored {} lists in {}",
+        state.lists.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, title: &str, completed: bool) -> TodoTask {
+        let mut task = TodoTask::local(id, title);
+        if completed {
+            task.status = "completed".to_string();
+        }
+        task
+    }
+
+    fn synced(title: &str, completed: bool) -> Synced {
+        Synced {
+            title: title.to_string(),
+            completed,
+        }
+    }
+
+    #[test]
+    fn parses_and_renders_lines() {
+        let file = "<!-- tdi:list L1 -->\n\
+            # Groceries\n\
+            \n\
+            - [x] Milk <!-- tdi:AAMk= -->\n  \
+              - [ ] Oat\n\
+            - [ ] Bread\n\
+            Bring bags <!-- tdi:not-a-task -->\n";
+        let lines = parse_file(file);
+        assert_eq!(lines[0], Line::List("L1".to_string()));
+        assert_eq!(
+            lines[3],
+            Line::Task {
+                id: Some("AAMk=".to_string()),
+                task: synced("Milk", true)
+            }
+        );
+        assert!(matches!(lines[4], Line::Text(_)));
+        assert_eq!(
+            lines[5],
+            Line::Task {
+                id: None,
+                task: synced("Bread", false)
+            }
+        );
+        assert!(matches!(lines[6], Line::Text(_)));
+        assert_eq!(render_file(&lines), file);
+    }
+
+    #[test]
+    fn merges_changes_from_both_sides() {
+        let file = parse_file(
+            "# List\n\
+            - [x] Milk <!-- tdi:1 -->\n\
+            - [ ] Bread, sliced <!-- tdi:2 -->\n\
+            - [ ] Eggs <!-- tdi:3 -->\n\
+            - [ ] Tea <!-- tdi:4 -->\n\
+            - [ ] Jam\n\
+            Notes at the end\n",
+        );
+        let base: BTreeMap<String, Synced> = [
+            ("1", synced("Milk", false)),
+            ("2", synced("Bread", false)),
+            ("3", synced("Eggs", false)),
+            ("4", synced("Tea", false)),
+            ("5", synced("Rice", false)),
+            ("6", synced("Salt", false)),
+        ]
+        .into_iter()
+        .map(|(id, synced)| (id.to_string(), synced))
+        .collect();
+        let remote = [
+            // Checked in the file.
+            task("1", "Milk", false),
+            // Renamed on both sides.
+            task("2", "Bread, whole", false),
+            // Completed in To Do.
+            task("3", "Eggs", true),
+            // 4 was deleted in To Do, 5 from the file.
+            task("5", "Rice", false),
+            // 6 is gone from the file but was renamed in To Do.
+            task("6", "Sea salt", false),
+            task("7", "Coffee", false),
+        ];
+        let merge = merge(file, &remote, &base);
+
+        let rendered: Vec<String> = merge.lines.iter().map(Line::render).collect();
+        assert_eq!(
+            rendered,
+            [
+                "# List",
+                "- [x] Milk <!-- tdi:1 -->",
+                "- [ ] Bread, whole <!-- tdi:2 -->",
+                "- [x] Eggs <!-- tdi:3 -->",
+                "- [ ] Jam",
+                "- [ ] Sea salt <!-- tdi:6 -->",
+                "- [ ] Coffee <!-- tdi:7 -->",
+                "Notes at the end",
+            ]
+        );
+        assert_eq!(
+            merge.actions,
+            [
+                Action::Update {
+                    id: "1".to_string(),
+                    changes: [("status".to_string(), json!("completed"))]
+                        .into_iter()
+                        .collect(),
+                },
+                Action::Create(4),
+                Action::Delete("5".to_string()),
+            ]
+        );
+        assert_eq!(merge.conflicts, ["Bread, whole"]);
+        // Bread, Eggs, Tea, Sea salt and Coffee.
+        assert_eq!(merge.pulled, 5);
+    }
+
+    #[test]
+    fn treats_copied_lines_and_lost_state_as_new() {
+        let file = parse_file("- [ ] Walk <!-- tdi:1 -->\n- [ ] Walk again <!-- tdi:1 -->\n");
+        let merge = merge(file, &[task("1", "Walk the dog", false)], &BTreeMap::new());
+        let rendered: Vec<String> = merge.lines.iter().map(Line::render).collect();
+        assert_eq!(
+            rendered,
+            ["- [ ] Walk the dog <!-- tdi:1 -->", "- [ ] Walk again"]
+        );
+        assert_eq!(merge.actions, [Action::Create(1)]);
+    }
+
+    #[test]
+    fn names_files_after_lists() {
+        let list = |name: &str| TodoList {
+            display_name: name.to_string(),
+            is_owner: true,
+            is_shared: false,
+            id: "l".to_string(),
+            wellknown_list_name: "none".to_string(),
+        };
+        let taken: HashSet<String> = ["Work.md".to_string()].into_iter().collect();
+        assert_eq!(file_name(&list("Home/Garden"), &taken), "Home-Garden.md");
+        assert_eq!(file_name(&list("Work"), &taken), "Work (2).md");
+        assert_eq!(file_name(&list(".."), &taken), "list.md");
+    }
+}
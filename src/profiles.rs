@@ -0,0 +1,184 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Named profiles, one per account. Each profile has its own token file and
+//! caches, under `profiles/<name>` in the config and cache directories.
+
+use anyhow::{bail, Result};
+use cli_table::{print_stdout, Cell, Style, Table};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::helper::{
+    get_base_cache_dir, get_base_config_dir, get_profile_cache_dir, get_profile_config_dir,
+    DEFAULT_PROFILE,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    name: String,
+    active: bool,
+    logged_in: bool,
+}
+
+fn active_profile_path() -> String {
+    get_base_config_dir() + "/active_profile"
+}
+
+/// The profile to use: the one requested with `--profile`/`TDI_PROFILE`,
+/// else the one selected with `tdi profiles use`, else "default".
+pub fn resolve_profile(requested: &Option<String>) -> Result<String> {
+    let name = match requested {
+        Some(name) => name.clone(),
+        None => std::fs::read_to_string(active_profile_path())
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| DEFAULT_PROFILE.to_string()),
+    };
+    validate(&name)?;
+    Ok(name)
+}
+
+fn validate(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!(
+            "invalid profile name {:?}, use letters, digits, '-' and '_'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Moves files from before profiles existed into the "default" profile.
+pub fn migrate_legacy_files() -> Result<()> {
+    let legacy = [
+        (
+            get_base_config_dir(),
+            get_profile_config_dir(DEFAULT_PROFILE),
+            vec!["tdi.json", "lists_cache.json", "tasks_cache.json"],
+        ),
+        (
+            get_base_cache_dir(),
+            get_profile_cache_dir(DEFAULT_PROFILE),
+            vec![
+                "store.json",
+                "journal.json",
+                "conflicts.json",
+                "history.json",
+            ],
+        ),
+    ];
+    for (from_dir, to_dir, files) in legacy {
+        for file in files {
+            let from = Path::new(&from_dir).join(file);
+            let to = Path::new(&to_dir).join(file);
+            if from.exists() && !to.exists() {
+                std::fs::create_dir_all(&to_dir)?;
+                std::fs::rename(from, to)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every profile with a config directory, "default" first.
+pub fn profile_names() -> Result<Vec<String>> {
+    let mut names = vec![DEFAULT_PROFILE.to_string()];
+    if let Ok(entries) = std::fs::read_dir(get_base_config_dir() + "/profiles") {
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    names[1..].sort();
+    Ok(names)
+}
+
+pub fn list_profiles(output_format: &str, active: &str) -> Result<()> {
+    let mut names = profile_names()?;
+    if !names.iter().any(|name| name == active) {
+        names.push(active.to_string());
+    }
+    let profiles: Vec<Profile> = names
+        .into_iter()
+        .map(|name| Profile {
+            active: name == active,
+            logged_in: Path::new(&get_profile_config_dir(&name))
+                .join("tdi.json")
+                .exists(),
+            name,
+        })
+        .collect();
+
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&profiles)?),
+        "table" => {
+            let rows: Vec<_> = profiles
+                .iter()
+                .map(|p| {
+                    vec![
+                        if p.active { "*" } else { "" }.cell(),
+                        p.name.as_str().cell(),
+                        if p.logged_in { "yes" } else { "no" }.cell(),
+                    ]
+                })
+                .collect();
+            let table = rows.table().title(vec![
+                "".cell(),
+                "Profile".cell().bold(true),
+                "Logged In".cell().bold(true),
+            ]);
+            print_stdout(table)?;
+        }
+        _ => {
+            for p in &profiles {
+                println!(
+                    "{} {}{}",
+                    if p.active { "*" } else { " " },
+                    p.name,
+                    if p.logged_in { "" } else { " (logged out)" }
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes `name` the profile used when none is given.
+pub fn use_profile(name: &str) -> Result<()> {
+    validate(name)?;
+    std::fs::create_dir_all(get_base_config_dir())?;
+    std::fs::write(active_profile_path(), name)?;
+    println!("tdi: now using profile {}.", name);
+    if !Path::new(&get_profile_config_dir(name))
+        .join("tdi.json")
+        .exists()
+    {
+        println!("tdi: run `tdi login` to sign in to it.");
+    }
+    Ok(())
+}
+
+/// Deletes the tokens and caches of a profile.
+pub fn remove_profile(name: &str) -> Result<()> {
+    validate(name)?;
+    for dir in [get_profile_config_dir(name), get_profile_cache_dir(name)] {
+        if Path::new(&dir).exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+    if resolve_profile(&None)? == name {
+        let _ = std::fs::remove_file(active_profile_path());
+    }
+    println!("tdi: removed profile {}.", name);
+    Ok(())
+}
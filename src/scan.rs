@@ -0,0 +1,561 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! `tdi scan`: the TODO, FIXME and HACK comments of a source tree as tasks
+//! of a list. A task links back to its comment with a linked resource,
+//! whose external id is made from the file, tag and text of the comment,
+//! so that a comment keeps its task when lines move around it. Tasks whose
+//! comments are gone are completed, and reopened if they come back.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use ignore::WalkBuilder;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+
+use crate::batch::BatchRequest;
+use crate::graph::GraphClient;
+use crate::history::{Change, History};
+use crate::lists::fetch_todo_lists;
+use crate::tasksv2::{previous_values, LinkedResource, TodoTask};
+
+/// The application name of the linked resources of scanned tasks.
+const APPLICATION: &str = "tdi scan";
+
+/// Larger files are not source code.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// The longest title Graph takes.
+const MAX_TITLE: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// Relative to the work tree, see [`top`], with "/" between folders.
+    pub path: String,
+    pub line: usize,
+    pub tag: String,
+    pub text: String,
+    /// Tells the comment apart from the others, see [`find_comments`].
+    pub key: String,
+}
+
+impl Comment {
+    fn location(&self) -> String {
+        format!("{}:{}", self.path, self.line)
+    }
+
+    fn title(&self) -> String {
+        let title = match self.text.is_empty() {
+            true => format!("{} in {}", self.tag, self.path),
+            false => format!("{}: {}", self.tag, self.text),
+        };
+        title.chars().take(MAX_TITLE).collect()
+    }
+}
+
+fn comment_regex() -> Regex {
+    // A tag right after the start of a comment, in most languages.
+    Regex::new(
+        r"(?:^|[^\w:])(?://+!?|#+|/\*+!?|\*|--|;+|<!--|%+)\s*\b(TODO|FIXME|HACK)\b(?:\([^)]*\))?:?(.*)$",
+    )
+    .unwrap()
+}
+
+/// The comments of one file. A comment is known by its file, tag and text,
+/// and how many the same came before it in the file.
+pub fn find_comments(path: &str, content: &str) -> Vec<Comment> {
+    let regex = comment_regex();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    let mut comments = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        let Some(captures) = regex.captures(line) else {
+            continue;
+        };
+        let tag = captures[1].to_string();
+        let text = captures[2].trim();
+        let text = text
+            .strip_suffix("*/")
+            .or_else(|| text.strip_suffix("-->"))
+            .unwrap_or(text);
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let count = seen.entry((tag.clone(), text.clone())).or_default();
+        *count += 1;
+        let mut hash = Sha1::new();
+        hash.update(format!("{}\n{}\n{}\n{}", path, tag, text, count));
+        let key = hash
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        comments.push(Comment {
+            path: path.to_string(),
+            line: n + 1,
+            tag,
+            text,
+            key,
+        });
+    }
+    comments
+}
+
+/// The files of the tree: those git does not ignore in a work tree, else
+/// those not hidden nor matched by a `.gitignore`.
+fn files(root: &Path) -> Result<Vec<PathBuf>> {
+    if root.is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args([
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ])
+        .output();
+    if let Some(output) = output.ok().filter(|output| output.status.success()) {
+        return Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|name| !name.is_empty())
+            .map(|name| root.join(name))
+            .filter(|path| path.is_file())
+            .collect());
+    }
+
+    let mut files = Vec::new();
+    for entry in WalkBuilder::new(root).require_git(false).build() {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|kind| kind.is_file()) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The folder paths are relative to: the git work tree of the scanned path,
+/// or else the root of the file system, so that keys do not depend on the
+/// part of the tree that is scanned.
+fn top(root: &Path) -> PathBuf {
+    let folder = match root.is_file() {
+        true => root.parent().unwrap_or(root),
+        false => root,
+    };
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(folder)
+        .args(["rev-parse", "--show-toplevel"])
+        .output();
+    output
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+                .canonicalize()
+                .ok()
+        })
+        .unwrap_or_else(|| root.ancestors().last().unwrap_or(root).to_path_buf())
+}
+
+/// The comments of every text file under `root`, with paths relative to
+/// `top`.
+fn scan_tree(root: &Path, top: &Path) -> Result<Vec<Comment>> {
+    let mut comments = Vec::new();
+    for file in files(root)? {
+        if std::fs::metadata(&file)?.len() > MAX_FILE_SIZE {
+            continue;
+        }
+        let bytes = std::fs::read(&file)?;
+        if bytes.iter().take(8000).any(|byte| *byte == 0) {
+            continue;
+        }
+        let relative: Vec<String> = file
+            .strip_prefix(top)
+            .unwrap_or(&file)
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy().to_string())
+            .collect();
+        comments.extend(find_comments(
+            &relative.join("/"),
+            &String::from_utf8_lossy(&bytes),
+        ));
+    }
+    Ok(comments)
+}
+
+/// A task made from a comment, with the resource linking to it.
+#[derive(Debug)]
+struct Tracked {
+    task: TodoTask,
+    resource: LinkedResource,
+}
+
+fn tracked(tasks: Vec<TodoTask>) -> Vec<Tracked> {
+    tasks
+        .into_iter()
+        .filter_map(|task| {
+            let resources: Vec<LinkedResource> = task
+                .extra
+                .get("linkedResources")
+                .and_then(|resources| serde_json::from_value(resources.clone()).ok())
+                .unwrap_or_default();
+            let resource = resources
+                .into_iter()
+                .find(|resource| resource.application_name.as_deref() == Some(APPLICATION))?;
+            Some(Tracked { task, resource })
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Step<'a> {
+    Create(&'a Comment),
+    /// The comment moved, or came back after its task was completed.
+    Update {
+        task: &'a TodoTask,
+        resource_id: &'a str,
+        comment: &'a Comment,
+        reopen: bool,
+        moved: bool,
+    },
+    /// The comment is gone.
+    Complete(&'a TodoTask),
+}
+
+/// The steps for the comments found under `root`. Tasks of comments
+/// elsewhere, out of the scanned part of the tree or in another one, are
+/// left alone.
+fn plan<'a>(comments: &'a [Comment], tracked: &'a [Tracked], root: &Path) -> Vec<Step<'a>> {
+    let by_key: HashMap<&str, &Tracked> = tracked
+        .iter()
+        .filter_map(|t| Some((t.resource.external_id.as_deref()?, t)))
+        .collect();
+    let mut steps = Vec::new();
+    for comment in comments {
+        match by_key.get(comment.key.as_str()) {
+            None => steps.push(Step::Create(comment)),
+            Some(tracked) => {
+                let reopen = tracked.task.status == "completed";
+                let moved = tracked.resource.display_name.as_deref() != Some(&comment.location());
+                if reopen || moved {
+                    steps.push(Step::Update {
+                        task: &tracked.task,
+                        resource_id: &tracked.resource.id,
+                        comment,
+                        reopen,
+                        moved,
+                    });
+                }
+            }
+        }
+    }
+    let keys: HashSet<&str> = comments.iter().map(|c| c.key.as_str()).collect();
+    for tracked in tracked {
+        let scanned = tracked
+            .resource
+            .web_url
+            .as_deref()
+            .and_then(|url| url::Url::parse(url).ok()?.to_file_path().ok())
+            .is_some_and(|file| file.starts_with(root));
+        let gone = tracked
+            .resource
+            .external_id
+            .as_deref()
+            .is_none_or(|key| !keys.contains(key));
+        if scanned && gone && tracked.task.status != "completed" {
+            steps.push(Step::Complete(&tracked.task));
+        }
+    }
+    steps
+}
+
+fn body(comment: &Comment) -> Value {
+    json!({ "content": comment.location(), "contentType": "text" })
+}
+
+fn resource(root: &Path, comment: &Comment) -> LinkedResource {
+    let file = root.join(&comment.path);
+    LinkedResource {
+        web_url: url::Url::from_file_path(&file).ok().map(String::from),
+        application_name: Some(APPLICATION.to_string()),
+        display_name: Some(comment.location()),
+        external_id: Some(comment.key.clone()),
+        ..LinkedResource::default()
+    }
+}
+
+/// Adds, updates and completes the tasks of `list` for the comments under
+/// `path`, creating the list if there is none with the name.
+pub fn scan(path: &str, list: &str, dry_run: &bool) -> Result<()> {
+    let root = PathBuf::from(shellexpand::tilde(path).to_string()).canonicalize()?;
+    let base = top(&root);
+    let comments = scan_tree(&root, &base)?;
+
+    let client = GraphClient::new()?;
+    let existing = fetch_todo_lists(&client)?
+        .into_iter()
+        .find(|existing| existing.display_name == list);
+    let tasks: Vec<TodoTask> = match &existing {
+        Some(existing) => client.get_all(&format!(
+            "/me/todo/lists/{}/tasks?$expand=linkedResources",
+            existing.id
+        ))?,
+        None => Vec::new(),
+    };
+    let tracked = tracked(tasks);
+    let steps = plan(&comments, &tracked, &root);
+
+    if *dry_run {
+        if existing.is_none() {
+            println!("tdi: would create the list {}", list);
+        }
+        for step in &steps {
+            match step {
+                Step::Create(comment) => println!(
+                    "tdi: would add {} ({})",
+                    comment.title(),
+                    comment.location()
+                ),
+                Step::Update { task, comment, .. } => {
+                    println!("tdi: would update {} ({})", task.title, comment.location())
+                }
+                Step::Complete(task) => println!("tdi: would complete {}", task.title),
+            }
+        }
+        return Ok(());
+    }
+
+    let list_id = match existing {
+        Some(existing) => existing.id,
+        None => {
+            let created = client.post("/me/todo/lists", &json!({ "displayName": list }))?;
+            eprintln!("tdi: created the list {}", list);
+            created["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("no id for the created list {}", list))?
+                .to_string()
+        }
+    };
+    let url = format!("/me/todo/lists/{}/tasks", list_id);
+
+    let mut requests = Vec::new();
+    let mut changes = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let id = i.to_string();
+        match step {
+            Step::Create(comment) => requests.push(BatchRequest::post(
+                &id,
+                &url,
+                json!({
+                    "title": comment.title(),
+                    "body": body(comment),
+                    "linkedResources": [resource(&base, comment)],
+                }),
+            )),
+            Step::Update {
+                task,
+                resource_id,
+                comment,
+                reopen,
+                moved,
+            } => {
+                let mut fields = Map::new();
+                if *reopen {
+                    fields.insert("status".to_string(), json!("notStarted"));
+                }
+                if *moved {
+                    fields.insert("body".to_string(), body(comment));
+                    requests.push(BatchRequest::patch(
+                        &format!("{}-link", id),
+                        &format!("{}/{}/linkedResources/{}", url, task.id, resource_id),
+                        json!(resource(&base, comment)),
+                    ));
+                }
+                changes.push((id.clone(), *task, Value::Object(fields.clone())));
+                requests.push(BatchRequest::patch(
+                    &id,
+                    &format!("{}/{}", url, task.id),
+                    Value::Object(fields),
+                ));
+            }
+            Step::Complete(task) => {
+                let fields = json!({ "status": "completed" });
+                changes.push((id.clone(), *task, fields.clone()));
+                requests.push(BatchRequest::patch(
+                    &id,
+                    &format!("{}/{}", url, task.id),
+                    fields,
+                ));
+            }
+        }
+    }
+
+    let responses = client.batch(requests)?;
+    let failed: HashSet<&str> = responses
+        .iter()
+        .filter(|response| !response.is_success())
+        .map(|response| response.id.trim_end_matches("-link"))
+        .collect();
+    for response in responses.iter().filter(|response| !response.is_success()) {
+        eprintln!("tdi: unable to save a task: {}", response.error());
+    }
+
+    let (mut added, mut updated, mut completed) = (0, 0, 0);
+    for (i, step) in steps.iter().enumerate() {
+        if failed.contains(i.to_string().as_str()) {
+            continue;
+        }
+        match step {
+            Step::Create(_) => added += 1,
+            Step::Update { .. } => updated += 1,
+            Step::Complete(_) => completed += 1,
+        }
+    }
+    History::record(
+        "scanned",
+        changes
+            .iter()
+            .filter(|(id, _, _)| !failed.contains(id.as_str()))
+            .map(|(_, task, fields)| Change::TaskUpdated {
+                list_id: list_id.clone(),
+                task_id: task.id.clone(),
+                title: task.title.clone(),
+                before: previous_values(task, fields),
+            })
+            .collect(),
+    )?;
+    println!(
+        "tdi: {} comments, {} tasks added, {} updated, {} completed",
+        comments.len(),
+        added,
+        updated,
+        completed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_comments_of_many_languages() {
+        let source = r#"
+fn main() {
+    // TODO: handle errors
+    let url = "http://example.com"; // not a TODO here
+    /* FIXME(ana) leaks memory */
+}
+# HACK: until the API is fixed
+-- TODO
+<!-- FIXME: the   footer -->
+let todo = "TODO";
+ * TODO: document the fields
+// TODO: handle errors
+"#;
+        let comments = find_comments("src/main.rs", source);
+        let found: Vec<(usize, &str, &str)> = comments
+            .iter()
+            .map(|c| (c.line, c.tag.as_str(), c.text.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, "TODO", "handle errors"),
+                (5, "FIXME", "leaks memory"),
+                (7, "HACK", "until the API is fixed"),
+                (8, "TODO", ""),
+                (9, "FIXME", "the footer"),
+                (11, "TODO", "document the fields"),
+                (12, "TODO", "handle errors"),
+            ]
+        );
+        assert_eq!(comments[3].title(), "TODO in src/main.rs");
+        // The same comment twice is two comments.
+        assert_ne!(comments[0].key, comments[6].key);
+        // Keys stay when lines move.
+        let moved = find_comments("src/main.rs", &format!("\n\n{}", source));
+        assert_eq!(moved[0].key, comments[0].key);
+        assert_eq!(moved[0].line, 5);
+    }
+
+    fn task(id: &str, status: &str, comment: Option<&Comment>, at: &str) -> Tracked {
+        let path = at.split(':').next().unwrap_or_default();
+        Tracked {
+            task: TodoTask {
+                status: status.to_string(),
+                ..TodoTask::local(id, id)
+            },
+            resource: LinkedResource {
+                id: format!("r{}", id),
+                application_name: Some(APPLICATION.to_string()),
+                display_name: Some(at.to_string()),
+                external_id: comment.map(|c| c.key.clone()),
+                web_url: Some(format!("file:///repo/{}", path)),
+            },
+        }
+    }
+
+    #[test]
+    fn plans_changes() {
+        let comments = find_comments("a.py", "# TODO: one\n# TODO: two\n# TODO: three\n");
+        let tracked = [
+            task("1", "notStarted", Some(&comments[0]), "a.py:1"),
+            task("2", "completed", Some(&comments[1]), "a.py:5"),
+            task("3", "notStarted", None, "a.py:9"),
+            task("4", "completed", None, "a.py:9"),
+        ];
+        let steps = plan(&comments, &tracked, Path::new("/repo"));
+        assert_eq!(
+            steps,
+            [
+                Step::Update {
+                    task: &tracked[1].task,
+                    resource_id: "r2",
+                    comment: &comments[1],
+                    reopen: true,
+                    moved: true,
+                },
+                Step::Create(&comments[2]),
+                Step::Complete(&tracked[2].task),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_tasks_out_of_the_scanned_part_alone() {
+        let comments = find_comments("src/b.py", "# TODO: kept\n");
+        let tracked = [
+            task("1", "notStarted", Some(&comments[0]), "src/b.py:1"),
+            task("2", "notStarted", None, "src/c.py:3"),
+            task("3", "notStarted", None, "a.py:1"),
+            task("4", "notStarted", None, "srcs/d.py:1"),
+        ];
+        let steps = plan(&comments, &tracked, Path::new("/repo/src"));
+        assert_eq!(steps, [Step::Complete(&tracked[1].task)]);
+        // A single file leaves even its neighbours alone.
+        let steps = plan(&[], &tracked, Path::new("/repo/src/b.py"));
+        assert_eq!(steps, [Step::Complete(&tracked[0].task)]);
+    }
+
+    #[test]
+    fn follows_gitignore_outside_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::create_dir_all(root.join(".cache")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        for file in ["main.rs", "target/out.rs", ".cache/old.rs"] {
+            std::fs::write(root.join(file), "// TODO: ship\n").unwrap();
+        }
+        assert_eq!(files(&root).unwrap(), [root.join("main.rs")]);
+        assert_eq!(top(&root), Path::new("/"));
+        dir.close().unwrap();
+    }
+}
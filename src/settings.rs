@@ -0,0 +1,277 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Settings of a profile, `settings.json` in its config directory, changed
+//! with `tdi config`.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::helper::{get_config_dir, get_profile};
+
+static ENDPOINTS: OnceLock<Endpoints> = OnceLock::new();
+
+/// The Microsoft cloud a profile's tenant lives in.
+/// https://learn.microsoft.com/en-us/graph/deployments
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cloud {
+    #[default]
+    Global,
+    /// US Government L4 (GCC High).
+    Usgov,
+    /// US Government L5 (DoD).
+    UsgovDod,
+    /// China, operated by 21Vianet.
+    China,
+    /// Endpoints given with the `authority` and `graph-endpoint` settings.
+    Custom,
+}
+
+impl fmt::Display for Cloud {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Cloud::Global => "global",
+            Cloud::Usgov => "usgov",
+            Cloud::UsgovDod => "usgov-dod",
+            Cloud::China => "china",
+            Cloud::Custom => "custom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Cloud {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Cloud> {
+        match name {
+            "global" => Ok(Cloud::Global),
+            "usgov" => Ok(Cloud::Usgov),
+            "usgov-dod" => Ok(Cloud::UsgovDod),
+            "china" => Ok(Cloud::China),
+            "custom" => Ok(Cloud::Custom),
+            _ => bail!(
+                "unknown cloud {}, use \"global\", \"usgov\", \"usgov-dod\", \"china\" or \"custom\"",
+                name
+            ),
+        }
+    }
+}
+
+/// Where to sign in and where to call Graph, without trailing slashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// e.g. `https://login.microsoftonline.com`
+    pub authority: String,
+    /// e.g. `https://graph.microsoft.com`
+    pub graph: String,
+}
+
+impl Endpoints {
+    /// The versioned Graph root requests are made against.
+    pub fn graph_url(&self) -> String {
+        format!("{}/v1.0", self.graph)
+    }
+
+    /// An endpoint of the `common` tenant, e.g. "authorize" or "token".
+    pub fn oauth_url(&self, endpoint: &str) -> String {
+        format!("{}/common/oauth2/v2.0/{}", self.authority, endpoint)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    #[serde(default)]
+    pub cloud: Cloud,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graph_endpoint: Option<String>,
+}
+
+/// The names of the settings, as used by `tdi config`.
+const KEYS: [&str; 3] = ["cloud", "authority", "graph-endpoint"];
+
+impl Settings {
+    fn path() -> String {
+        get_config_dir() + "/settings.json"
+    }
+
+    pub fn load() -> Result<Settings> {
+        match std::fs::File::open(Settings::path()) {
+            Ok(file) => Ok(serde_json::from_reader(std::io::BufReader::new(file))?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(get_config_dir())?;
+        serde_json::to_writer_pretty(std::fs::File::create(Settings::path())?, self)?;
+        Ok(())
+    }
+
+    pub fn endpoints(&self) -> Result<Endpoints> {
+        let (authority, graph) = match self.cloud {
+            Cloud::Global => (
+                "https://login.microsoftonline.com",
+                "https://graph.microsoft.com",
+            ),
+            Cloud::Usgov => (
+                "https://login.microsoftonline.us",
+                "https://graph.microsoft.us",
+            ),
+            Cloud::UsgovDod => (
+                "https://login.microsoftonline.us",
+                "https://dod-graph.microsoft.us",
+            ),
+            Cloud::China => (
+                "https://login.chinacloudapi.cn",
+                "https://microsoftgraph.chinacloudapi.cn",
+            ),
+            Cloud::Custom => {
+                let missing = |key| {
+                    anyhow!(
+                        "the cloud is custom but {} is not set, run `tdi config set {} <url>`",
+                        key,
+                        key
+                    )
+                };
+                let authority = self
+                    .authority
+                    .as_deref()
+                    .ok_or_else(|| missing("authority"))?;
+                let graph = self
+                    .graph_endpoint
+                    .as_deref()
+                    .ok_or_else(|| missing("graph-endpoint"))?;
+                (authority, graph)
+            }
+        };
+        Ok(Endpoints {
+            authority: authority.trim_end_matches('/').to_string(),
+            graph: graph.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match key {
+            "cloud" => Ok(Some(self.cloud.to_string())),
+            "authority" => Ok(self.authority.clone()),
+            "graph-endpoint" => Ok(self.graph_endpoint.clone()),
+            _ => bail!("unknown setting {}, use one of {}", key, KEYS.join(", ")),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let url = |value: Option<&str>| -> Result<Option<String>> {
+            match value {
+                Some(url) if !url.starts_with("https://") => {
+                    bail!("{} must be an https:// URL", key)
+                }
+                url => Ok(url.map(str::to_string)),
+            }
+        };
+        match key {
+            "cloud" => self.cloud = value.map(str::parse).transpose()?.unwrap_or_default(),
+            "authority" => self.authority = url(value)?,
+            "graph-endpoint" => self.graph_endpoint = url(value)?,
+            _ => bail!("unknown setting {}, use one of {}", key, KEYS.join(", ")),
+        }
+        Ok(())
+    }
+}
+
+/// The endpoints of the active profile's cloud.
+pub fn endpoints() -> Result<&'static Endpoints> {
+    if let Some(endpoints) = ENDPOINTS.get() {
+        return Ok(endpoints);
+    }
+    let endpoints = Settings::load()?.endpoints()?;
+    Ok(ENDPOINTS.get_or_init(|| endpoints))
+}
+
+pub fn list_settings(output_format: &str) -> Result<()> {
+    let settings = Settings::load()?;
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&settings)?),
+        _ => {
+            for key in KEYS {
+                if let Some(value) = settings.get(key)? {
+                    println!("{} = {}", key, value);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn get_setting(key: &str) -> Result<()> {
+    if let Some(value) = Settings::load()?.get(key)? {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+/// Changes a setting, or resets it to its default when `value` is `None`.
+pub fn set_setting(key: &str, value: Option<&str>) -> Result<()> {
+    let mut settings = Settings::load()?;
+    let before = settings.endpoints().ok();
+    settings.set(key, value)?;
+    let after = settings.endpoints();
+    if settings.cloud == Cloud::Custom {
+        // Only complain once both URLs had a chance to be set.
+        if let Err(err) = &after {
+            eprintln!("tdi: {}", err);
+        }
+    }
+    settings.save()?;
+
+    match value {
+        Some(value) => println!(
+            "tdi: set {} to {} for profile {}.",
+            key,
+            value,
+            get_profile()
+        ),
+        None => println!("tdi: reset {} for profile {}.", key, get_profile()),
+    }
+    if after.ok() != before {
+        println!("tdi: the sign in endpoints changed, run `tdi login` again.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clouds_switch_authority_and_graph_together() {
+        let mut settings = Settings::default();
+        settings.set("cloud", Some("usgov-dod")).unwrap();
+        let endpoints = settings.endpoints().unwrap();
+        assert_eq!(endpoints.authority, "https://login.microsoftonline.us");
+        assert_eq!(endpoints.graph_url(), "https://dod-graph.microsoft.us/v1.0");
+
+        settings.set("cloud", Some("custom")).unwrap();
+        assert!(settings.endpoints().is_err());
+        settings
+            .set("authority", Some("https://login.example.com/"))
+            .unwrap();
+        settings
+            .set("graph-endpoint", Some("https://graph.example.com"))
+            .unwrap();
+        assert_eq!(
+            settings.endpoints().unwrap().oauth_url("token"),
+            "https://login.example.com/common/oauth2/v2.0/token"
+        );
+        assert!(settings.set("authority", Some("http://insecure")).is_err());
+        assert!(settings.set("cloud", Some("mars")).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! CSV files from spreadsheets for `tdi import --format csv`, a task per
+//! row. The header names the columns, in any order and case:
+//!
+//! * `title`, the only one needed;
+//! * `list`, the list to add to, else the one of `--list`;
+//! * `due`, as `tdi add --due` takes it;
+//! * `importance`, "high", "normal" or "low";
+//! * `categories` and `checklist`, separated by ";";
+//! * `notes`.
+
+use anyhow::{bail, Result};
+use chrono_tz::Tz;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde_json::{json, Map};
+
+use crate::backup::{Backup, BackupList, BackupTask};
+use crate::dates::{self, DateTimeTimeZone};
+use crate::tasksv2::{ChecklistItem, TodoTask};
+
+const COLUMNS: [&str; 7] = [
+    "title",
+    "list",
+    "due",
+    "importance",
+    "categories",
+    "notes",
+    "checklist",
+];
+
+/// The tasks of a CSV file, with the row each came from.
+#[derive(Debug)]
+pub struct Rows {
+    pub backup: Backup,
+    /// The row numbers of the tasks, by list and task index.
+    pub rows: Vec<Vec<u64>>,
+}
+
+impl Rows {
+    /// The row a task of `backup` came from.
+    pub fn row(&self, task: &BackupTask) -> Option<u64> {
+        self.backup
+            .lists
+            .iter()
+            .zip(&self.rows)
+            .flat_map(|(list, rows)| list.tasks.iter().zip(rows))
+            .find(|(candidate, _)| std::ptr::eq(*candidate, task))
+            .map(|(_, row)| *row)
+    }
+}
+
+fn split(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The task of a row, checking every field.
+fn parse_row(columns: &[String], record: &StringRecord, tz: &Tz) -> Result<BackupTask> {
+    let get = |name: &str| {
+        columns
+            .iter()
+            .position(|column| column == name)
+            .and_then(|i| record.get(i))
+            .unwrap_or_default()
+    };
+    let title = get("title");
+    if title.is_empty() {
+        bail!("no title");
+    }
+
+    let mut fields = Map::new();
+    let due = get("due");
+    if !due.is_empty() {
+        let due = dates::parse_date(due, tz)?;
+        fields.insert(
+            "dueDateTime".to_string(),
+            json!(DateTimeTimeZone::day(&due)),
+        );
+    }
+    let importance = get("importance").to_lowercase();
+    match importance.as_str() {
+        "" => {}
+        "high" | "normal" | "low" => {
+            fields.insert("importance".to_string(), json!(importance));
+        }
+        _ => bail!(
+            "importance {:?} is not \"high\", \"normal\" or \"low\"",
+            get("importance")
+        ),
+    }
+    let categories = split(get("categories"));
+    if !categories.is_empty() {
+        fields.insert("categories".to_string(), json!(categories));
+    }
+    let notes = get("notes");
+    if !notes.is_empty() {
+        fields.insert(
+            "body".to_string(),
+            json!({ "content": notes, "contentType": "text" }),
+        );
+    }
+
+    let mut task = BackupTask::new(TodoTask::local("", title).with_changes(&fields));
+    task.checklist_items = split(get("checklist"))
+        .into_iter()
+        .map(|item| ChecklistItem {
+            display_name: item,
+            is_checked: false,
+            id: String::new(),
+            extra: Map::new(),
+        })
+        .collect();
+    Ok(task)
+}
+
+/// Reads every row into the list of its `list` column, else `default`.
+/// Nothing is returned unless every row is valid, the error lists those
+/// that are not.
+pub fn parse(input: &str, default: BackupList, tz: &Tz) -> Result<Rows> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(input.as_bytes());
+    let columns: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|column| column.to_lowercase())
+        .collect();
+    for column in &columns {
+        if !COLUMNS.contains(&column.as_str()) {
+            bail!(
+                "unknown column {:?}, the columns are {}",
+                column,
+                COLUMNS.join(", ")
+            );
+        }
+    }
+    if !columns.iter().any(|column| column == "title") {
+        bail!("no title column");
+    }
+
+    let mut rows = Rows {
+        backup: Backup::new(vec![default]),
+        rows: vec![Vec::new()],
+    };
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                errors.push(err.to_string());
+                continue;
+            }
+        };
+        let row = record.position().map_or(0, |position| position.line());
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let task = match parse_row(&columns, &record, tz) {
+            Ok(task) => task,
+            Err(err) => {
+                errors.push(format!("row {}: {}", row, err));
+                continue;
+            }
+        };
+
+        let list = columns
+            .iter()
+            .position(|column| column == "list")
+            .and_then(|i| record.get(i))
+            .filter(|list| !list.is_empty());
+        let lists = &mut rows.backup.lists;
+        let index = match list {
+            None => 0,
+            Some(name) => match lists.iter().position(|l| l.display_name == name) {
+                Some(index) => index,
+                None => {
+                    lists.push(BackupList {
+                        display_name: name.to_string(),
+                        wellknown_list_name: String::new(),
+                        tasks: Vec::new(),
+                    });
+                    rows.rows.push(Vec::new());
+                    lists.len() - 1
+                }
+            },
+        };
+        lists[index].tasks.push(task);
+        rows.rows[index].push(row);
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "{} invalid rows, nothing was imported:\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default() -> BackupList {
+        BackupList {
+            display_name: "Tasks".to_string(),
+            wellknown_list_name: "defaultList".to_string(),
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn maps_columns_and_lists() {
+        let input = "Title,List,Due,Importance,Categories,Notes,Checklist\n\
+            Book the room,,2024-03-10,High,Planning; Q2,\"Ask for the big one, with a screen\",\n\
+            ,,,,,,\n\
+            Send the agenda,Team,,,,,Goals;Budget ; Risks\n";
+        let rows = parse(input, default(), &Tz::UTC).unwrap();
+        let lists = &rows.backup.lists;
+        assert_eq!(lists.len(), 2);
+        assert_eq!(rows.rows, [vec![2], vec![4]]);
+
+        let room = &lists[0].tasks[0];
+        assert_eq!(room.task.title, "Book the room");
+        assert_eq!(room.task.importance, "high");
+        assert_eq!(room.task.extra["categories"], json!(["Planning", "Q2"]));
+        assert_eq!(
+            room.task.extra["body"]["content"],
+            "Ask for the big one, with a screen"
+        );
+        assert_eq!(rows.row(room), Some(2));
+
+        assert_eq!(lists[1].display_name, "Team");
+        let checklist: Vec<&str> = lists[1].tasks[0]
+            .checklist_items
+            .iter()
+            .map(|item| item.display_name.as_str())
+            .collect();
+        assert_eq!(checklist, ["Goals", "Budget", "Risks"]);
+    }
+
+    #[test]
+    fn reports_every_invalid_row() {
+        let input = "title,due,importance\n\
+            Fine,,\n\
+            Late,next week,\n\
+            ,2024-01-01,\n\
+            Loud,,urgent\n";
+        let err = parse(input, default(), &Tz::UTC).unwrap_err().to_string();
+        assert!(err.starts_with("3 invalid rows"), "{}", err);
+        for row in ["row 3:", "row 4: no title", "row 5: importance \"urgent\""] {
+            assert!(err.contains(row), "{}", err);
+        }
+        assert!(!err.contains("row 2"));
+
+        assert!(parse("title,owner\nA,me\n", default(), &Tz::UTC).is_err());
+        assert!(parse("list\nTeam\n", default(), &Tz::UTC).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! `tdi sync`: brings the offline store up to date using Graph delta
+//! queries, so only what changed since the last sync is downloaded.
+//!
+//! https://learn.microsoft.com/en-us/graph/delta-query-overview
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::batch::BatchRequest;
+use crate::cache::{Store, StoredList};
+use crate::conflicts::Conflicts;
+use crate::graph::{Collection, GraphClient, GraphError};
+use crate::journal::{Journal, Replay};
+use crate::lists::TodoList;
+use crate::tasksv2::TodoTask;
+
+/// Graph answers `410 Gone` when a delta token has expired.
+const DELTA_EXPIRED: u16 = 410;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListReport {
+    display_name: String,
+    /// The delta token had expired (or there was none) and everything was
+    /// downloaded again.
+    resynced: bool,
+    #[serde(flatten)]
+    changes: Changes,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncReport {
+    /// Offline changes pushed before downloading.
+    replay: Replay,
+    lists: ListReport,
+    tasks: Vec<ListReport>,
+}
+
+enum Delta<T> {
+    Upsert(T),
+    Removed(String),
+}
+
+pub fn sync(output_format: &str) -> Result<()> {
+    let client = GraphClient::new()?;
+    let mut store = Store::load()?;
+
+    let mut journal = Journal::load()?;
+    let mut conflicts = Conflicts::load()?;
+    let replay = journal.replay(&client, &mut store, &mut conflicts)?;
+    journal.save()?;
+    conflicts.save()?;
+    // The pushed creates left the store, which must not wait on the download.
+    store.save()?;
+
+    let report = sync_store(&client, &mut store, replay)?;
+    store.save()?;
+
+    match output_format {
+        "json" => println!("{}", serde_json::to_string(&report)?),
+        _ => print_report(&report),
+    }
+    Ok(())
+}
+
+/// Applies remote changes to `store` in place.
+fn sync_store(client: &GraphClient, store: &mut Store, replay: Replay) -> Result<SyncReport> {
+    let lists = sync_lists(client, store)?;
+
+    let requests = store
+        .lists
+        .iter()
+        .enumerate()
+        .map(|(i, stored)| BatchRequest::get(&i.to_string(), &tasks_delta_url(client, stored)))
+        .collect();
+    let responses = client.batch(requests)?;
+
+    let mut tasks = Vec::new();
+    for (stored, response) in store.lists.iter_mut().zip(responses) {
+        let mut resynced = stored.delta_link.is_none();
+        let first = if response.status == DELTA_EXPIRED {
+            resynced = true;
+            stored.delta_link = None;
+            client.get(&tasks_delta_url(client, stored))?
+        } else {
+            response.json()?
+        };
+        let (items, delta_link) = read_delta::<TodoTask>(client, first)?;
+
+        let changes = apply_tasks(&mut stored.tasks, items, resynced);
+        stored.delta_link = delta_link;
+        stored.synced_at = Some(Utc::now());
+        tasks.push(ListReport {
+            display_name: stored.list.display_name.clone(),
+            resynced,
+            changes,
+        });
+    }
+
+    Ok(SyncReport {
+        replay,
+        lists,
+        tasks,
+    })
+}
+
+fn sync_lists(client: &GraphClient, store: &mut Store) -> Result<ListReport> {
+    let mut resynced = store.delta_link.is_none();
+    let first = match &store.delta_link {
+        Some(link) => match client.get(link) {
+            Err(err) if GraphError::status_of(&err) == Some(DELTA_EXPIRED) => {
+                resynced = true;
+                client.get("/me/todo/lists/delta")?
+            }
+            page => page?,
+        },
+        None => client.get("/me/todo/lists/delta")?,
+    };
+    let (items, delta_link) = read_delta::<TodoList>(client, first)?;
+
+    let mut changes = Changes::default();
+    let mut previous = if resynced {
+        std::mem::take(&mut store.lists)
+    } else {
+        Vec::new()
+    };
+    for item in items {
+        match item {
+            Delta::Upsert(list) => {
+                let existing = match previous.iter().position(|s| s.list.id == list.id) {
+                    Some(i) => Some(previous.swap_remove(i)),
+                    None => store
+                        .lists
+                        .iter()
+                        .position(|s| s.list.id == list.id)
+                        .map(|i| store.lists.remove(i)),
+                };
+                match existing {
+                    Some(mut stored) => {
+                        if stored.list.display_name != list.display_name {
+                            changes.updated.push(list.display_name.clone());
+                        }
+                        stored.list = list;
+                        store.lists.push(stored);
+                    }
+                    None => {
+                        changes.added.push(list.display_name.clone());
+                        store.lists.push(StoredList::new(list));
+                    }
+                }
+            }
+            Delta::Removed(id) => {
+                if let Some(i) = store.lists.iter().position(|s| s.list.id == id) {
+                    changes
+                        .removed
+                        .push(store.lists.remove(i).list.display_name);
+                }
+            }
+        }
+    }
+    // A full resync returns every list, so whatever was not returned is gone.
+    changes
+        .removed
+        .extend(previous.into_iter().map(|s| s.list.display_name));
+
+    store.delta_link = delta_link;
+    store.synced_at = Some(Utc::now());
+    Ok(ListReport {
+        display_name: "Lists".to_string(),
+        resynced,
+        changes,
+    })
+}
+
+fn tasks_delta_url(client: &GraphClient, stored: &StoredList) -> String {
+    match &stored.delta_link {
+        Some(link) => client.relative_url(link).to_string(),
+        None => format!("/me/todo/lists/{}/tasks/delta", stored.list.id),
+    }
+}
+
+/// Follows `@odata.nextLink` until the final page, returning every change and
+/// the `@odata.deltaLink` to continue from next time.
+fn read_delta<T: DeserializeOwned>(
+    client: &GraphClient,
+    first: Collection<Value>,
+) -> Result<(Vec<Delta<T>>, Option<String>)> {
+    let mut items = Vec::new();
+    let mut page = first;
+    loop {
+        for value in page.value {
+            items.push(parse_delta(value)?);
+        }
+        match page.next_link {
+            Some(next_link) => page = client.get(&next_link)?,
+            None => break,
+        }
+    }
+    Ok((items, page.delta_link))
+}
+
+fn parse_delta<T: DeserializeOwned>(value: Value) -> Result<Delta<T>> {
+    if value.get("@removed").is_some() {
+        match value["id"].as_str() {
+            Some(id) => Ok(Delta::Removed(id.to_string())),
+            None => bail!("removed item without an id in delta response"),
+        }
+    } else {
+        Ok(Delta::Upsert(serde_json::from_value(value)?))
+    }
+}
+
+fn apply_tasks(tasks: &mut Vec<TodoTask>, items: Vec<Delta<TodoTask>>, full: bool) -> Changes {
+    let mut changes = Changes::default();
+    let mut previous = if full {
+        std::mem::take(tasks)
+    } else {
+        Vec::new()
+    };
+
+    for item in items {
+        match item {
+            Delta::Upsert(task) => {
+                let existing = match previous.iter().position(|t| t.id == task.id) {
+                    Some(i) => Some(previous.swap_remove(i)),
+                    None => tasks
+                        .iter()
+                        .position(|t| t.id == task.id)
+                        .map(|i| tasks.remove(i)),
+                };
+                match existing {
+                    Some(old) if old == task => {}
+                    Some(_) => changes.updated.push(task.title.clone()),
+                    None => changes.added.push(task.title.clone()),
+                }
+                tasks.push(task);
+            }
+            Delta::Removed(id) => {
+                if let Some(i) = tasks.iter().position(|t| t.id == id) {
+                    changes.removed.push(tasks.remove(i).title);
+                }
+            }
+        }
+    }
+    changes
+        .removed
+        .extend(previous.into_iter().map(|task| task.title));
+    changes
+}
+
+fn print_report(report: &SyncReport) {
+    let replay = &report.replay;
+    if !replay.pushed.is_empty() {
+        println!("Pushed {} offline change(s)", replay.pushed.len());
+        for title in &replay.pushed {
+            println!("  > {}", title);
+        }
+    }
+    if !replay.failed.is_empty() {
+        println!(
+            "{} offline change(s) could not be pushed and stay queued",
+            replay.failed.len()
+        );
+        for failure in &replay.failed {
+            println!("  ? {}", failure);
+        }
+    }
+    if !replay.conflicts.is_empty() {
+        println!(
+            "{} conflict(s), see `tdi conflicts`",
+            replay.conflicts.len()
+        );
+        for title in &replay.conflicts {
+            println!("  ! {}", title);
+        }
+    }
+
+    let reports = std::iter::once(&report.lists).chain(&report.tasks);
+    let mut changed = false;
+    for list in reports.filter(|list| !list.changes.is_empty()) {
+        changed = true;
+        println!(
+            "{}: {} added, {} updated, {} removed{}",
+            list.display_name,
+            list.changes.added.len(),
+            list.changes.updated.len(),
+            list.changes.removed.len(),
+            if list.resynced { " (full resync)" } else { "" }
+        );
+        for title in &list.changes.added {
+            println!("  + {}", title);
+        }
+        for title in &list.changes.updated {
+            println!("  ~ {}", title);
+        }
+        for title in &list.changes.removed {
+            println!("  - {}", title);
+        }
+    }
+    if !changed {
+        println!("tdi: everything is up to date.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn task(id: &str, title: &str) -> TodoTask {
+        serde_json::from_value(json!({
+            "id": id,
+            "title": title,
+            "status": "notStarted",
+            "importance": "normal",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_removed_items() {
+        let item = json!({ "id": "1", "@removed": { "reason": "deleted" } });
+        assert!(matches!(parse_delta::<TodoTask>(item).unwrap(), Delta::Removed(id) if id == "1"));
+    }
+
+    #[test]
+    fn applies_incremental_changes() {
+        let mut tasks = vec![task("1", "one"), task("2", "two")];
+        let items = vec![
+            Delta::Upsert(task("2", "two, renamed")),
+            Delta::Upsert(task("3", "three")),
+            Delta::Removed("1".to_string()),
+            Delta::Removed("unknown".to_string()),
+        ];
+
+        let changes = apply_tasks(&mut tasks, items, false);
+        assert_eq!(changes.added, vec!["three"]);
+        assert_eq!(changes.updated, vec!["two, renamed"]);
+        assert_eq!(changes.removed, vec!["one"]);
+        assert_eq!(tasks.len(), 2);
+    }
+
+    #[test]
+    fn full_resync_removes_missing_tasks() {
+        let mut tasks = vec![task("1", "one"), task("2", "two")];
+        let items = vec![Delta::Upsert(task("2", "two"))];
+
+        let changes = apply_tasks(&mut tasks, items, true);
+        assert!(changes.added.is_empty());
+        assert!(changes.updated.is_empty());
+        assert_eq!(changes.removed, vec!["one"]);
+        assert_eq!(tasks.len(), 1);
+    }
+}
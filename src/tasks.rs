@@ -1,14 +1,15 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
+use anyhow::Result;
 use chrono::{serde::ts_seconds, DateTime, Utc};
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use std::io::Result;
 
 use crate::auth::*;
 use crate::cli::Cli;
 use crate::cli::Commands::*;
+use crate::tasksv2;
 use crate::user;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,19 +21,6 @@ pub struct Task {
     pub updated_at: DateTime<Utc>,
 }
 
-impl Task {
-    pub fn new(id: u32, text: String) -> Task {
-        let updated_at: DateTime<Utc> = Utc::now();
-        let state = "todo".to_string();
-        Task {
-            text,
-            state,
-            id,
-            updated_at,
-        }
-    }
-}
-
 pub fn show_tasks(json: &bool) -> Result<()> {
     // let token = read_access_token();
     // let client = Graph::new(&token);
@@ -51,27 +39,6 @@ pub fn show_tasks(json: &bool) -> Result<()> {
     Ok(())
 }
 
-pub fn add_task(new_task: &String) -> Result<()> {
-    let task = Task::new(99, new_task.to_string());
-    println!("Adding new task: {:?}", task);
-    Ok(())
-}
-
-pub fn complete_task(id: &u32) -> Result<()> {
-    println!("Completing task: {}", id);
-    Ok(())
-}
-
-pub fn reopen_task(id: &u32) -> Result<()> {
-    println!("Reopening task: {}", id);
-    Ok(())
-}
-
-pub fn delete_task(id: &u32) -> Result<()> {
-    println!("Deleting task: {}", id);
-    Ok(())
-}
-
 fn collect_tasks() -> Result<Vec<Task>> {
     let tasks = vec![];
     Ok(tasks)
@@ -82,25 +49,32 @@ pub fn interactive() -> Result<()> {
     let command = rl.readline("tdi>>");
 
     loop {
-        match command {
-            Ok(ref command) => {
-                let args: Vec<&str> = command.split_whitespace().collect();
-                let command = Cli::try_parse_from(args).expect("unable to parse");
-                match &command.command {
-                    Some(Login {}) => login(),
-                    Some(Me { output_format }) => user::show_me(output_format),
-                    Some(Show { json }) => show_tasks(json),
-                    Some(Add { task }) => add_task(task),
-                    Some(Complete { id }) => complete_task(id),
-                    Some(Reopen { id }) => reopen_task(id),
-                    Some(Delete { id }) => delete_task(id),
-                    _ => {
-                        println!("command is {:?}", command);
-                        return Ok(());
-                    }
-                }?;
-            }
-            Err(_) => {}
+        if let Ok(ref command) = command {
+            let args: Vec<&str> = command.split_whitespace().collect();
+            let command = Cli::try_parse_from(args).expect("unable to parse");
+            match &command.command {
+                Some(Login {
+                    encrypt,
+                    manual,
+                    port,
+                    timeout,
+                }) => login(encrypt, manual, port, timeout),
+                Some(Me { output_format }) => user::show_me(output_format),
+                Some(Show { json }) => show_tasks(json),
+                Some(Add {
+                    task,
+                    list_id,
+                    due,
+                    reminder,
+                }) => tasksv2::add_task(task, list_id, due, reminder, &command.offline),
+                Some(Complete { ids }) => tasksv2::complete_tasks(ids, &command.offline),
+                Some(Reopen { ids }) => tasksv2::reopen_tasks(ids, &command.offline),
+                Some(Delete { ids }) => tasksv2::delete_tasks(ids, &command.offline),
+                _ => {
+                    println!("command is {:?}", command);
+                    return Ok(());
+                }
+            }?;
         }
     }
 
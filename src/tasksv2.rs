@@ -1,167 +1,767 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
-use directories::ProjectDirs;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use cli_table::{print_stdout, Cell, Style, Table};
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
-use std::io::Result;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
 
-use crate::auth::read_access_token;
+use crate::batch::{BatchRequest, BatchResponse};
+use crate::cache::{self, Store};
+use crate::dates::{self, DateTimeTimeZone};
+use crate::graph::{Collection, GraphClient};
+use crate::helper::get_config_dir;
+use crate::history::{Change, DeletedTask, History};
+use crate::journal::{record_offline, Operation, OperationKind, LOCAL_ID_PREFIX};
+use crate::lists::{fetch_todo_lists, get_real_list_id};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct TodoTasks {
-    importance: String,
-    status: String,
-    title: String,
-    id: String,
+pub struct TodoTask {
+    pub importance: String,
+    pub status: String,
+    pub title: String,
+    pub id: String,
+    /// Every other property Graph returned, kept so cached and exported
+    /// tasks are complete.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TodoTaskResponse {
-    value: Vec<TodoTasks>,
+impl TodoTask {
+    /// A task created offline, before Graph has assigned it an id.
+    pub fn local(id: &str, title: &str) -> TodoTask {
+        TodoTask {
+            importance: "normal".to_string(),
+            status: "notStarted".to_string(),
+            title: title.to_string(),
+            id: id.to_string(),
+            extra: Map::new(),
+        }
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.extra.get("@odata.etag").and_then(Value::as_str)
+    }
+
+    pub fn last_modified(&self) -> Option<&str> {
+        self.extra
+            .get("lastModifiedDateTime")
+            .and_then(Value::as_str)
+    }
+
+    /// The day the task is due.
+    pub fn due(&self) -> Option<String> {
+        dates::display_day(self.extra.get("dueDateTime"))
+    }
+
+    /// Returns a copy with the given Graph fields overwritten.
+    pub fn with_changes(&self, changes: &Map<String, Value>) -> TodoTask {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(map) = value.as_object_mut() {
+            map.extend(changes.clone());
+        }
+        serde_json::from_value(value).unwrap_or_else(|_| self.clone())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct TodoListIdCache {
-    display_name: String,
-    id: String,
-    easy_id: String,
+pub struct ChecklistItem {
+    pub display_name: String,
+    pub is_checked: bool,
+    pub id: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A link from a task to where it came from, e.g. an email or a file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct TaskIdCache {
-    display_name: String,
-    id: String,
-    easy_id: String,
+pub struct LinkedResource {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
 }
 
-pub fn get_todo_tasks(output_format: &str, display_all: &bool, id: &u16) -> Result<()> {
-    let token = read_access_token();
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
+/// The fields of a task that can be sent when creating it.
+pub fn writable_fields(task: &TodoTask) -> Map<String, Value> {
+    const WRITABLE: [&str; 11] = [
+        "title",
+        "status",
+        "importance",
+        "body",
+        "categories",
+        "dueDateTime",
+        "startDateTime",
+        "reminderDateTime",
+        "isReminderOn",
+        "recurrence",
+        "completedDateTime",
+    ];
+    let value = serde_json::to_value(task).unwrap_or_default();
+    WRITABLE
+        .iter()
+        .filter_map(|field| value.get(*field).map(|v| (field.to_string(), v.clone())))
+        .filter(|(_, v)| !v.is_null())
+        .collect()
+}
 
-    let _res =
-        rt.block_on(async { get_todo_tasks_async(output_format, display_all, token, id).await });
+/// The tasks of one list, as shown by `tdi tasks`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListTasks {
+    display_name: String,
+    id: String,
+    value: Vec<TodoTask>,
+}
 
-    Ok(())
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskIdCache {
+    pub display_name: String,
+    pub id: String,
+    pub easy_id: String,
+    #[serde(default)]
+    pub list_id: String,
 }
 
-async fn get_todo_tasks_async(
+pub fn get_todo_tasks(
     output_format: &str,
     display_all: &bool,
-    token: String,
-    id: &u16,
+    id: &Option<u16>,
+    offline: &bool,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
-
-    let real_id = get_real_list_id(id);
-
-    let response = client
-        .get(format!(
-            "https://graph.microsoft.com/v1.0/me/todo/lists/{real_id}/tasks",
-            real_id = real_id
-        ))
-        .bearer_auth(token)
-        .send()
-        .await
-        .unwrap();
-
-    //dbg!("response: {:?}", &response);
-    let body = response.text().await.unwrap();
-    //dbg!("body: {:?}", &body);
-    let todo_tasks_response: TodoTaskResponse = serde_json::from_str(&body).unwrap();
-    //dbg!("todo_lists: {:?}", &todo_tasks_response);
+    let todo_tasks = match cache::online(*offline, || fetch_tasks(id))? {
+        Some(todo_tasks) => todo_tasks,
+        None => cached_tasks(id)?,
+    };
 
     let mut list_counter = 0i16;
     let mut todo_task_id_cache: Vec<TaskIdCache> = Vec::new();
 
-    for task in &todo_tasks_response.value {
-        list_counter += 1;
-        todo_task_id_cache.push(TaskIdCache {
-            display_name: task.title.clone(),
-            id: task.id.clone(),
-            easy_id: list_counter.to_string(),
-        });
+    for list in &todo_tasks {
+        for task in &list.value {
+            list_counter += 1;
+            todo_task_id_cache.push(TaskIdCache {
+                display_name: task.title.clone(),
+                id: task.id.clone(),
+                easy_id: list_counter.to_string(),
+                list_id: list.id.clone(),
+            });
+        }
     }
 
     match output_format {
-        "json" => as_json(todo_tasks_response, &display_all),
-        "table" => as_table(todo_tasks_response, &display_all),
-        "lines" => as_lines(todo_tasks_response, &display_all),
-        _ => as_lines(todo_tasks_response, &display_all),
+        "json" => as_json(&todo_tasks, display_all),
+        "table" => as_table(&todo_tasks, display_all),
+        "lines" => as_lines(&todo_tasks, display_all),
+        _ => as_lines(&todo_tasks, display_all),
     }
 
-    let _result = serde_json::to_writer_pretty(
-        std::fs::File::create(get_config_dir() + "/tasks_cache.json").unwrap(),
+    std::fs::create_dir_all(get_config_dir())?;
+    serde_json::to_writer_pretty(
+        std::fs::File::create(get_config_dir() + "/tasks_cache.json")?,
         &todo_task_id_cache,
-    );
+    )?;
+
+    Ok(())
+}
+
+/// Fetches the tasks of one or every list from Graph and updates the store.
+fn fetch_tasks(id: &Option<u16>) -> Result<Vec<ListTasks>> {
+    let client = GraphClient::new()?;
+    let mut store = Store::load()?;
+
+    let todo_tasks = match id {
+        Some(id) => {
+            let real_id = get_real_list_id(id)?;
+            let tasks: Vec<TodoTask> =
+                client.get_all(&format!("/me/todo/lists/{}/tasks", real_id))?;
+            // So that `--offline` has the list without `tdi lists` first.
+            if store.list(&real_id).is_none() {
+                store.add_list(client.get(&format!("/me/todo/lists/{}", real_id))?);
+            }
+            store.set_tasks(&real_id, tasks.clone());
+            vec![ListTasks {
+                display_name: String::new(),
+                id: real_id,
+                value: tasks,
+            }]
+        }
+        None => {
+            let todo_tasks = get_all_lists_tasks(&client, &mut store)?;
+            for list in &todo_tasks {
+                store.set_tasks(&list.id, list.value.clone());
+            }
+            todo_tasks
+        }
+    };
+
+    store.save()?;
+    Ok(todo_tasks)
+}
+
+/// Reads the tasks of one or every list from the store.
+fn cached_tasks(id: &Option<u16>) -> Result<Vec<ListTasks>> {
+    let store = Store::load_offline()?;
+    let lists: Vec<_> = match id {
+        Some(id) => {
+            let real_id = get_real_list_id(id)?;
+            vec![store
+                .list(&real_id)
+                .ok_or_else(|| anyhow!("list {} is not in the offline data", id))?]
+        }
+        None => store.lists.iter().collect(),
+    };
+
+    let oldest = lists
+        .iter()
+        .map(|stored| stored.synced_at)
+        .min()
+        .unwrap_or(store.synced_at);
+    cache::print_age(oldest);
+
+    Ok(lists
+        .into_iter()
+        .map(|stored| ListTasks {
+            display_name: if id.is_some() {
+                String::new()
+            } else {
+                stored.list.display_name.clone()
+            },
+            id: stored.list.id.clone(),
+            value: stored.tasks.clone(),
+        })
+        .collect())
+}
+
+/// Fetches the tasks of every list, one `$batch` round trip per 20 lists.
+fn get_all_lists_tasks(client: &GraphClient, store: &mut Store) -> Result<Vec<ListTasks>> {
+    let todo_lists = fetch_todo_lists(client)?;
+    store.set_lists(todo_lists.clone());
+    let requests = todo_lists
+        .iter()
+        .enumerate()
+        .map(|(i, list)| {
+            BatchRequest::get(&i.to_string(), &format!("/me/todo/lists/{}/tasks", list.id))
+        })
+        .collect();
+
+    let responses = client.batch(requests)?;
+    let mut all_tasks = Vec::new();
+    for (list, response) in todo_lists.into_iter().zip(responses) {
+        let page: Collection<TodoTask> = response
+            .json()
+            .map_err(|err| anyhow!("unable to fetch tasks for {}: {}", list.display_name, err))?;
+        let mut tasks = page.value;
+        if let Some(next_link) = page.next_link {
+            tasks.extend(client.get_all::<TodoTask>(&next_link)?);
+        }
+        all_tasks.push(ListTasks {
+            display_name: list.display_name,
+            id: list.id,
+            value: tasks,
+        });
+    }
+    Ok(all_tasks)
+}
+
+pub fn complete_tasks(ids: &[u32], offline: &bool) -> Result<()> {
+    update_tasks(
+        ids,
+        "completed",
+        Some(json!({ "status": "completed" })),
+        offline,
+    )
+}
 
+pub fn reopen_tasks(ids: &[u32], offline: &bool) -> Result<()> {
+    update_tasks(
+        ids,
+        "reopened",
+        Some(json!({ "status": "notStarted" })),
+        offline,
+    )
+}
+
+pub fn delete_tasks(ids: &[u32], offline: &bool) -> Result<()> {
+    update_tasks(ids, "deleted", None, offline)
+}
+
+/// The `dueDateTime` and `reminderDateTime` fields for dates given on the
+/// command line, in the zone dates are entered in.
+fn date_fields(due: &Option<String>, reminder: &Option<String>) -> Result<Map<String, Value>> {
+    let tz = dates::time_zone();
+    let mut fields = Map::new();
+    if let Some(due) = due {
+        let due = dates::parse_date(due, &tz)?;
+        fields.insert(
+            "dueDateTime".to_string(),
+            json!(DateTimeTimeZone::day(&due)),
+        );
+    }
+    if let Some(reminder) = reminder {
+        let reminder = dates::parse_date(reminder, &tz)?;
+        fields.insert(
+            "reminderDateTime".to_string(),
+            json!(DateTimeTimeZone::from_date_time(&reminder)),
+        );
+        fields.insert("isReminderOn".to_string(), json!(true));
+    }
+    Ok(fields)
+}
+
+pub fn add_task(
+    title: &str,
+    list_id: &Option<u16>,
+    due: &Option<String>,
+    reminder: &Option<String>,
+    offline: &bool,
+) -> Result<()> {
+    let mut body = date_fields(due, reminder)?;
+    body.insert("title".to_string(), json!(title));
+    let body = Value::Object(body);
+    let mut store = Store::load()?;
+    let real_id = match list_id {
+        Some(id) => get_real_list_id(id)?,
+        None => match store.default_list() {
+            Some(list) => list.id.clone(),
+            None => fetch_todo_lists(&GraphClient::new()?)?
+                .into_iter()
+                .find(|list| list.wellknown_list_name == "defaultList")
+                .map(|list| list.id)
+                .ok_or_else(|| anyhow!("no default list, pass one with --list-id"))?,
+        },
+    };
+
+    let url = format!("/me/todo/lists/{}/tasks", real_id);
+    match cache::online(*offline, || GraphClient::new()?.post(&url, &body))? {
+        Some(_) => println!("tdi: added {}", title),
+        None => {
+            let local_id = format!(
+                "{}{}",
+                LOCAL_ID_PREFIX,
+                Utc::now().timestamp_nanos_opt().unwrap_or_default()
+            );
+            let operation = Operation::new(
+                OperationKind::Create,
+                &real_id,
+                &local_id,
+                title,
+                object(body),
+                None,
+            );
+            record_offline(&mut store, operation)?;
+            println!("tdi: added {} (offline, run `tdi sync` to push)", title);
+        }
+    }
     Ok(())
 }
 
-fn as_json(todo_tasks: TodoTaskResponse, display_all: &bool) {
+pub fn edit_task(
+    id: &u32,
+    title: &Option<String>,
+    importance: &Option<String>,
+    due: &Option<String>,
+    reminder: &Option<String>,
+    offline: &bool,
+) -> Result<()> {
+    let mut changes = date_fields(due, reminder)?;
+    if let Some(title) = title {
+        changes.insert("title".to_string(), json!(title));
+    }
+    if let Some(importance) = importance {
+        if !["low", "normal", "high"].contains(&importance.as_str()) {
+            bail!("importance must be \"low\", \"normal\" or \"high\"");
+        }
+        changes.insert("importance".to_string(), json!(importance));
+    }
+    if changes.is_empty() {
+        bail!("nothing to change, pass --title, --importance, --due or --reminder");
+    }
+
+    update_tasks(&[*id], "edited", Some(Value::Object(changes)), offline)
+}
+
+/// Applies the same change to every task given by short id, batched. `None`
+/// deletes the tasks. Offline, the change is queued for `tdi sync`.
+fn update_tasks(ids: &[u32], verb: &str, changes: Option<Value>, offline: &bool) -> Result<()> {
+    let tasks = get_cached_tasks(ids)?;
+
+    // Tasks created offline only exist in the journal until they are synced.
+    let offline = *offline
+        || tasks
+            .iter()
+            .any(|task| task.id.starts_with(LOCAL_ID_PREFIX));
+    if cache::online(offline, || send_updates(&tasks, verb, &changes))?.is_some() {
+        return Ok(());
+    }
+
+    let mut store = Store::load()?;
+    for task in &tasks {
+        let base = store.task(&task.list_id, &task.id).cloned();
+        let title = changes
+            .as_ref()
+            .and_then(|changes| changes["title"].as_str())
+            .unwrap_or(&task.display_name)
+            .to_string();
+        let operation = match &changes {
+            Some(changes) => Operation::new(
+                OperationKind::Update,
+                &task.list_id,
+                &task.id,
+                &title,
+                object(changes.clone()),
+                base,
+            ),
+            None => Operation::new(
+                OperationKind::Delete,
+                &task.list_id,
+                &task.id,
+                &title,
+                Map::new(),
+                base,
+            ),
+        };
+        record_offline(&mut store, operation)?;
+        println!(
+            "tdi: {} [{}] {} (offline, run `tdi sync` to push)",
+            verb, task.easy_id, task.display_name
+        );
+    }
+    Ok(())
+}
+
+/// Sends the change for every task in one batch. Each write depends on a
+/// read of the task (and, before deleting it, of its checklist items) so the
+/// previous state can be kept in the history for `tdi undo`.
+fn send_updates(tasks: &[TaskIdCache], verb: &str, changes: &Option<Value>) -> Result<()> {
+    let mut requests = Vec::new();
+    for task in tasks {
+        let url = format!("/me/todo/lists/{}/tasks/{}", task.list_id, task.id);
+        let mut previous = format!("get-{}", task.easy_id);
+        requests.push(BatchRequest::get(&previous, &url));
+        if changes.is_none() {
+            let items = format!("items-{}", task.easy_id);
+            requests.push(
+                BatchRequest::get(&items, &format!("{}/checklistItems", url)).depends_on(&previous),
+            );
+            previous = items;
+        }
+        let write = match changes {
+            Some(changes) => BatchRequest::patch(&task.easy_id, &url, changes.clone()),
+            None => BatchRequest::delete(&task.easy_id, &url),
+        };
+        requests.push(write.depends_on(&previous));
+    }
+
+    let client = GraphClient::new()?;
+    let responses: HashMap<String, BatchResponse> = client
+        .batch(requests)?
+        .into_iter()
+        .map(|response| (response.id.clone(), response))
+        .collect();
+
+    let mut failed = 0;
+    let mut history = Vec::new();
+    for task in tasks {
+        let response = &responses[&task.easy_id];
+        if !response.is_success() {
+            failed += 1;
+            eprintln!(
+                "tdi: [{}] {}: {}",
+                task.easy_id,
+                task.display_name,
+                response.error()
+            );
+            continue;
+        }
+        println!("tdi: {} [{}] {}", verb, task.easy_id, task.display_name);
+
+        let Ok(before) = responses[&format!("get-{}", task.easy_id)].json::<TodoTask>() else {
+            continue;
+        };
+        history.push(match changes {
+            Some(changes) => Change::TaskUpdated {
+                list_id: task.list_id.clone(),
+                task_id: task.id.clone(),
+                title: before.title.clone(),
+                before: previous_values(&before, changes),
+            },
+            None => Change::TaskDeleted {
+                list_id: task.list_id.clone(),
+                deleted: DeletedTask {
+                    checklist_items: responses[&format!("items-{}", task.easy_id)]
+                        .json::<Collection<ChecklistItem>>()
+                        .map(|items| items.value)
+                        .unwrap_or_default(),
+                    task: before,
+                },
+            },
+        });
+    }
+    History::record(verb, history)?;
+
+    if failed > 0 {
+        bail!("{} of {} tasks could not be {}", failed, tasks.len(), verb);
+    }
+    Ok(())
+}
+
+/// The values `task` had for the fields in `changes`.
+pub fn previous_values(task: &TodoTask, changes: &Value) -> Map<String, Value> {
+    let task = serde_json::to_value(task).unwrap_or_default();
+    object(changes.clone())
+        .keys()
+        .map(|field| {
+            (
+                field.clone(),
+                task.get(field).cloned().unwrap_or(Value::Null),
+            )
+        })
+        .collect()
+}
+
+/// Moves tasks to another list. Graph cannot move tasks, so each is copied
+/// with its checklist items and the original deleted.
+pub fn move_tasks(ids: &[u32], to: &u16) -> Result<()> {
+    let tasks = get_cached_tasks(ids)?;
+    let to_list_id = get_real_list_id(to)?;
+    let client = GraphClient::new()?;
+
+    let mut requests = Vec::new();
+    for task in &tasks {
+        let url = format!("/me/todo/lists/{}/tasks/{}", task.list_id, task.id);
+        requests.push(BatchRequest::get(&format!("get-{}", task.easy_id), &url));
+        requests.push(BatchRequest::get(
+            &format!("items-{}", task.easy_id),
+            &format!("{}/checklistItems", url),
+        ));
+    }
+    let responses: HashMap<String, BatchResponse> = client
+        .batch(requests)?
+        .into_iter()
+        .map(|response| (response.id.clone(), response))
+        .collect();
+
+    let mut history = Vec::new();
+    let result = tasks
+        .iter()
+        .filter(|task| task.list_id != to_list_id)
+        .try_for_each(|task| -> Result<()> {
+            let original: TodoTask = responses[&format!("get-{}", task.easy_id)].json()?;
+            let checklist_items = responses[&format!("items-{}", task.easy_id)]
+                .json::<Collection<ChecklistItem>>()?
+                .value;
+
+            let new_task_id = create_task(&client, &to_list_id, &original, &checklist_items)?;
+            client.request(
+                Method::DELETE,
+                &format!("/me/todo/lists/{}/tasks/{}", task.list_id, task.id),
+                None,
+            )?;
+            println!("tdi: moved [{}] {}", task.easy_id, task.display_name);
+
+            history.push(Change::TaskMoved {
+                from_list_id: task.list_id.clone(),
+                to_list_id: to_list_id.clone(),
+                new_task_id,
+                deleted: DeletedTask {
+                    task: original,
+                    checklist_items,
+                },
+            });
+            Ok(())
+        });
+
+    History::record("moved", history)?;
+    result
+}
+
+/// Creates a copy of `task` and its checklist items in a list, returning the
+/// id of the new task.
+pub fn create_task(
+    client: &GraphClient,
+    list_id: &str,
+    task: &TodoTask,
+    checklist_items: &[ChecklistItem],
+) -> Result<String> {
+    let url = format!("/me/todo/lists/{}/tasks", list_id);
+    let created = client.post(&url, &Value::Object(writable_fields(task)))?;
+    let id = created["id"]
+        .as_str()
+        .ok_or_else(|| anyhow!("no id for the created task {}", task.title))?
+        .to_string();
+
+    let requests = checklist_items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            BatchRequest::post(
+                &i.to_string(),
+                &format!("{}/{}/checklistItems", url, id),
+                json!({ "displayName": item.display_name, "isChecked": item.is_checked }),
+            )
+        })
+        .collect();
+    // The task is there now, so it is returned even without its checklist.
+    let responses = client.batch(requests).unwrap_or_else(|err| {
+        eprintln!(
+            "tdi: unable to copy the checklist of {}: {}",
+            task.title, err
+        );
+        Vec::new()
+    });
+    for response in responses {
+        if !response.is_success() {
+            eprintln!(
+                "tdi: unable to copy a checklist item of {}: {}",
+                task.title,
+                response.error()
+            );
+        }
+    }
+    Ok(id)
+}
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// The tasks with the ids of the last `tdi tasks`, each once however many
+/// times it is given, as batch requests are named after them.
+fn get_cached_tasks(ids: &[u32]) -> Result<Vec<TaskIdCache>> {
+    let mut seen = HashSet::new();
+    ids.iter()
+        .filter(|id| seen.insert(**id))
+        .map(get_cached_task)
+        .collect()
+}
+
+pub fn get_cached_task(easy_id: &u32) -> Result<TaskIdCache> {
+    let todo_task_id_cache: Vec<TaskIdCache> = serde_json::from_reader(
+        std::fs::File::open(get_config_dir() + "/tasks_cache.json")
+            .map_err(|_| anyhow!("no cached task ids, run `tdi tasks` first"))?,
+    )?;
+
+    todo_task_id_cache
+        .into_iter()
+        .find(|task| task.easy_id == easy_id.to_string() && !task.list_id.is_empty())
+        .ok_or_else(|| anyhow!("no task with id {}, run `tdi tasks` to refresh", easy_id))
+}
+
+fn visible<'a>(tasks: &'a [TodoTask], display_all: &bool) -> Vec<&'a TodoTask> {
+    tasks
+        .iter()
+        .filter(|task| *display_all || task.status != "completed")
+        .collect()
+}
+
+fn as_json(todo_tasks: &[ListTasks], display_all: &bool) {
     if *display_all {
         let json = serde_json::to_string(&todo_tasks);
         println!("{}", json.unwrap());
     } else {
         // Filter out the completed tasks
+        let lists: Vec<_> = todo_tasks
+            .iter()
+            .map(|list| {
+                json!({
+                    "displayName": list.display_name,
+                    "id": list.id,
+                    "value": visible(&list.value, display_all),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&lists).unwrap());
     }
 }
 
-fn as_table(todo_tasks: TodoTaskResponse, display_all: &bool) {
-    // TODO
-    // if *display_all {
-    // } else {
-    // }
-}
-
-fn as_lines(todo_tasks: TodoTaskResponse, display_all: &bool) {
+fn as_table(todo_tasks: &[ListTasks], display_all: &bool) {
     let mut list_counter = 0i16;
+    let mut rows = Vec::new();
 
-    if *display_all {
-        for task in &todo_tasks.value {
+    for list in todo_tasks {
+        for task in &list.value {
             list_counter += 1;
-            println!(
-                "[{}] {} // ({}:{})",
-                list_counter, &task.title, &task.importance, &task.status
-            );
-        }
-    } else {
-        // filter out completed
-        for task in &todo_tasks.value {
-            list_counter += 1;
-            if task.status != "completed" {
-                println!(
-                    "[{}] {} // ({})",
-                    list_counter, &task.title, &task.importance
-                );
+            if *display_all || task.status != "completed" {
+                rows.push(vec![
+                    list_counter.cell(),
+                    list.display_name.clone().cell(),
+                    task.title.clone().cell(),
+                    task.importance.clone().cell(),
+                    task.status.clone().cell(),
+                    task.due().unwrap_or_default().cell(),
+                ]);
             }
         }
     }
-}
 
-fn get_config_dir() -> String {
-    let proj_dirs = ProjectDirs::from("com", "microsofthackathons", "tdi");
-    let config_dir = proj_dirs.unwrap().config_dir().to_path_buf();
-    config_dir.into_os_string().into_string().unwrap()
+    let table = rows.table().title(vec![
+        "ID".cell().bold(true),
+        "List".cell().bold(true),
+        "Title".cell().bold(true),
+        "Importance".cell().bold(true),
+        "Status".cell().bold(true),
+        "Due".cell().bold(true),
+    ]);
+    if let Err(e) = print_stdout(table) {
+        println!("{:?}", e);
+    }
 }
 
-fn get_real_list_id(easy_id: &u16) -> String {
-    let mut real_id = String::new();
-    let todo_list_id_cache: Vec<TodoListIdCache> = serde_json::from_reader(
-        std::fs::File::open(get_config_dir() + "/lists_cache.json").unwrap(),
-    )
-    .unwrap();
+fn as_lines(todo_tasks: &[ListTasks], display_all: &bool) {
+    let due = |task: &TodoTask| {
+        task.due()
+            .map(|due| format!(" due {}", due))
+            .unwrap_or_default()
+    };
+    let mut list_counter = 0i16;
 
-    for todo_list in &todo_list_id_cache {
-        if todo_list.easy_id == easy_id.to_string() {
-            real_id = todo_list.id.clone();
+    for list in todo_tasks {
+        if !list.display_name.is_empty() {
+            println!("# {}", list.display_name);
+        }
+        if *display_all {
+            for task in &list.value {
+                list_counter += 1;
+                println!(
+                    "[{}] {} // ({}:{}){}",
+                    list_counter,
+                    &task.title,
+                    &task.importance,
+                    &task.status,
+                    due(task)
+                );
+            }
+        } else {
+            // filter out completed
+            for task in &list.value {
+                list_counter += 1;
+                if task.status != "completed" {
+                    println!(
+                        "[{}] {} // ({}){}",
+                        list_counter,
+                        &task.title,
+                        &task.importance,
+                        due(task)
+                    );
+                }
+            }
         }
     }
-
-    real_id
 }
@@ -0,0 +1,338 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! Taskwarrior's JSON, as written by `task export` and read by
+//! `task import`, for `tdi export` and `tdi import`. See
+//! [`crate::cli::TASKWARRIOR_HELP`] for how fields map.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+
+use crate::backup::{Backup, BackupList, BackupTask};
+use crate::dates::DateTimeTimeZone;
+use crate::tasksv2::TodoTask;
+
+/// The namespace of the version 5 UUIDs derived from task ids.
+const NAMESPACE: [u8; 16] = [
+    0x6b, 0x3d, 0x1f, 0x52, 0x8e, 0x0a, 0x4c, 0x27, 0x9d, 0x41, 0x5a, 0xe2, 0x73, 0x0c, 0xb8, 0x96,
+];
+
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Task {
+    uuid: String,
+    description: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Annotation {
+    entry: String,
+    description: String,
+}
+
+/// The version 5 UUID of a task id, so that exporting a task again gives
+/// Taskwarrior the same task.
+fn uuid(id: &str) -> String {
+    let mut hash = Sha1::new();
+    hash.update(NAMESPACE);
+    hash.update(id.as_bytes());
+    let mut bytes: [u8; 16] = hash.finalize()[..16].try_into().unwrap();
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A Graph timestamp such as `createdDateTime` in Taskwarrior's format.
+fn timestamp(task: &TodoTask, field: &str) -> Option<String> {
+    let time: DateTime<Utc> = task.extra.get(field)?.as_str()?.parse().ok()?;
+    Some(time.format(TIME_FORMAT).to_string())
+}
+
+/// A `dateTimeTimeZone` field in Taskwarrior's format.
+fn date(task: &TodoTask, field: &str) -> Option<String> {
+    let date: DateTimeTimeZone = serde_json::from_value(task.extra.get(field)?.clone()).ok()?;
+    let time = date.in_zone(&Tz::UTC).ok()?;
+    Some(time.format(TIME_FORMAT).to_string())
+}
+
+fn parse_time(value: &str, tz: &Tz) -> Result<DateTime<Tz>> {
+    let time = NaiveDateTime::parse_from_str(value, TIME_FORMAT)
+        .map_err(|_| anyhow!("invalid date {}", value))?;
+    Ok(time.and_utc().with_timezone(tz))
+}
+
+fn format_task(task: &TodoTask, project: &str) -> Task {
+    let entry = timestamp(task, "createdDateTime");
+    let note = task
+        .extra
+        .get("body")
+        .and_then(|body| body["content"].as_str())
+        .unwrap_or_default();
+    let annotated = timestamp(task, "lastModifiedDateTime").or_else(|| entry.clone());
+    let annotations = note
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| Annotation {
+            entry: annotated.clone().unwrap_or_default(),
+            description: line.to_string(),
+        })
+        .collect();
+    let tags = task
+        .extra
+        .get("categories")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|category| category.replace(' ', "_"))
+        .collect();
+
+    Task {
+        uuid: uuid(&task.id),
+        description: task.title.clone(),
+        status: match task.status.as_str() {
+            "completed" => "completed",
+            _ => "pending",
+        }
+        .to_string(),
+        entry,
+        modified: timestamp(task, "lastModifiedDateTime"),
+        end: date(task, "completedDateTime"),
+        due: date(task, "dueDateTime"),
+        priority: match task.importance.as_str() {
+            "high" => Some("H".to_string()),
+            "low" => Some("L".to_string()),
+            _ => None,
+        },
+        project: Some(project.to_string()),
+        tags,
+        annotations,
+    }
+}
+
+/// Every task of the backup, as the JSON array `task import` reads.
+pub fn format(backup: &Backup) -> Result<String> {
+    let tasks: Vec<Task> = backup
+        .lists
+        .iter()
+        .flat_map(|list| {
+            list.tasks
+                .iter()
+                .map(|task| format_task(&task.task, &list.display_name))
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&tasks)? + "\n")
+}
+
+/// The To Do task for a Taskwarrior one; `None` for those To Do has no
+/// place for.
+fn parse_task(task: &Task, tz: &Tz) -> Result<Option<TodoTask>> {
+    let status = match task.status.as_str() {
+        "completed" => "completed",
+        // Recurring tasks are templates, their pending instances come along.
+        "deleted" | "recurring" => return Ok(None),
+        _ => "notStarted",
+    };
+    let mut fields = Map::new();
+    fields.insert("status".to_string(), json!(status));
+    let importance = match task.priority.as_deref() {
+        Some("H") => "high",
+        Some("L") => "low",
+        _ => "normal",
+    };
+    fields.insert("importance".to_string(), json!(importance));
+    if let Some(due) = &task.due {
+        let due = DateTimeTimeZone::day(&parse_time(due, tz)?);
+        fields.insert("dueDateTime".to_string(), json!(due));
+    }
+    if let (Some(end), "completed") = (&task.end, status) {
+        let end = DateTimeTimeZone::from_date_time(&parse_time(end, tz)?);
+        fields.insert("completedDateTime".to_string(), json!(end));
+    }
+    if !task.tags.is_empty() {
+        let categories: Vec<String> = task.tags.iter().map(|tag| tag.replace('_', " ")).collect();
+        fields.insert("categories".to_string(), json!(categories));
+    }
+    if !task.annotations.is_empty() {
+        let note: Vec<&str> = task
+            .annotations
+            .iter()
+            .map(|annotation| annotation.description.as_str())
+            .collect();
+        fields.insert(
+            "body".to_string(),
+            json!({ "content": note.join("\n"), "contentType": "text" }),
+        );
+    }
+    Ok(Some(
+        TodoTask::local("", &task.description).with_changes(&fields),
+    ))
+}
+
+/// Reads `task export` output, a JSON array or one task per line. Tasks go
+/// to `list`, or with `by_project` to a list named by their project if they
+/// have one.
+pub fn parse(input: &str, list: BackupList, by_project: bool, tz: &Tz) -> Result<Backup> {
+    let tasks: Vec<Task> = match input.trim_start().starts_with('[') {
+        true => serde_json::from_str(input)?,
+        false => input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                serde_json::from_str(line).map_err(|err| anyhow!("line {}: {}", n + 1, err))
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    let mut lists = vec![list];
+    let mut left_out = 0;
+    for task in &tasks {
+        let Some(todo) = parse_task(task, tz)? else {
+            left_out += 1;
+            continue;
+        };
+        let project = task.project.as_deref().filter(|_| by_project);
+        let index = match project {
+            Some(project) => match lists.iter().position(|l| l.display_name == project) {
+                Some(index) => index,
+                None => {
+                    lists.push(BackupList {
+                        display_name: project.to_string(),
+                        wellknown_list_name: String::new(),
+                        tasks: Vec::new(),
+                    });
+                    lists.len() - 1
+                }
+            },
+            None => 0,
+        };
+        lists[index].tasks.push(BackupTask::new(todo));
+    }
+    if left_out > 0 {
+        eprintln!(
+            "tdi: left out {} deleted or recurring template tasks",
+            left_out
+        );
+    }
+    lists.retain(|list| !list.tasks.is_empty());
+    Ok(Backup::new(lists))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list() -> BackupList {
+        BackupList {
+            display_name: "Tasks".to_string(),
+            wellknown_list_name: "defaultList".to_string(),
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn derives_stable_version_5_uuids() {
+        let first = uuid("AAMkAGI2");
+        assert_eq!(first, uuid("AAMkAGI2"));
+        assert_ne!(first, uuid("AAMkAGI3"));
+        assert_eq!(first.len(), 36);
+        assert_eq!(&first[14..15], "5");
+        assert!(matches!(&first[19..20], "8" | "9" | "a" | "b"));
+    }
+
+    #[test]
+    fn round_trips_tasks() {
+        let tz = Tz::Europe__Berlin;
+        let task: TodoTask = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Renew the passport",
+            "status": "completed",
+            "importance": "high",
+            "body": { "content": "Photos first\n\nthen the form", "contentType": "text" },
+            "categories": ["Paper work"],
+            "createdDateTime": "2024-03-01T08:00:00.1234567Z",
+            "completedDateTime": { "dateTime": "2024-03-09T23:00:00", "timeZone": "UTC" },
+            "dueDateTime": { "dateTime": "2024-03-10T00:00:00", "timeZone": "W. Europe Standard Time" },
+        }))
+        .unwrap();
+        let mut backup = Backup::new(vec![list()]);
+        backup.lists[0].display_name = "Errands".to_string();
+        backup.lists[0].tasks.push(BackupTask::new(task.clone()));
+
+        let exported = format(&backup).unwrap();
+        let warrior: Vec<Value> = serde_json::from_str(&exported).unwrap();
+        assert_eq!(warrior[0]["uuid"], json!(uuid("t1")));
+        assert_eq!(warrior[0]["due"], json!("20240309T230000Z"));
+        assert_eq!(warrior[0]["entry"], json!("20240301T080000Z"));
+        assert_eq!(warrior[0]["priority"], json!("H"));
+        assert_eq!(warrior[0]["tags"], json!(["Paper_work"]));
+        assert_eq!(warrior[0]["annotations"].as_array().unwrap().len(), 2);
+
+        let parsed = parse(&exported, list(), true, &tz).unwrap();
+        assert_eq!(parsed.lists.len(), 1);
+        assert_eq!(parsed.lists[0].display_name, "Errands");
+        let parsed = &parsed.lists[0].tasks[0].task;
+        assert_eq!(parsed.title, task.title);
+        assert_eq!(parsed.status, "completed");
+        assert_eq!(parsed.importance, "high");
+        assert_eq!(parsed.extra["categories"], task.extra["categories"]);
+        assert_eq!(parsed.extra["dueDateTime"], task.extra["dueDateTime"]);
+        assert_eq!(
+            parsed.extra["body"]["content"],
+            json!("Photos first\nthen the form")
+        );
+    }
+
+    #[test]
+    fn reads_one_task_per_line_and_leaves_out_deleted_ones() {
+        let input = r#"
+{"uuid":"a","description":"Back up the server","status":"pending","project":"ops","priority":"M"}
+{"uuid":"b","description":"Old","status":"deleted"}
+{"uuid":"c","description":"Stretch","status":"waiting","urgency":1.8}
+"#;
+        let backup = parse(input, list(), false, &Tz::UTC).unwrap();
+        assert_eq!(backup.lists.len(), 1);
+        let titles: Vec<&str> = backup.lists[0]
+            .tasks
+            .iter()
+            .map(|task| task.task.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Back up the server", "Stretch"]);
+        assert_eq!(backup.lists[0].tasks[0].task.importance, "normal");
+        assert!(parse("{not json", list(), false, &Tz::UTC).is_err());
+    }
+}
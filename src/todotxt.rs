@@ -0,0 +1,226 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! The todo.txt format, http://todotxt.org, for `tdi export` and
+//! `tdi import`. One task per line:
+//!
+//! * importance is the priority, "(A)" high, none normal and "(C)" low;
+//! * categories are `+project`s, with spaces as "_", except those starting
+//!   with "@", which are `@context`s;
+//! * the due date is `due:YYYY-MM-DD`;
+//! * completed tasks start with "x" and the day they were completed, and
+//!   keep their priority as `pri:A`.
+//!
+//! Dates are days in the zone dates are shown in. Lists, notes and
+//! checklists have no place in todo.txt and are left out.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde_json::{json, Map, Value};
+
+use crate::backup::{Backup, BackupList, BackupTask};
+use crate::dates::{self, DateTimeTimeZone};
+use crate::tasksv2::TodoTask;
+
+fn priority(importance: &str) -> Option<char> {
+    match importance {
+        "high" => Some('A'),
+        "low" => Some('C'),
+        _ => None,
+    }
+}
+
+fn importance(priority: char) -> &'static str {
+    match priority {
+        'A' => "high",
+        'B' => "normal",
+        _ => "low",
+    }
+}
+
+fn is_date(token: &str) -> bool {
+    NaiveDate::parse_from_str(token, "%Y-%m-%d").is_ok()
+}
+
+/// The day of a `dateTimeTimeZone` field in `tz`.
+fn day(task: &TodoTask, field: &str, tz: &Tz) -> Option<String> {
+    let date: DateTimeTimeZone = serde_json::from_value(task.extra.get(field)?.clone()).ok()?;
+    let time = date.in_zone(tz).ok()?;
+    Some(time.format("%Y-%m-%d").to_string())
+}
+
+/// The day a task was created in `tz`, from `createdDateTime`.
+fn created(task: &TodoTask, tz: &Tz) -> Option<String> {
+    let created = task.extra.get("createdDateTime")?.as_str()?;
+    let time: DateTime<Utc> = created.parse().ok()?;
+    Some(time.with_timezone(tz).format("%Y-%m-%d").to_string())
+}
+
+/// A task as a todo.txt line.
+pub fn format_task(task: &TodoTask, tz: &Tz) -> String {
+    let mut parts = Vec::new();
+    let priority = priority(&task.importance);
+    let completed = task.status == "completed";
+    if completed {
+        parts.push("x".to_string());
+        // A creation date can only follow a completion date.
+        if let Some(done) = day(task, "completedDateTime", tz) {
+            parts.push(done);
+            parts.extend(created(task, tz));
+        }
+    } else {
+        parts.extend(priority.map(|priority| format!("({})", priority)));
+        parts.extend(created(task, tz));
+    }
+    parts.push(task.title.clone());
+
+    let categories = task.extra.get("categories").and_then(Value::as_array);
+    for category in categories.into_iter().flatten().filter_map(Value::as_str) {
+        let tag = category.replace(' ', "_");
+        parts.push(match tag.starts_with('@') {
+            true => tag,
+            false => format!("+{}", tag),
+        });
+    }
+    parts.extend(
+        dates::display_day(task.extra.get("dueDateTime")).map(|due| format!("due:{}", due)),
+    );
+    if completed {
+        parts.extend(priority.map(|priority| format!("pri:{}", priority)));
+    }
+    parts.join(" ")
+}
+
+/// Every task of the backup, one line each.
+pub fn format(backup: &Backup, tz: &Tz) -> String {
+    backup
+        .lists
+        .iter()
+        .flat_map(|list| &list.tasks)
+        .map(|task| format_task(&task.task, tz) + "\n")
+        .collect()
+}
+
+/// A todo.txt line as a task; `None` for a blank line.
+pub fn parse_task(line: &str, tz: &Tz) -> Result<Option<TodoTask>> {
+    let mut tokens = line.split_whitespace().peekable();
+    if tokens.peek().is_none() {
+        return Ok(None);
+    }
+
+    let mut fields = Map::new();
+    let mut priority = None;
+    if tokens.peek() == Some(&"x") {
+        tokens.next();
+        fields.insert("status".to_string(), json!("completed"));
+        if let Some(done) = tokens.next_if(|token| is_date(token)) {
+            let done = dates::parse_date(done, tz)?;
+            fields.insert(
+                "completedDateTime".to_string(),
+                json!(DateTimeTimeZone::from_date_time(&done)),
+            );
+        }
+    } else if let Some(token) = tokens.next_if(|token| {
+        let bytes = token.as_bytes();
+        bytes.len() == 3 && bytes[0] == b'(' && bytes[1].is_ascii_uppercase() && bytes[2] == b')'
+    }) {
+        priority = token.chars().nth(1);
+    }
+    // Graph sets the creation date itself.
+    tokens.next_if(|token| is_date(token));
+
+    let mut words = Vec::new();
+    let mut categories = Vec::new();
+    for token in tokens {
+        if let Some(project) = token.strip_prefix('+').filter(|p| !p.is_empty()) {
+            categories.push(project.replace('_', " "));
+        } else if token.len() > 1 && token.starts_with('@') {
+            categories.push(token.replace('_', " "));
+        } else if let Some(due) = token.strip_prefix("due:") {
+            let due = dates::parse_date(due, tz)?;
+            fields.insert(
+                "dueDateTime".to_string(),
+                json!(DateTimeTimeZone::day(&due)),
+            );
+        } else if let Some(pri) = token.strip_prefix("pri:").filter(|p| p.len() == 1) {
+            priority = pri.chars().next();
+        } else {
+            words.push(token);
+        }
+    }
+
+    if words.is_empty() {
+        bail!("no task in the line {:?}", line);
+    }
+    if let Some(priority) = priority {
+        fields.insert("importance".to_string(), json!(importance(priority)));
+    }
+    if !categories.is_empty() {
+        fields.insert("categories".to_string(), json!(categories));
+    }
+    Ok(Some(
+        TodoTask::local("", &words.join(" ")).with_changes(&fields),
+    ))
+}
+
+/// Reads the lines of a todo.txt file into `list`.
+pub fn parse(input: &str, mut list: BackupList, tz: &Tz) -> Result<Backup> {
+    for (n, line) in input.lines().enumerate() {
+        let task = parse_task(line, tz).map_err(|err| anyhow!("line {}: {}", n + 1, err))?;
+        list.tasks.extend(task.map(BackupTask::new));
+    }
+    Ok(Backup::new(vec![list]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_tasks() {
+        let tz = Tz::Europe__Berlin;
+        let lines = [
+            "(A) Call mom +Family @phone due:2024-03-31",
+            "Water the plants",
+            "(C) Read +Side_Project",
+            "x 2024-03-10 File taxes due:2024-04-15 pri:A",
+        ];
+        for line in lines {
+            let task = parse_task(line, &tz).unwrap().unwrap();
+            assert_eq!(format_task(&task, &tz), line);
+        }
+    }
+
+    #[test]
+    fn maps_fields() {
+        let tz = Tz::America__Los_Angeles;
+        let task = parse_task("(B) 2024-01-02 Buy milk +Home_Errands due:2024-03-10", &tz)
+            .unwrap()
+            .unwrap();
+        assert_eq!(task.title, "Buy milk");
+        assert_eq!(task.importance, "normal");
+        assert_eq!(task.status, "notStarted");
+        assert_eq!(task.extra["categories"], json!(["Home Errands"]));
+        assert_eq!(
+            task.extra["dueDateTime"],
+            json!({ "dateTime": "2024-03-10T00:00:00", "timeZone": "Pacific Standard Time" })
+        );
+
+        assert!(parse_task("   ", &tz).unwrap().is_none());
+        assert!(parse_task("(A) +OnlyAProject", &tz).is_err());
+        assert!(parse_task("Pay rent due:soon", &tz).is_err());
+    }
+
+    #[test]
+    fn leaves_out_creation_dates_of_completed_tasks_without_a_completion_date() {
+        let tz = Tz::UTC;
+        let mut task = TodoTask::local("", "Done");
+        task.status = "completed".to_string();
+        task.extra
+            .insert("createdDateTime".to_string(), json!("2024-01-02T03:04:05Z"));
+        assert_eq!(format_task(&task, &tz), "x Done");
+        task.status = "notStarted".to_string();
+        assert_eq!(format_task(&task, &tz), "2024-01-02 Done");
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! The token file of a profile, `tdi.json` in its config directory. It holds
+//! the OAuth state including the refresh token, so it is only readable by the
+//! user, and can be encrypted with a passphrase.
+
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use graph_rs_sdk::oauth::OAuth;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::helper::get_config_dir;
+
+/// Where the passphrase of an encrypted token file is read from before
+/// prompting for it.
+pub const PASSPHRASE_ENV: &str = "TDI_PASSPHRASE";
+
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// An encrypted token file: the OAuth state sealed with XChaCha20-Poly1305,
+/// under a key derived from the passphrase with Argon2id.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sealed {
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    encrypted: Sealed,
+}
+
+pub fn path() -> String {
+    get_config_dir() + "/tdi.json"
+}
+
+/// Reads the OAuth state, `None` when the profile is not logged in.
+pub fn load() -> Result<Option<OAuth>> {
+    let path = path();
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    warn_if_readable_by_others(&path);
+
+    let data = match serde_json::from_slice::<EncryptedFile>(&data) {
+        Ok(file) => open(&file.encrypted, passphrase(false)?)?,
+        Err(_) => data,
+    };
+    let oauth = serde_json::from_slice(&data)
+        .map_err(|err| anyhow!("unable to parse {}: {}, run `tdi login`", path, err))?;
+    Ok(Some(oauth))
+}
+
+/// Writes the OAuth state, encrypted or not, readable only by the user.
+pub fn save(oauth: &OAuth, encrypt: bool) -> Result<()> {
+    let mut data = serde_json::to_vec(oauth)?;
+    if encrypt {
+        let sealed = seal(&data, passphrase(!is_encrypted())?)?;
+        data = serde_json::to_vec_pretty(&EncryptedFile { encrypted: sealed })?;
+    }
+
+    std::fs::create_dir_all(get_config_dir())?;
+    let tmp = path() + ".tmp";
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    std::fs::rename(tmp, path())?;
+    Ok(())
+}
+
+/// Takes the lock that is held while refreshing and rewriting the token
+/// file, waiting for another process holding it. Released when dropped.
+pub fn lock() -> Result<std::fs::File> {
+    std::fs::create_dir_all(get_config_dir())?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path() + ".lock")?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Whether the current token file is encrypted, so that rewriting it after a
+/// refresh keeps it that way.
+pub fn is_encrypted() -> bool {
+    std::fs::read(path())
+        .map(|data| serde_json::from_slice::<EncryptedFile>(&data).is_ok())
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            eprintln!(
+                "tdi: warning: {} can be read by other users, run `chmod 600 {}`",
+                path, path
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_path: &str) {}
+
+/// The passphrase from `TDI_PASSPHRASE`, else asked for once per process;
+/// `confirm` asks twice, for a passphrase that is about to be set.
+fn passphrase(confirm: bool) -> Result<&'static str> {
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = rpassword::prompt_password("tdi: token passphrase: ")?;
+            if confirm && rpassword::prompt_password("tdi: repeat passphrase: ")? != passphrase {
+                bail!("the passphrases do not match");
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        bail!("the token passphrase must not be empty");
+    }
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("unable to derive the token key: {}", err))?;
+    Ok(key)
+}
+
+fn seal(plaintext: &[u8], passphrase: &str) -> Result<Sealed> {
+    let params = Params::default();
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt, params.clone())?;
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(&key)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow!("unable to encrypt the token file"))?;
+    Ok(Sealed {
+        kdf: "argon2id".to_string(),
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    })
+}
+
+fn open(sealed: &Sealed, passphrase: &str) -> Result<Vec<u8>> {
+    if sealed.kdf != "argon2id" {
+        bail!("unknown key derivation {} in the token file", sealed.kdf);
+    }
+    let params = Params::new(sealed.m_cost, sealed.t_cost, sealed.p_cost, None)
+        .map_err(|err| anyhow!("invalid key derivation parameters: {}", err))?;
+    let key = derive_key(passphrase, &base64::decode(&sealed.salt)?, params)?;
+
+    let nonce = base64::decode(&sealed.nonce)?;
+    if nonce.len() != 24 {
+        bail!("invalid nonce in the token file");
+    }
+    XChaCha20Poly1305::new(&key)
+        .decrypt(
+            XNonce::from_slice(&nonce),
+            base64::decode(&sealed.ciphertext)?.as_slice(),
+        )
+        .map_err(|_| {
+            anyhow!(
+                "wrong passphrase for the token file, see {}",
+                PASSPHRASE_ENV
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_tokens_open_only_with_the_passphrase() {
+        let sealed = seal(b"{\"access_token\":null}", "correct horse").unwrap();
+        assert!(!sealed.ciphertext.contains("access_token"));
+        assert_eq!(
+            open(&sealed, "correct horse").unwrap(),
+            b"{\"access_token\":null}"
+        );
+        assert!(open(&sealed, "battery staple").is_err());
+    }
+}
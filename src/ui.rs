@@ -0,0 +1,1023 @@
+// Copyright (c) Microsoft Corporation - 2022.
+// Licensed under the MIT License.
+
+//! `tdi ui`, a full-screen interface: lists on the left, the tasks of the
+//! selected list and the details of the selected task, or the tasks as a
+//! kanban board. Reads and writes go through a [`Service`], Graph when
+//! online and the local store, read only, with `--offline`.
+
+use anyhow::{anyhow, bail, Result};
+use chrono_tz::Tz;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use reqwest::Method;
+use serde_json::{json, Map, Value};
+
+use crate::cache::Store;
+use crate::dates;
+use crate::graph::GraphClient;
+use crate::history::{Change, DeletedTask, History};
+use crate::links;
+use crate::lists::{fetch_todo_lists, TodoList};
+use crate::tasksv2::{previous_values, ChecklistItem, LinkedResource, TodoTask};
+
+/// The columns of the kanban board and the statuses that fall in each; a
+/// task moved to a column gets its first status.
+const COLUMNS: [(&str, &[&str]); 3] = [
+    ("To Do", &["notStarted", "waitingOnOthers", "deferred"]),
+    ("In Progress", &["inProgress"]),
+    ("Completed", &["completed"]),
+];
+
+const HELP: &str = "q quit  tab focus  a add  e edit  space done  d delete  ! importance  c completed  b board  < > column  r refresh";
+
+/// Where the lists and tasks shown come from and changes go to.
+pub trait Service {
+    fn lists(&mut self) -> Result<Vec<TodoList>>;
+    /// The tasks of a list, with their `checklistItems`.
+    fn tasks(&mut self, list_id: &str) -> Result<Vec<TodoTask>>;
+    fn add_task(&mut self, list_id: &str, title: &str) -> Result<TodoTask>;
+    /// Changes fields of `task`, `verb` says how for the history.
+    fn update_task(
+        &mut self,
+        list_id: &str,
+        task: &TodoTask,
+        verb: &str,
+        changes: Map<String, Value>,
+    ) -> Result<TodoTask>;
+    fn delete_task(&mut self, list_id: &str, task: &TodoTask) -> Result<()>;
+    fn check_item(
+        &mut self,
+        list_id: &str,
+        task_id: &str,
+        item_id: &str,
+        checked: bool,
+    ) -> Result<ChecklistItem>;
+}
+
+/// Microsoft Graph. Changes are kept in the history so `tdi undo` reverts
+/// them like those made on the command line.
+struct GraphService {
+    client: GraphClient,
+}
+
+impl Service for GraphService {
+    fn lists(&mut self) -> Result<Vec<TodoList>> {
+        fetch_todo_lists(&self.client)
+    }
+
+    fn tasks(&mut self, list_id: &str) -> Result<Vec<TodoTask>> {
+        self.client.get_all(&format!(
+            "/me/todo/lists/{}/tasks?$expand=checklistItems,linkedResources",
+            list_id
+        ))
+    }
+
+    fn add_task(&mut self, list_id: &str, title: &str) -> Result<TodoTask> {
+        let url = format!("/me/todo/lists/{}/tasks", list_id);
+        let task = self.client.post(&url, &json!({ "title": title }))?;
+        Ok(serde_json::from_value(task)?)
+    }
+
+    fn update_task(
+        &mut self,
+        list_id: &str,
+        task: &TodoTask,
+        verb: &str,
+        changes: Map<String, Value>,
+    ) -> Result<TodoTask> {
+        let url = format!("/me/todo/lists/{}/tasks/{}", list_id, task.id);
+        let changes = Value::Object(changes);
+        let updated = serde_json::from_value(self.client.patch(&url, &changes)?)?;
+        History::record(
+            verb,
+            vec![Change::TaskUpdated {
+                list_id: list_id.to_string(),
+                task_id: task.id.clone(),
+                title: task.title.clone(),
+                before: previous_values(task, &changes),
+            }],
+        )?;
+        Ok(updated)
+    }
+
+    fn delete_task(&mut self, list_id: &str, task: &TodoTask) -> Result<()> {
+        let url = format!("/me/todo/lists/{}/tasks/{}", list_id, task.id);
+        self.client.request(Method::DELETE, &url, None)?;
+        let mut task = task.clone();
+        History::record(
+            "deleted",
+            vec![Change::TaskDeleted {
+                list_id: list_id.to_string(),
+                deleted: DeletedTask {
+                    checklist_items: checklist(&task),
+                    task: {
+                        task.extra.remove("checklistItems");
+                        task
+                    },
+                },
+            }],
+        )
+    }
+
+    fn check_item(
+        &mut self,
+        list_id: &str,
+        task_id: &str,
+        item_id: &str,
+        checked: bool,
+    ) -> Result<ChecklistItem> {
+        let url = format!(
+            "/me/todo/lists/{}/tasks/{}/checklistItems/{}",
+            list_id, task_id, item_id
+        );
+        let item = self.client.patch(&url, &json!({ "isChecked": checked }))?;
+        Ok(serde_json::from_value(item)?)
+    }
+}
+
+/// The local store, for looking around with `--offline`.
+struct StoreService {
+    store: Store,
+}
+
+impl StoreService {
+    fn read_only<T>(&self) -> Result<T> {
+        bail!("changes cannot be made offline")
+    }
+}
+
+impl Service for StoreService {
+    fn lists(&mut self) -> Result<Vec<TodoList>> {
+        Ok(self
+            .store
+            .lists
+            .iter()
+            .map(|stored| stored.list.clone())
+            .collect())
+    }
+
+    fn tasks(&mut self, list_id: &str) -> Result<Vec<TodoTask>> {
+        self.store
+            .list(list_id)
+            .map(|stored| stored.tasks.clone())
+            .ok_or_else(|| anyhow!("the list is not in the offline data"))
+    }
+
+    fn add_task(&mut self, _: &str, _: &str) -> Result<TodoTask> {
+        self.read_only()
+    }
+
+    fn update_task(
+        &mut self,
+        _: &str,
+        _: &TodoTask,
+        _: &str,
+        _: Map<String, Value>,
+    ) -> Result<TodoTask> {
+        self.read_only()
+    }
+
+    fn delete_task(&mut self, _: &str, _: &TodoTask) -> Result<()> {
+        self.read_only()
+    }
+
+    fn check_item(&mut self, _: &str, _: &str, _: &str, _: bool) -> Result<ChecklistItem> {
+        self.read_only()
+    }
+}
+
+/// The checklist items that came expanded with a task.
+fn checklist(task: &TodoTask) -> Vec<ChecklistItem> {
+    task.extra
+        .get("checklistItems")
+        .and_then(|items| serde_json::from_value(items.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn column_of(task: &TodoTask) -> usize {
+    COLUMNS
+        .iter()
+        .position(|(_, statuses)| statuses.contains(&task.status.as_str()))
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Lists,
+    Tasks,
+    Details,
+}
+
+/// A line being typed, or a question, in the status bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Prompt {
+    Add(String),
+    Edit(String),
+    Delete,
+}
+
+pub struct App<S: Service> {
+    service: S,
+    lists: Vec<TodoList>,
+    /// The tasks of the selected list, in the order Graph returned them.
+    tasks: Vec<TodoTask>,
+    list: usize,
+    /// The selected row of the task pane or of the board column.
+    row: usize,
+    /// The selected checklist item.
+    item: usize,
+    column: usize,
+    focus: Focus,
+    kanban: bool,
+    show_completed: bool,
+    /// A task completed since the tasks were loaded, kept in the task pane
+    /// so that it stays selected and can be reopened.
+    just_completed: Option<String>,
+    prompt: Option<Prompt>,
+    status: String,
+    tz: Tz,
+    quit: bool,
+}
+
+impl<S: Service> App<S> {
+    pub fn new(service: S, tz: Tz) -> App<S> {
+        let mut app = App {
+            service,
+            lists: Vec::new(),
+            tasks: Vec::new(),
+            list: 0,
+            row: 0,
+            item: 0,
+            column: 0,
+            focus: Focus::Lists,
+            kanban: false,
+            show_completed: false,
+            just_completed: None,
+            prompt: None,
+            status: String::new(),
+            tz,
+            quit: false,
+        };
+        app.refresh();
+        app
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    fn refresh(&mut self) {
+        match self.service.lists() {
+            Ok(lists) => {
+                self.lists = lists;
+                self.list = self.list.min(self.lists.len().saturating_sub(1));
+                self.load_tasks();
+            }
+            Err(err) => self.status = format!("error: {}", err),
+        }
+    }
+
+    fn load_tasks(&mut self) {
+        self.tasks = match self.list_id() {
+            Some(list_id) => self.service.tasks(&list_id).unwrap_or_else(|err| {
+                self.status = format!("error: {}", err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        self.just_completed = None;
+        self.clamp();
+    }
+
+    fn list_id(&self) -> Option<String> {
+        self.lists.get(self.list).map(|list| list.id.clone())
+    }
+
+    /// Indices into `tasks` of the rows of the task pane, or of the selected
+    /// board column.
+    fn rows(&self) -> Vec<usize> {
+        self.column_rows(self.column)
+    }
+
+    fn column_rows(&self, column: usize) -> Vec<usize> {
+        (0..self.tasks.len())
+            .filter(|&i| {
+                let task = &self.tasks[i];
+                if self.kanban {
+                    column_of(task) == column
+                } else {
+                    self.show_completed
+                        || task.status != "completed"
+                        || self.just_completed.as_ref() == Some(&task.id)
+                }
+            })
+            .collect()
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.rows().get(self.row).copied()
+    }
+
+    fn selected_checklist(&self) -> Vec<ChecklistItem> {
+        self.selected()
+            .map(|i| checklist(&self.tasks[i]))
+            .unwrap_or_default()
+    }
+
+    fn clamp(&mut self) {
+        self.row = self.row.min(self.rows().len().saturating_sub(1));
+        self.item = self
+            .item
+            .min(self.selected_checklist().len().saturating_sub(1));
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if self.prompt.is_some() {
+            self.prompt_key(key);
+            return;
+        }
+        self.status.clear();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => self.cycle_focus(true),
+            KeyCode::BackTab => self.cycle_focus(false),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Left | KeyCode::Char('h') => {
+                if self.kanban && self.focus == Focus::Tasks && self.column > 0 {
+                    self.column -= 1;
+                    self.clamp();
+                } else if self.focus != Focus::Lists {
+                    self.cycle_focus(false);
+                }
+            }
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => {
+                if self.kanban && self.focus == Focus::Tasks {
+                    self.column = (self.column + 1).min(COLUMNS.len() - 1);
+                    self.clamp();
+                } else if self.focus != Focus::Details {
+                    self.cycle_focus(true);
+                }
+            }
+            KeyCode::Char('a') if self.list_id().is_some() => {
+                self.prompt = Some(Prompt::Add(String::new()))
+            }
+            KeyCode::Char('e') => {
+                if let Some(i) = self.selected() {
+                    self.prompt = Some(Prompt::Edit(self.tasks[i].title.clone()));
+                }
+            }
+            KeyCode::Char('d') if self.selected().is_some() => self.prompt = Some(Prompt::Delete),
+            KeyCode::Char(' ') | KeyCode::Char('x') => {
+                if self.focus == Focus::Details {
+                    self.toggle_item();
+                } else {
+                    self.toggle_completed();
+                }
+            }
+            KeyCode::Char('!') => self.toggle_importance(),
+            KeyCode::Char('>') if self.kanban => self.move_to_column(1),
+            KeyCode::Char('<') if self.kanban => self.move_to_column(-1),
+            KeyCode::Char('c') => {
+                self.show_completed = !self.show_completed;
+                self.clamp();
+            }
+            KeyCode::Char('b') => {
+                // Keep the selected task selected in the other view.
+                let selected = self.selected();
+                self.kanban = !self.kanban;
+                if self.focus == Focus::Details {
+                    self.focus = Focus::Tasks;
+                }
+                if let Some(i) = selected {
+                    self.column = column_of(&self.tasks[i]);
+                }
+                self.row = self
+                    .rows()
+                    .iter()
+                    .position(|&r| Some(r) == selected)
+                    .unwrap_or(0);
+                self.clamp();
+            }
+            KeyCode::Char('r') => {
+                self.refresh();
+                if self.status.is_empty() {
+                    self.status = "refreshed".to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = self.prompt.take() else {
+            return;
+        };
+        match (prompt, key.code) {
+            (_, KeyCode::Esc) => {}
+            (Prompt::Delete, KeyCode::Char('y')) => self.delete(),
+            (Prompt::Delete, _) => {}
+            (Prompt::Add(title), KeyCode::Enter) => self.add(title.trim()),
+            (Prompt::Edit(title), KeyCode::Enter) if !title.trim().is_empty() => {
+                let mut changes = Map::new();
+                changes.insert("title".to_string(), json!(title.trim()));
+                self.update("edited", changes);
+            }
+            (mut prompt, code) => {
+                if let Prompt::Add(text) | Prompt::Edit(text) = &mut prompt {
+                    match code {
+                        KeyCode::Char(c) => text.push(c),
+                        KeyCode::Backspace => {
+                            text.pop();
+                        }
+                        _ => {}
+                    }
+                }
+                self.prompt = Some(prompt);
+            }
+        }
+    }
+
+    fn cycle_focus(&mut self, forward: bool) {
+        let order: &[Focus] = if self.kanban {
+            &[Focus::Lists, Focus::Tasks]
+        } else {
+            &[Focus::Lists, Focus::Tasks, Focus::Details]
+        };
+        let at = order.iter().position(|&f| f == self.focus).unwrap_or(0);
+        let next = if forward {
+            at + 1
+        } else {
+            at + order.len() - 1
+        };
+        self.focus = order[next % order.len()];
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let step = |index: usize, len: usize| {
+            (index as isize + delta).clamp(0, len.saturating_sub(1) as isize) as usize
+        };
+        match self.focus {
+            Focus::Lists => {
+                let list = step(self.list, self.lists.len());
+                if list != self.list {
+                    self.list = list;
+                    self.row = 0;
+                    self.load_tasks();
+                }
+            }
+            Focus::Tasks => {
+                self.row = step(self.row, self.rows().len());
+                self.item = 0;
+            }
+            Focus::Details => self.item = step(self.item, self.selected_checklist().len()),
+        }
+    }
+
+    fn report(&mut self, result: Result<String>) {
+        self.status = match result {
+            Ok(message) => message,
+            Err(err) => format!("error: {}", err),
+        };
+    }
+
+    fn add(&mut self, title: &str) {
+        let Some(list_id) = self.list_id() else {
+            return;
+        };
+        if title.is_empty() {
+            return;
+        }
+        let result = self.service.add_task(&list_id, title).map(|task| {
+            self.tasks.push(task);
+            let added = self.tasks.len() - 1;
+            self.column = column_of(&self.tasks[added]);
+            self.row = self.rows().iter().position(|&i| i == added).unwrap_or(0);
+            format!("added {}", title)
+        });
+        self.report(result);
+    }
+
+    fn update(&mut self, verb: &str, changes: Map<String, Value>) {
+        let (Some(list_id), Some(i)) = (self.list_id(), self.selected()) else {
+            return;
+        };
+        // Graph does not expand the checklist in the response.
+        let items = self.tasks[i].extra.get("checklistItems").cloned();
+        let result = self
+            .service
+            .update_task(&list_id, &self.tasks[i], verb, changes)
+            .map(|mut updated| {
+                if let Some(items) = items {
+                    updated.extra.insert("checklistItems".to_string(), items);
+                }
+                let message = format!("{} {}", verb, updated.title);
+                self.tasks[i] = updated;
+                message
+            });
+        self.report(result);
+        self.clamp();
+    }
+
+    fn delete(&mut self) {
+        let (Some(list_id), Some(i)) = (self.list_id(), self.selected()) else {
+            return;
+        };
+        let result = self
+            .service
+            .delete_task(&list_id, &self.tasks[i])
+            .map(|_| format!("deleted {}", self.tasks.remove(i).title));
+        self.report(result);
+        self.clamp();
+    }
+
+    fn toggle_completed(&mut self) {
+        let Some(i) = self.selected() else {
+            return;
+        };
+        let (verb, status) = match self.tasks[i].status.as_str() {
+            "completed" => ("reopened", "notStarted"),
+            _ => ("completed", "completed"),
+        };
+        let mut changes = Map::new();
+        changes.insert("status".to_string(), json!(status));
+        let id = self.tasks[i].id.clone();
+        self.update(verb, changes);
+        // Stay on the task, in the column it went to on the board.
+        if self.tasks.get(i).is_some_and(|task| task.id == id) {
+            if self.tasks[i].status == "completed" {
+                self.just_completed = Some(id);
+            }
+            if self.kanban {
+                self.column = column_of(&self.tasks[i]);
+            }
+            self.row = self.rows().iter().position(|&r| r == i).unwrap_or(0);
+            self.clamp();
+        }
+    }
+
+    fn toggle_importance(&mut self) {
+        let Some(i) = self.selected() else {
+            return;
+        };
+        let importance = match self.tasks[i].importance.as_str() {
+            "high" => "normal",
+            _ => "high",
+        };
+        let mut changes = Map::new();
+        changes.insert("importance".to_string(), json!(importance));
+        self.update("edited", changes);
+    }
+
+    /// Moves the selected task to the next or previous board column.
+    fn move_to_column(&mut self, delta: isize) {
+        let Some(i) = self.selected() else {
+            return;
+        };
+        let column = column_of(&self.tasks[i]) as isize + delta;
+        if !(0..COLUMNS.len() as isize).contains(&column) {
+            return;
+        }
+        let column = column as usize;
+        let mut changes = Map::new();
+        changes.insert("status".to_string(), json!(COLUMNS[column].1[0]));
+        let id = self.tasks[i].id.clone();
+        self.update("edited", changes);
+        if self
+            .tasks
+            .get(i)
+            .is_some_and(|task| task.id == id && column_of(task) == column)
+        {
+            self.column = column;
+            self.row = self.rows().iter().position(|&r| r == i).unwrap_or(0);
+        }
+    }
+
+    fn toggle_item(&mut self) {
+        let (Some(list_id), Some(i)) = (self.list_id(), self.selected()) else {
+            return;
+        };
+        let mut items = checklist(&self.tasks[i]);
+        let Some(item) = items.get(self.item) else {
+            return;
+        };
+        let result = self
+            .service
+            .check_item(&list_id, &self.tasks[i].id, &item.id, !item.is_checked)
+            .map(|updated| {
+                let message = match updated.is_checked {
+                    true => format!("checked {}", updated.display_name),
+                    false => format!("unchecked {}", updated.display_name),
+                };
+                items[self.item] = updated;
+                self.tasks[i]
+                    .extra
+                    .insert("checklistItems".to_string(), json!(items));
+                message
+            });
+        self.report(result);
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [sidebar, body] =
+            Layout::horizontal([Constraint::Length(24), Constraint::Fill(1)]).areas(main);
+
+        let lists: Vec<ListItem> = self
+            .lists
+            .iter()
+            .map(|list| ListItem::new(list.display_name.clone()))
+            .collect();
+        self.draw_list(frame, sidebar, lists, "Lists", self.list, Focus::Lists);
+
+        if self.kanban {
+            let areas = Layout::horizontal([Constraint::Fill(1); COLUMNS.len()]).split(body);
+            for (column, (title, _)) in COLUMNS.iter().enumerate() {
+                let rows = self.column_rows(column);
+                let items = rows
+                    .iter()
+                    .map(|&i| self.task_item(&self.tasks[i]))
+                    .collect();
+                let selected = if column == self.column {
+                    self.row
+                } else {
+                    usize::MAX
+                };
+                self.draw_list(frame, areas[column], items, title, selected, Focus::Tasks);
+            }
+        } else {
+            let [tasks, details] =
+                Layout::horizontal([Constraint::Fill(3), Constraint::Fill(2)]).areas(body);
+            let items = self
+                .rows()
+                .iter()
+                .map(|&i| self.task_item(&self.tasks[i]))
+                .collect();
+            self.draw_list(frame, tasks, items, "Tasks", self.row, Focus::Tasks);
+            self.draw_details(frame, details);
+        }
+
+        match &self.prompt {
+            Some(prompt) => {
+                let line = match prompt {
+                    Prompt::Add(text) => format!("Add task: {}", text),
+                    Prompt::Edit(text) => format!("Title: {}", text),
+                    Prompt::Delete => "Delete this task? (y/n)".to_string(),
+                };
+                let width = line.chars().count() as u16;
+                frame.render_widget(Paragraph::new(line), status);
+                frame.set_cursor_position(Position::new(status.x + width, status.y));
+            }
+            None if !self.status.is_empty() => {
+                frame.render_widget(Paragraph::new(self.status.as_str()), status)
+            }
+            None => frame.render_widget(Paragraph::new(HELP).dim(), status),
+        }
+    }
+
+    fn block(&self, title: &str, focus: Focus) -> Block<'static> {
+        let block = Block::bordered().title(title.to_string());
+        match self.focus == focus {
+            true => block.border_style(Style::new().fg(Color::Cyan)),
+            false => block,
+        }
+    }
+
+    fn draw_list(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        items: Vec<ListItem>,
+        title: &str,
+        selected: usize,
+        focus: Focus,
+    ) {
+        let list = List::new(items)
+            .block(self.block(title, focus))
+            .highlight_style(Style::new().reversed());
+        let selected = (selected != usize::MAX).then_some(selected);
+        let mut state = ListState::default().with_selected(selected);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn task_item(&self, task: &TodoTask) -> ListItem<'static> {
+        let done = if task.status == "completed" { "x" } else { " " };
+        let important = if task.importance == "high" { "! " } else { "" };
+        let due = task
+            .due()
+            .map(|due| format!(" ({})", due))
+            .unwrap_or_default();
+        ListItem::new(format!("[{}] {}{}{}", done, important, task.title, due))
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: Rect) {
+        let block = self.block("Details", Focus::Details);
+        let Some(i) = self.selected() else {
+            frame.render_widget(block, area);
+            return;
+        };
+        let task = &self.tasks[i];
+        let mut lines = vec![
+            Line::from(task.title.clone()).bold(),
+            Line::from(format!("Status: {}", task.status)),
+            Line::from(format!("Importance: {}", task.importance)),
+        ];
+        if let Some(due) = task.due() {
+            lines.push(Line::from(format!("Due: {}", due)));
+        }
+        if task.extra.get("isReminderOn") == Some(&json!(true)) {
+            if let Some(reminder) = dates::display(task.extra.get("reminderDateTime"), &self.tz) {
+                lines.push(Line::from(format!("Reminder: {}", reminder)));
+            }
+        }
+        if let Some(categories) = task.extra.get("categories").and_then(Value::as_array) {
+            let names: Vec<&str> = categories.iter().filter_map(Value::as_str).collect();
+            if !names.is_empty() {
+                lines.push(Line::from(format!("Categories: {}", names.join(", "))));
+            }
+        }
+        let note = task
+            .extra
+            .get("body")
+            .and_then(|body| body["content"].as_str())
+            .unwrap_or_default();
+        if !note.trim().is_empty() {
+            lines.push(Line::default());
+            lines.extend(note.lines().map(|line| Line::from(line.to_string())));
+        }
+
+        let links: Vec<LinkedResource> = task
+            .extra
+            .get("linkedResources")
+            .and_then(|links| serde_json::from_value(links.clone()).ok())
+            .unwrap_or_default();
+        if !links.is_empty() {
+            lines.push(Line::default());
+            lines.push(Line::from("Links:"));
+            lines.extend(links.iter().map(|link| Line::from(links::describe(link))));
+        }
+
+        let items = checklist(task);
+        if !items.is_empty() {
+            lines.push(Line::default());
+            for (n, item) in items.iter().enumerate() {
+                let check = if item.is_checked { "x" } else { " " };
+                let line = Line::from(format!("[{}] {}", check, item.display_name));
+                lines.push(match self.focus == Focus::Details && n == self.item {
+                    true => line.reversed(),
+                    false => line,
+                });
+            }
+        }
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+}
+
+fn event_loop<S: Service>(terminal: &mut DefaultTerminal, app: &mut App<S>) -> Result<()> {
+    while !app.should_quit() {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                app.handle_key(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_app<S: Service>(service: S) -> Result<()> {
+    let mut app = App::new(service, dates::time_zone());
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
+
+pub fn run(offline: &bool) -> Result<()> {
+    if *offline {
+        run_app(StoreService {
+            store: Store::load_offline()?,
+        })
+    } else {
+        run_app(GraphService {
+            client: GraphClient::new()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::KeyModifiers;
+    use ratatui::Terminal;
+    use std::collections::HashMap;
+
+    /// Lists and tasks kept in memory.
+    #[derive(Default)]
+    struct FakeService {
+        lists: Vec<TodoList>,
+        tasks: HashMap<String, Vec<TodoTask>>,
+    }
+
+    impl FakeService {
+        fn new() -> FakeService {
+            let list = |id: &str, name: &str| TodoList {
+                display_name: name.to_string(),
+                is_owner: true,
+                is_shared: false,
+                id: id.to_string(),
+                wellknown_list_name: "none".to_string(),
+            };
+            let task = |value: Value| serde_json::from_value::<TodoTask>(value).unwrap();
+            let mut service = FakeService {
+                lists: vec![list("l1", "Groceries"), list("l2", "Work")],
+                ..FakeService::default()
+            };
+            service.tasks.insert(
+                "l1".to_string(),
+                vec![
+                    task(json!({
+                        "id": "t1", "title": "Milk", "status": "notStarted", "importance": "high",
+                        "dueDateTime": { "dateTime": "2024-03-10T00:00:00.0000000", "timeZone": "UTC" },
+                        "checklistItems": [
+                            { "id": "c1", "displayName": "Oat", "isChecked": false },
+                        ],
+                        "linkedResources": [
+                            { "id": "r1", "displayName": "Recipe", "webUrl": "https://example.com/r" },
+                        ],
+                    })),
+                    task(json!({
+                        "id": "t2", "title": "Bread", "status": "inProgress", "importance": "normal",
+                    })),
+                ],
+            );
+            service.tasks.insert("l2".to_string(), Vec::new());
+            service
+        }
+
+        fn task_mut(&mut self, list_id: &str, task_id: &str) -> &mut TodoTask {
+            self.tasks
+                .get_mut(list_id)
+                .and_then(|tasks| tasks.iter_mut().find(|task| task.id == task_id))
+                .unwrap()
+        }
+    }
+
+    impl Service for FakeService {
+        fn lists(&mut self) -> Result<Vec<TodoList>> {
+            Ok(self.lists.clone())
+        }
+
+        fn tasks(&mut self, list_id: &str) -> Result<Vec<TodoTask>> {
+            Ok(self.tasks[list_id].clone())
+        }
+
+        fn add_task(&mut self, list_id: &str, title: &str) -> Result<TodoTask> {
+            let task = TodoTask::local(&format!("new-{}", title), title);
+            self.tasks.get_mut(list_id).unwrap().push(task.clone());
+            Ok(task)
+        }
+
+        fn update_task(
+            &mut self,
+            list_id: &str,
+            task: &TodoTask,
+            _: &str,
+            changes: Map<String, Value>,
+        ) -> Result<TodoTask> {
+            let stored = self.task_mut(list_id, &task.id);
+            *stored = stored.with_changes(&changes);
+            stored.extra.remove("checklistItems");
+            Ok(stored.clone())
+        }
+
+        fn delete_task(&mut self, list_id: &str, task: &TodoTask) -> Result<()> {
+            self.tasks
+                .get_mut(list_id)
+                .unwrap()
+                .retain(|t| t.id != task.id);
+            Ok(())
+        }
+
+        fn check_item(
+            &mut self,
+            list_id: &str,
+            task_id: &str,
+            item_id: &str,
+            checked: bool,
+        ) -> Result<ChecklistItem> {
+            let task = self.task_mut(list_id, task_id);
+            let mut items = checklist(task);
+            let item = items.iter_mut().find(|item| item.id == item_id).unwrap();
+            item.is_checked = checked;
+            let item = item.clone();
+            task.extra
+                .insert("checklistItems".to_string(), json!(items));
+            Ok(item)
+        }
+    }
+
+    fn press(app: &mut App<FakeService>, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    fn screen(app: &App<FakeService>) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(100, 16)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn shows_lists_tasks_and_details() {
+        let app = App::new(FakeService::new(), Tz::UTC);
+        let screen = screen(&app);
+        assert!(screen.contains("Groceries"));
+        assert!(screen.contains("Work"));
+        assert!(screen.contains("[ ] ! Milk (2024-03-10)"));
+        assert!(screen.contains("[ ] Bread"));
+        assert!(screen.contains("Importance: high"));
+        assert!(screen.contains("[ ] Oat"));
+        assert!(screen.contains("Recipe https://example.com/r"));
+    }
+
+    #[test]
+    fn adds_edits_completes_and_deletes_tasks() {
+        let mut app = App::new(FakeService::new(), Tz::UTC);
+        press(&mut app, "\taEggs\n");
+        assert_eq!(app.status, "added Eggs");
+        assert!(screen(&app).contains("[ ] Eggs"));
+
+        press(&mut app, "e");
+        app.handle_key(KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+        press(&mut app, "!\n");
+        assert_eq!(app.service.tasks["l1"][2].title, "Egg!");
+
+        // The task stays selected once completed, until the tasks are
+        // loaded again.
+        press(&mut app, "x");
+        assert_eq!(app.service.tasks["l1"][2].status, "completed");
+        assert!(screen(&app).contains("[x] Egg!"));
+        press(&mut app, "x");
+        assert_eq!(app.status, "reopened Egg!");
+        press(&mut app, "xc");
+        assert!(screen(&app).contains("[x] Egg!"));
+
+        press(&mut app, "dy");
+        assert_eq!(app.status, "deleted Egg!");
+        assert_eq!(app.service.tasks["l1"].len(), 2);
+    }
+
+    #[test]
+    fn toggles_checklist_items() {
+        let mut app = App::new(FakeService::new(), Tz::UTC);
+        press(&mut app, "\t\t ");
+        assert_eq!(app.status, "checked Oat");
+        assert!(checklist(&app.service.tasks["l1"][0])[0].is_checked);
+        assert!(screen(&app).contains("[x] Oat"));
+
+        // The checklist stays after other changes to the task.
+        press(&mut app, "\t\t\t!");
+        assert!(screen(&app).contains("[x] Oat"));
+    }
+
+    #[test]
+    fn moves_tasks_across_the_board() {
+        let mut app = App::new(FakeService::new(), Tz::UTC);
+        press(&mut app, "\tb");
+        let board = screen(&app);
+        assert!(board.contains("To Do"));
+        assert!(board.contains("In Progress"));
+
+        press(&mut app, ">");
+        assert_eq!(app.service.tasks["l1"][0].status, "inProgress");
+        press(&mut app, ">");
+        assert_eq!(app.service.tasks["l1"][0].status, "completed");
+        assert_eq!(app.column, 2);
+        assert!(screen(&app).contains("[x] ! Milk"));
+    }
+}
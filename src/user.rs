@@ -1,194 +1,230 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
+use anyhow::{bail, Result};
 use cli_table::{format::Justify, print_stdout, Cell, Style, Table};
-use graph_rs_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::io::Result;
-
-use crate::auth::*;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct User {
-    pub display_name: String,
-    pub given_name: String,
-    pub surname: String,
-    pub id: String,
-    pub job_title: String,
-    pub mail: String,
-    pub mobile_phone: String,
-    pub office_location: String,
-    pub user_principal_name: String,
+
+use crate::batch::BatchRequest;
+use crate::cache::Store;
+use crate::graph::GraphClient;
+use crate::helper::get_profile;
+
+/// The signed in user, from `GET /me`. Graph leaves out or nulls whatever
+/// is not set on the account, so every field is optional.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct User {
+    pub display_name: Option<String>,
+    pub given_name: Option<String>,
+    pub surname: Option<String>,
+    pub id: Option<String>,
+    pub job_title: Option<String>,
+    pub mail: Option<String>,
+    pub mobile_phone: Option<String>,
+    pub office_location: Option<String>,
+    pub user_principal_name: Option<String>,
+    /// The tdi profile the account is signed in with.
+    pub profile: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mailbox_settings: Option<MailboxSettings>,
 }
 
-impl User {
-    pub fn new(
-        display_name: String,
-        given_name: String,
-        surname: String,
-        id: String,
-        job_title: String,
-        mail: String,
-        mobile_phone: String,
-        office_location: String,
-        user_principal_name: String,
-    ) -> User {
-        User {
-            display_name,
-            given_name,
-            surname,
-            id,
-            job_title,
-            mail,
-            mobile_phone,
-            office_location,
-            user_principal_name,
+/// The parts of `GET /me/mailboxSettings` used to show dates the way the
+/// user set up Outlook.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MailboxSettings {
+    /// A Windows or IANA time zone name, e.g. "Pacific Standard Time".
+    pub time_zone: Option<String>,
+    /// e.g. "MM/dd/yyyy"
+    pub date_format: Option<String>,
+    /// e.g. "h:mm tt"
+    pub time_format: Option<String>,
+    pub working_hours: Option<WorkingHours>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkingHours {
+    pub days_of_week: Vec<String>,
+    /// e.g. "08:00:00.0000000"
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub time_zone: Option<NamedTimeZone>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamedTimeZone {
+    pub name: Option<String>,
+}
+
+impl WorkingHours {
+    fn describe(&self) -> String {
+        let time = |time: &Option<String>| {
+            // Drop the seconds and fractions Graph adds.
+            time.as_deref()
+                .map(|time| time.get(..5).unwrap_or(time).to_string())
+                .unwrap_or_default()
+        };
+        let mut description = format!(
+            "{} {}-{}",
+            self.days_of_week.join(", "),
+            time(&self.start_time),
+            time(&self.end_time)
+        );
+        if let Some(name) = self.time_zone.as_ref().and_then(|tz| tz.name.as_ref()) {
+            description += &format!(" ({})", name);
         }
+        description
     }
+}
+
+impl User {
+    /// Label and value of every field that is set, in display order.
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        let fields = [
+            ("Display Name", &self.display_name),
+            ("Given Name", &self.given_name),
+            ("Surname", &self.surname),
+            ("ID", &self.id),
+            ("Job Title", &self.job_title),
+            ("Mail", &self.mail),
+            ("Mobile Phone", &self.mobile_phone),
+            ("Office Location", &self.office_location),
+            ("User Principal Name", &self.user_principal_name),
+        ];
+        let mut rows: Vec<(&str, String)> = fields
+            .into_iter()
+            .filter_map(|(label, value)| value.clone().map(|value| (label, value)))
+            .collect();
+        rows.push(("Profile", self.profile.clone()));
 
-    pub fn empty() -> User {
-        User {
-            display_name: "".to_string(),
-            given_name: "".to_string(),
-            surname: "".to_string(),
-            id: "".to_string(),
-            job_title: "".to_string(),
-            mail: "".to_string(),
-            mobile_phone: "".to_string(),
-            office_location: "".to_string(),
-            user_principal_name: "".to_string(),
+        if let Some(settings) = &self.mailbox_settings {
+            let fields = [
+                ("Time Zone", &settings.time_zone),
+                ("Date Format", &settings.date_format),
+                ("Time Format", &settings.time_format),
+            ];
+            rows.extend(
+                fields
+                    .into_iter()
+                    .filter_map(|(label, value)| value.clone().map(|value| (label, value))),
+            );
+            if let Some(hours) = &settings.working_hours {
+                rows.push(("Working Hours", hours.describe()));
+            }
         }
+        rows
     }
 
-    pub fn as_table(self) {
+    pub fn as_table(&self) {
         // output user as table
-        let table = vec![
-            vec![
-                "Display Name".cell().bold(true),
-                self.display_name.cell().justify(Justify::Right),
-            ],
-            vec![
-                "Given Name".cell().bold(true),
-                self.given_name.cell().justify(Justify::Right),
-            ],
-            vec![
-                "Surname".cell().bold(true),
-                self.surname.cell().justify(Justify::Right),
-            ],
-            vec![
-                "ID".cell().bold(true),
-                self.id.cell().justify(Justify::Right),
-            ],
-            vec![
-                "Mail".cell().bold(true),
-                self.mail.cell().justify(Justify::Right),
-            ],
-            vec![
-                "Mobile Phone".cell().bold(true),
-                self.mobile_phone.cell().justify(Justify::Right),
-            ],
-            vec![
-                "Office Location".cell().bold(true),
-                self.office_location.cell().justify(Justify::Right),
-            ],
-            vec![
-                "User Principal Name".cell().bold(true),
-                self.user_principal_name.cell().justify(Justify::Right),
-            ],
-        ]
-        .table();
-        //.title(vec!["Items".cell().bold(true), "Details".cell().bold(true)])
-        //.bold(true);
-
-        match print_stdout(table) {
-            Err(e) => println!("{:?}", e),
-            _ => (),
+        let table = self
+            .rows()
+            .into_iter()
+            .map(|(label, value)| {
+                vec![
+                    label.cell().bold(true),
+                    value.cell().justify(Justify::Right),
+                ]
+            })
+            .collect::<Vec<_>>()
+            .table();
+
+        if let Err(e) = print_stdout(table) {
+            println!("{:?}", e);
         }
     }
 
-    pub fn as_json(self) {
+    pub fn as_json(&self) {
         // output user as json
         let json = serde_json::to_string(&self);
         println!("{}", json.unwrap());
     }
 
-    pub fn as_lines(self) {
-        println!("Display Name: {:?}", self.display_name);
-        println!("Given Name: {:?}", self.given_name);
-        println!("Surname: {:?}", self.surname);
-        println!("ID: {:?}", self.id);
-        println!("Mail: {:?}", self.mail);
-        println!("Mobile Phone: {:?}", self.mobile_phone);
-        println!("Office Location: {:?}", self.office_location);
-        println!("User Principal Name: {:?}", self.user_principal_name);
+    pub fn as_lines(&self) {
+        for (label, value) in self.rows() {
+            println!("{}: {}", label, value);
+        }
+    }
+}
+
+/// Fetches the user and their mailbox settings in one round trip. The
+/// mailbox settings are left out when they cannot be read, e.g. for accounts
+/// without a mailbox or tokens from before the permission was requested.
+fn fetch_me(client: &GraphClient) -> Result<User> {
+    let responses = client.batch(vec![
+        BatchRequest::get("me", "/me"),
+        BatchRequest::get("mailboxSettings", "/me/mailboxSettings"),
+    ])?;
+    let (me, settings) = (&responses[0], &responses[1]);
+    if !me.is_success() {
+        bail!("{}", me.error());
     }
+
+    let mut user: User = me.json()?;
+    user.mailbox_settings = if settings.is_success() {
+        settings.json().ok()
+    } else {
+        None
+    };
+    Ok(user)
 }
 
 pub fn show_me(output_format: &str) -> Result<()> {
-    let token = read_access_token();
-    let client = Graph::new(&token);
-
-    match client.v1().me().get_user().send() {
-        Ok(res) => {
-            let user = match res.body().as_object() {
-                None => User::empty(),
-                Some(map) => User::new(
-                    map.get(&"displayName".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"givenName".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"surname".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"id".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"jobTitle".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"mail".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"mobilePhone".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"officeLocation".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                    map.get(&"userPrincipalName".to_string())
-                        .unwrap()
-                        .to_string()
-                        .replace("\"", ""),
-                ),
-            };
-
-            match output_format {
-                "json" => {
-                    user.as_json();
-                }
-                "lines" => {
-                    user.as_lines();
-                }
-                "table" => {
-                    user.as_table();
-                }
-                _ => {
-                    user.as_lines();
-                }
-            }
-        }
-        Err(err) => println!("Error: {}", err),
+    let client = GraphClient::new()?;
+    let mut user = fetch_me(&client)?;
+    user.profile = get_profile().to_string();
+
+    // Kept for commands that show dates in the user's time zone.
+    let mut store = Store::load()?;
+    store.mailbox_settings = user.mailbox_settings.clone();
+    store.save()?;
+    if user.mailbox_settings.is_none() {
+        eprintln!(
+            "tdi: unable to read the mailbox settings, run `tdi login` again if this account has a mailbox."
+        );
+    }
+
+    match output_format {
+        "json" => user.as_json(),
+        "table" => user.as_table(),
+        _ => user.as_lines(),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn missing_and_null_fields_are_left_out() {
+        let user: User = serde_json::from_value(json!({
+            "displayName": "Ada Lovelace",
+            "mobilePhone": null,
+            "mail": "ada@contoso.com",
+        }))
+        .unwrap();
+        let labels: Vec<&str> = user.rows().into_iter().map(|(label, _)| label).collect();
+        assert_eq!(labels, vec!["Display Name", "Mail", "Profile"]);
+    }
+
+    #[test]
+    fn describes_working_hours() {
+        let hours: WorkingHours = serde_json::from_value(json!({
+            "daysOfWeek": ["monday", "friday"],
+            "startTime": "08:00:00.0000000",
+            "endTime": "16:30:00.0000000",
+            "timeZone": { "name": "Pacific Standard Time" },
+        }))
+        .unwrap();
+        assert_eq!(
+            hours.describe(),
+            "monday, friday 08:00-16:30 (Pacific Standard Time)"
+        );
+    }
+}
@@ -1,9 +1,18 @@
 // Copyright (c) Microsoft Corporation - 2022.
 // Licensed under the MIT License.
 
-use assert_cmd::prelude::*; // Add methods on commands
-                            //use predicates::prelude::*; // Used for writing assertions
-use std::process::Command; // Run programs
+use assert_cmd::Command; // Run programs
+use std::path::Path;
+
+/// Runs tdi with its home, cache and config in `home`.
+fn tdi(home: &Path, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("tdi").unwrap();
+    cmd.env("HOME", home)
+        .env("XDG_CACHE_HOME", home.join(".cache"))
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .args(args);
+    cmd
+}
 
 #[test]
 fn test_help() -> Result<(), Box<dyn std::error::Error>> {
@@ -16,3 +25,113 @@ fn test_help() -> Result<(), Box<dyn std::error::Error>> {
         ));
     Ok(())
 }
+
+#[test]
+fn test_complete_requires_ids() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("tdi")?;
+    cmd.arg("complete");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_offline_without_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-offline");
+    tdi(&home, &["--offline", "lists"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("no offline data yet"));
+    Ok(())
+}
+
+#[test]
+fn test_profiles_list() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-profiles");
+    tdi(&home, &["profiles", "list"])
+        .env("TDI_PROFILE", "work")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("  default (logged out)"))
+        .stdout(predicates::str::contains("* work (logged out)"));
+    Ok(())
+}
+
+#[test]
+fn test_auth_status_logged_out() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-auth-status");
+    tdi(&home, &["auth", "status"])
+        .assert()
+        .code(exitcode::NOUSER)
+        .stdout(predicates::str::contains(
+            "not logged in to profile default",
+        ));
+    Ok(())
+}
+
+#[test]
+fn test_config_cloud() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-config");
+    let _ = std::fs::remove_dir_all(&home);
+    tdi(&home, &["config", "set", "cloud", "china"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("run `tdi login` again"));
+    tdi(&home, &["config", "get", "cloud"])
+        .assert()
+        .success()
+        .stdout("china\n");
+    tdi(&home, &["config", "set", "cloud", "mars"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+#[test]
+fn test_logout_keeps_unsynced_changes() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-logout");
+    let _ = std::fs::remove_dir_all(&home);
+    let cache = home.join(".cache/tdi/profiles/default");
+    std::fs::create_dir_all(&cache)?;
+    std::fs::write(
+        cache.join("journal.json"),
+        r#"{"operations":[{"kind":"create","listId":"l","taskId":"local-1","title":"t",
+            "changes":{},"base":null,"recordedAt":"2022-10-01T00:00:00Z"}]}"#,
+    )?;
+    tdi(&home, &["logout"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("1 offline change(s)"));
+    tdi(&home, &["logout", "--force"]).assert().success();
+    assert!(!cache.exists());
+    // Logging out again is fine.
+    tdi(&home, &["logout"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("is not logged in"));
+    Ok(())
+}
+
+#[test]
+fn test_import_rejects_newer_exports() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-import");
+    tdi(&home, &["import", "-", "--dry-run"])
+        .write_stdin(r#"{"version":99,"exportedAt":"2022-10-01T00:00:00Z","lists":[]}"#)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("update tdi"));
+    Ok(())
+}
+
+#[test]
+fn test_import_checks_every_csv_row_first() -> Result<(), Box<dyn std::error::Error>> {
+    let home = std::env::temp_dir().join("tdi-test-import-csv");
+    tdi(&home, &["import", "-", "--format", "csv"])
+        .write_stdin("title,due\nBook the room,soon\n,2024-03-10\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "2 invalid rows, nothing was imported",
+        ))
+        .stderr(predicates::str::contains("row 3: no title"));
+    Ok(())
+}